@@ -1,16 +1,19 @@
 use crate::{
     engine::{
         codegen::{self, CodeGenResult},
+        controls::{Control, FloatInRangeControl},
         data_transfer::IOData,
         data_transfer::{
             DynDataCollector, FeedbackData, FeedbackDisplayer, GlobalData, GlobalParameters,
+            NUM_HOST_PARAMETERS,
         },
         julia_thread,
-        parts::{Module, ModuleGraph},
+        parts::{Module, ModuleGraph, TuningSettings, VoiceSettings},
     },
-    registry::{save_data::Patch, Registry},
+    registry::{save_data::Patch, tuning::KeyFrequencies, Registry},
 };
-use crossbeam_channel::{Receiver, Sender, TrySendError};
+use crossbeam_channel::{Receiver, Sender};
+use crossbeam_queue::ArrayQueue;
 use crossbeam_utils::atomic::AtomicCell;
 use julia_helper::GeneratedCode;
 use observatory::{observable, ObservablePtr};
@@ -20,44 +23,182 @@ use std::{
     time::{Duration, Instant},
 };
 
-const DEFAULT_CHANNELS: usize = 2;
+pub(super) const DEFAULT_CHANNELS: usize = 2;
+/// Enough for 7.1 surround sound.
+pub const MAX_CHANNELS: usize = 8;
 const DEFAULT_BUFFER_LENGTH: usize = 512;
 const DEFAULT_SAMPLE_RATE: usize = 44100;
 const FEEDBACK_UPDATE_INTERVAL: Duration = Duration::from_millis(50);
+/// How many buffers ahead of the host audio is rendered by default.
+const DEFAULT_RENDER_LATENCY: usize = 1;
+pub const MAX_RENDER_LATENCY: usize = 16;
+/// How many note events can wait to be rendered at once. Events past this are dropped, which only
+/// happens if the Julia thread stops taking render requests for a long time.
+const MAX_QUEUED_NOTE_EVENTS: usize = 1024;
 
 type PreferredPerfCounter = shared_util::perf_counter::SimplePerfCounter;
 
+/// Functions the host provides to find out when the GUI changes a host parameter, so that the
+/// change can be recorded as automation. Each one is given `data` and the index of the parameter.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct HostParameterCallbacks {
+    pub data: *mut i8,
+    pub begin_gesture: extern "C" fn(*mut i8, i32),
+    pub set_value: extern "C" fn(*mut i8, i32, f32),
+    pub end_gesture: extern "C" fn(*mut i8, i32),
+}
+
+/// The name and range of the control assigned to each host parameter. Hosts can ask for these
+/// from any thread, so they are copied here whenever the assignments change instead of being read
+/// from the module graph.
+pub struct HostParameterInfo {
+    parameters: Mutex<Vec<(String, (f32, f32))>>,
+}
+
+impl HostParameterInfo {
+    fn new() -> Self {
+        Self {
+            parameters: Mutex::new((0..NUM_HOST_PARAMETERS).map(Self::unassigned).collect()),
+        }
+    }
+
+    fn unassigned(index: usize) -> (String, (f32, f32)) {
+        (format!("Parameter {}", index + 1), (0.0, 1.0))
+    }
+
+    /// Returns the name of the control assigned to the host parameter, or a placeholder if there
+    /// is none.
+    pub fn get_name(&self, index: usize) -> String {
+        self.parameters.lock().unwrap()[index].0.clone()
+    }
+
+    /// Returns the range of the control assigned to the host parameter, which the host's 0 to 1
+    /// value is mapped to.
+    pub fn get_range(&self, index: usize) -> (f32, f32) {
+        self.parameters.lock().unwrap()[index].1
+    }
+}
+
 struct UiThreadData {
     registry: Rcrc<Registry>,
     module_graph: Rcrc<ModuleGraph>,
     dyn_data_collector: DynDataCollector,
+    /// Controls which have been changed since the current patch was loaded. These stay out of
+    /// constant folding so that changing them again does not require new code.
+    unfolded_controls: Vec<Rcrc<dyn Control>>,
     feedback_displayer: FeedbackDisplayer,
     current_patch_save_data: ObservablePtr<Rcrc<Patch>>,
     posted_errors: Vec<String>,
     julia_errors: Receiver<String>,
+    reported_xruns: usize,
+    host_parameter_callbacks: Option<HostParameterCallbacks>,
 }
 
 pub(super) struct Communication {
     pub julia_thread_status: AtomicCell<julia_thread::Status>,
 
     pub new_global_params: AtomicCell<Option<()>>,
-    pub new_note_graph_code: AtomicCell<Option<(GeneratedCode, Vec<IOData>)>>,
+    /// The second field is whether the code has an effects section.
+    pub new_note_graph_code: AtomicCell<Option<(GeneratedCode, bool, Vec<IOData>)>>,
     pub new_dyn_data: AtomicCell<Option<Vec<IOData>>>,
+    pub new_voice_settings: AtomicCell<Option<VoiceSettings>>,
+    pub new_key_frequencies: AtomicCell<Option<KeyFrequencies>>,
     pub new_feedback: AtomicCell<Option<FeedbackData>>,
+    /// Feedback data the GUI is done with, sent back so that its vectors can be reused.
+    pub spare_feedback: AtomicCell<Option<FeedbackData>>,
     pub do_dummy_note: AtomicCell<bool>,
     pub do_dummy_note_once: AtomicCell<bool>,
     pub module_view_index: AtomicCell<usize>,
 
     pub global_params: AtomicCell<GlobalParameters>,
-    pub note_events: Mutex<Vec<julia_thread::NoteEvent>>,
+    /// Events which will be sent with the next render request. This is a fixed size queue so that
+    /// adding events from the audio thread never blocks or allocates.
+    pub note_events: ArrayQueue<julia_thread::NoteEvent>,
     pub julia_render_pipe: Sender<julia_thread::RenderRequest>,
     pub julia_poll_pipe: Sender<()>,
+    /// How many times the audio thread had to output silence because audio was not rendered in
+    /// time.
+    pub xruns: AtomicCell<usize>,
+    /// The most recent MIDI controller the host sent a value for, used for MIDI learn.
+    pub last_midi_control: AtomicCell<Option<u8>>,
+    /// Set by the host on the audio thread and by the GUI when a control assigned to a host
+    /// parameter is changed, ranging from 0 to 1.
+    pub host_parameters: [AtomicCell<f32>; NUM_HOST_PARAMETERS],
+    /// Set when the host pushes a new value for a host parameter, so that only those values are
+    /// copied into the controls the GUI shows.
+    pub host_parameters_changed: [AtomicCell<bool>; NUM_HOST_PARAMETERS],
+    pub host_parameter_info: Arc<HostParameterInfo>,
+}
+
+impl Communication {
+    fn new(
+        global_params: GlobalParameters,
+        julia_render_pipe: Sender<julia_thread::RenderRequest>,
+        julia_poll_pipe: Sender<()>,
+    ) -> Self {
+        Self {
+            julia_thread_status: AtomicCell::new(julia_thread::Status::Busy),
+
+            new_global_params: Default::default(),
+            new_note_graph_code: Default::default(),
+            new_dyn_data: Default::default(),
+            new_voice_settings: Default::default(),
+            new_key_frequencies: Default::default(),
+            new_feedback: Default::default(),
+            spare_feedback: Default::default(),
+            do_dummy_note: AtomicCell::new(false),
+            do_dummy_note_once: AtomicCell::new(false),
+            module_view_index: AtomicCell::new(0),
+
+            global_params: AtomicCell::new(global_params),
+            note_events: ArrayQueue::new(MAX_QUEUED_NOTE_EVENTS),
+            julia_render_pipe,
+            julia_poll_pipe,
+            xruns: AtomicCell::new(0),
+            last_midi_control: AtomicCell::new(None),
+            host_parameters: Default::default(),
+            host_parameters_changed: Default::default(),
+            host_parameter_info: Arc::new(HostParameterInfo::new()),
+        }
+    }
+
+    /// Queues an event to be applied before the next buffer is rendered. The event is dropped if
+    /// the queue is full.
+    pub fn queue_note_event(&self, event: julia_thread::NoteEvent) {
+        let _ = self.note_events.push(event);
+    }
 }
 
 struct AudioThreadData {
     global_data: GlobalData,
     last_feedback_data_update: Instant,
     audio_response_output: Receiver<julia_thread::AudioResponse>,
+    /// How many buffers ahead of the host the Julia thread renders.
+    render_latency: usize,
+    /// How many render requests have been sent whose audio has not been played yet.
+    buffers_in_flight: usize,
+    /// Buffers which came back from the Julia thread and can be sent with the next request.
+    spare_buffers: Vec<julia_thread::RenderBuffers>,
+}
+
+impl AudioThreadData {
+    fn new(audio_response_output: Receiver<julia_thread::AudioResponse>) -> Self {
+        Self {
+            global_data: GlobalData::new(),
+            last_feedback_data_update: Instant::now(),
+            audio_response_output,
+            render_latency: DEFAULT_RENDER_LATENCY,
+            buffers_in_flight: 0,
+            // There is never more than one more request in flight than the render latency.
+            spare_buffers: (0..MAX_RENDER_LATENCY + 2)
+                .map(|_| julia_thread::RenderBuffers {
+                    note_events: Vec::with_capacity(MAX_QUEUED_NOTE_EVENTS),
+                    ..Default::default()
+                })
+                .collect(),
+        }
+    }
 }
 
 pub struct UiThreadEngine {
@@ -97,56 +238,49 @@ pub fn new_engine(
         })?;
     let CodeGenResult {
         code,
+        has_effects,
         dyn_data_collector,
         feedback_displayer,
         ..
-    } = codegen::generate_code(&module_graph, &global_params).map_err(|_| {
+    } = codegen::generate_code(&module_graph, &global_params, &[]).map_err(|_| {
         format!(concat!(
-            "Default patch contains feedback loops!\n",
+            "Default patch contains connections to modules which do not exist!\n",
             "This is a critical error, please submit a bug report containing this ",
             "error.",
         ),)
     })?;
     let dyn_data = dyn_data_collector.collect();
-
-    let (renderi, rendero) = crossbeam_channel::bounded(0);
+    let voice_settings = module_graph.voice_settings.clone();
+    let key_frequencies = module_graph
+        .tuning
+        .key_frequencies(&*registry)
+        .unwrap_or_default();
+
+    // Requests and responses are both capped by the render latency, so these never fill up.
+    let (renderi, rendero) = crossbeam_channel::bounded(MAX_RENDER_LATENCY + 1);
     let (polli, pollo) = crossbeam_channel::bounded(0xFF);
-    let (audio_resi, audio_reso) = crossbeam_channel::bounded(0);
+    let (audio_resi, audio_reso) = crossbeam_channel::bounded(MAX_RENDER_LATENCY + 1);
     let (jerrori, jerroro) = crossbeam_channel::unbounded();
 
     let utd = UiThreadData {
         registry: Rc::clone(&registry_ptr),
         module_graph: rcrc(module_graph),
         dyn_data_collector,
+        unfolded_controls: Vec::new(),
         feedback_displayer,
         current_patch_save_data: observable(default_patch),
         posted_errors: Vec::new(),
         julia_errors: jerroro,
+        reported_xruns: 0,
+        host_parameter_callbacks: None,
     };
 
-    let atd = AudioThreadData {
-        global_data: GlobalData::new(),
-        last_feedback_data_update: Instant::now(),
-        audio_response_output: audio_reso,
-    };
+    let atd = AudioThreadData::new(audio_reso);
 
     let global_params_2 = global_params.clone();
-    let comms = Communication {
-        julia_thread_status: AtomicCell::new(julia_thread::Status::Busy),
-
-        new_global_params: Default::default(),
-        new_note_graph_code: Default::default(),
-        new_dyn_data: Default::default(),
-        new_feedback: Default::default(),
-        do_dummy_note: AtomicCell::new(false),
-        do_dummy_note_once: AtomicCell::new(false),
-        module_view_index: AtomicCell::new(0),
-
-        global_params: AtomicCell::new(global_params),
-        note_events: Default::default(),
-        julia_render_pipe: renderi,
-        julia_poll_pipe: polli,
-    };
+    let comms = Communication::new(global_params, renderi, polli);
+    comms.new_voice_settings.store(Some(voice_settings));
+    comms.new_key_frequencies.store(Some(key_frequencies));
     let comms = Arc::new(comms);
 
     let registry_source = codegen::generate_registry_code(&*registry)?;
@@ -157,6 +291,7 @@ pub fn new_engine(
             global_params_2,
             registry_source,
             code,
+            has_effects,
             dyn_data,
             rendero,
             pollo,
@@ -168,12 +303,16 @@ pub fn new_engine(
         .name("julia_executor".to_owned())
         .spawn(julia_executor)
         .unwrap();
+    // Makes the Julia thread pick up the voice settings and tuning of the default patch.
+    comms.julia_poll_pipe.send(()).unwrap();
 
+    let ui_engine = UiThreadEngine {
+        data: utd,
+        comms: Arc::clone(&comms),
+    };
+    ui_engine.publish_host_parameters();
     Ok((
-        rcrc(UiThreadEngine {
-            data: utd,
-            comms: Arc::clone(&comms),
-        }),
+        rcrc(ui_engine),
         rcrc(AudioThreadEngine {
             data: atd,
             comms: Arc::clone(&comms),
@@ -207,6 +346,17 @@ impl UiThreadEngine {
         while let Ok(error) = self.data.julia_errors.try_recv() {
             errors.push(error);
         }
+        let xruns = self.comms.xruns.load();
+        if xruns > self.data.reported_xruns {
+            errors.push(format!(
+                concat!(
+                    "WARNING: Audio dropped out {} time(s) because it could not be rendered in ",
+                    "time."
+                ),
+                xruns - self.data.reported_xruns
+            ));
+            self.data.reported_xruns = xruns;
+        }
         errors
     }
 
@@ -221,6 +371,7 @@ impl UiThreadEngine {
             .borrow_untracked()
             .borrow()
             .is_writable());
+        self.sync_host_parameters();
         let patch_ref_ref = self.data.current_patch_save_data.borrow_untracked();
         let mut patch_ref = patch_ref_ref.borrow_mut();
         let reg = self.data.registry.borrow();
@@ -233,6 +384,7 @@ impl UiThreadEngine {
     }
 
     pub fn serialize_current_patch(&self) -> String {
+        self.sync_host_parameters();
         let patch_ref_ref = self.data.current_patch_save_data.borrow_untracked();
         let patch_ref = patch_ref_ref.borrow();
         let reg = self.data.registry.borrow();
@@ -299,6 +451,28 @@ impl UiThreadEngine {
         Ok(())
     }
 
+    /// Saves a group to the User library so that copies of it can be added to any patch from the
+    /// module browser.
+    pub fn export_module_group(&mut self, group: &Rcrc<Module>) {
+        let mut reg = self.data.registry.borrow_mut();
+        let mut graph = ModuleGraph::new();
+        graph.add_module(Rc::clone(group));
+        let name = group.borrow().group.as_ref().unwrap().name.clone();
+        let new_group = Rc::clone(reg.create_new_user_group());
+        let mut new_group_ref = new_group.borrow_mut();
+        new_group_ref.set_name(name);
+        new_group_ref.save_note_graph(&graph, &*reg);
+        let res = new_group_ref.write();
+        drop(new_group_ref);
+        drop(reg);
+        if let Err(err) = res {
+            self.post_error(format!(
+                "ERROR: Failed to export group, caused by:\nERROR: {}",
+                err
+            ));
+        }
+    }
+
     pub fn load_patch(&mut self, patch: Rcrc<Patch>) -> Result<(), ()> {
         let reg = self.data.registry.borrow();
         self.data.current_patch_save_data.set(patch);
@@ -314,10 +488,164 @@ impl UiThreadEngine {
             return Err(());
         }
         self.data.module_graph.borrow().rebuild_widget();
+        self.data.unfolded_controls.clear();
         self.regenerate_code();
+        self.send_voice_settings();
+        self.send_key_frequencies();
+        self.publish_host_parameters();
         Ok(())
     }
 
+    fn host_parameter_controls(&self) -> Vec<(usize, String, Rcrc<FloatInRangeControl>)> {
+        self.data.module_graph.borrow().host_parameter_controls()
+    }
+
+    /// Tells the host and the audio thread the value of every assigned host parameter, e.g. after
+    /// a patch was loaded.
+    fn publish_host_parameters(&self) {
+        self.update_host_parameter_info();
+        for (_, _, control) in self.host_parameter_controls() {
+            self.host_parameter_changed(&*control.borrow());
+        }
+    }
+
+    /// Copies the name and range of every assigned host parameter to where the host can read them.
+    fn update_host_parameter_info(&self) {
+        let mut info: Vec<_> = (0..NUM_HOST_PARAMETERS)
+            .map(HostParameterInfo::unassigned)
+            .collect();
+        for (index, name, control) in self.host_parameter_controls() {
+            info[index] = (name, control.borrow().range);
+        }
+        *self.comms.host_parameter_info.parameters.lock().unwrap() = info;
+    }
+
+    /// The names and ranges of host parameters, which can be read from any thread.
+    pub fn host_parameter_info(&self) -> Arc<HostParameterInfo> {
+        Arc::clone(&self.comms.host_parameter_info)
+    }
+
+    pub fn set_host_parameter_callbacks(&mut self, callbacks: HostParameterCallbacks) {
+        self.data.host_parameter_callbacks = Some(callbacks);
+        self.publish_host_parameters();
+    }
+
+    /// Assigns the control to the first unused host parameter, or unassigns it if it already has
+    /// one. Automated controls cannot be assigned because their lanes decide their value instead.
+    pub fn toggle_host_parameter(&mut self, control: &Rcrc<FloatInRangeControl>) {
+        let assigned = control.borrow().host_parameter;
+        if assigned.is_some() {
+            control.borrow_mut().host_parameter = None;
+        } else if control.borrow().is_automated() {
+            self.post_error(format!(
+                "ERROR: Automated controls cannot be assigned to a host parameter, remove their \
+                automation first."
+            ));
+            return;
+        } else {
+            let used: Vec<_> = self
+                .host_parameter_controls()
+                .into_iter()
+                .map(|(parameter, ..)| parameter)
+                .collect();
+            let free = (0..NUM_HOST_PARAMETERS).find(|index| !used.contains(index));
+            if let Some(index) = free {
+                control.borrow_mut().host_parameter = Some(index);
+                self.host_parameter_changed(&*control.borrow());
+            } else {
+                self.post_error(format!(
+                    "ERROR: All {} host parameters are already in use.",
+                    NUM_HOST_PARAMETERS
+                ));
+                return;
+            }
+        }
+        self.regenerate_code();
+    }
+
+    /// Call this whenever the GUI changes the value of a control so that the host can record it
+    /// if the control is assigned to a host parameter.
+    pub fn host_parameter_changed(&self, control: &FloatInRangeControl) {
+        if let Some(index) = control.host_parameter {
+            let value = control.get_normalized_value();
+            self.comms.host_parameters[index].store(value);
+            if let Some(callbacks) = &self.data.host_parameter_callbacks {
+                (callbacks.set_value)(callbacks.data, index as i32, value);
+            }
+        }
+    }
+
+    /// Lets the host know the user started changing a control, so the changes can be grouped into
+    /// a single undo step.
+    pub fn begin_host_parameter_gesture(&self, control: &FloatInRangeControl) {
+        if let (Some(index), Some(callbacks)) =
+            (control.host_parameter, &self.data.host_parameter_callbacks)
+        {
+            (callbacks.begin_gesture)(callbacks.data, index as i32);
+        }
+    }
+
+    pub fn end_host_parameter_gesture(&self, control: &FloatInRangeControl) {
+        if let (Some(index), Some(callbacks)) =
+            (control.host_parameter, &self.data.host_parameter_callbacks)
+        {
+            (callbacks.end_gesture)(callbacks.data, index as i32);
+        }
+    }
+
+    /// Copies values the host pushed for host parameters into the controls they are assigned to so
+    /// that the GUI shows them and they are saved with the patch. Values which were not changed by
+    /// the host are left alone so that they do not fight with the user dragging a control.
+    pub fn sync_host_parameters(&self) {
+        for (index, _, control) in self.host_parameter_controls() {
+            if self.comms.host_parameters_changed[index].swap(false) {
+                let value = self.comms.host_parameters[index].load();
+                control.borrow_mut().set_normalized_value(value);
+            }
+        }
+    }
+
+    pub fn get_tuning_settings(&self) -> TuningSettings {
+        self.data.module_graph.borrow().tuning.clone()
+    }
+
+    pub fn set_tuning_settings(&mut self, settings: TuningSettings) {
+        self.data.module_graph.borrow_mut().tuning = settings;
+        self.send_key_frequencies();
+    }
+
+    fn send_key_frequencies(&mut self) {
+        let settings = self.get_tuning_settings();
+        let frequencies = settings.key_frequencies(&*self.data.registry.borrow());
+        let frequencies = match frequencies {
+            Ok(value) => value,
+            Err(err) => {
+                self.post_error(format!(
+                    "ERROR: Falling back to twelve tone equal temperament, caused by:\n{}",
+                    err
+                ));
+                KeyFrequencies::default()
+            }
+        };
+        self.comms.new_key_frequencies.store(Some(frequencies));
+        self.comms.julia_poll_pipe.send(()).unwrap();
+    }
+
+    pub fn get_voice_settings(&self) -> VoiceSettings {
+        self.data.module_graph.borrow().voice_settings.clone()
+    }
+
+    pub fn set_voice_settings(&mut self, settings: VoiceSettings) {
+        self.data.module_graph.borrow_mut().voice_settings = settings;
+        self.send_voice_settings();
+    }
+
+    fn send_voice_settings(&mut self) {
+        let settings = self.get_voice_settings();
+        self.comms.new_voice_settings.store(Some(settings));
+        self.comms.julia_poll_pipe.send(()).unwrap();
+    }
+
     pub fn borrow_module_graph_ref(&self) -> &Rcrc<ModuleGraph> {
         &self.data.module_graph
     }
@@ -325,26 +653,36 @@ impl UiThreadEngine {
     pub fn regenerate_code(&mut self) {
         let module_graph_ref = self.data.module_graph.borrow();
         let params = self.comms.global_params.load();
-        let new_gen = codegen::generate_code(&*module_graph_ref, &params);
+        let new_gen =
+            codegen::generate_code(&*module_graph_ref, &params, &self.data.unfolded_controls);
         let new_gen = if let Ok(value) = new_gen {
             value
         } else {
             drop(module_graph_ref);
-            self.post_error("Module graph contains feedback loops.".to_owned());
+            self.post_error("Module graph contains connections to missing modules.".to_owned());
             return;
         };
         drop(module_graph_ref);
+        // Assignments may have changed along with the graph.
+        self.update_host_parameter_info();
         self.comms.new_dyn_data.store(None);
         let dyn_data = new_gen.dyn_data_collector.collect();
         self.comms
             .new_note_graph_code
-            .store(Some((new_gen.code, dyn_data)));
+            .store(Some((new_gen.code, new_gen.has_effects, dyn_data)));
         self.comms.julia_poll_pipe.send(()).unwrap();
         self.data.dyn_data_collector = new_gen.dyn_data_collector;
         self.data.feedback_displayer = new_gen.feedback_displayer;
     }
 
     pub fn reload_dyn_data(&mut self) {
+        let changed = self.data.dyn_data_collector.changed_folded_controls();
+        if changed.len() > 0 {
+            self.data.unfolded_controls.extend(changed);
+            self.regenerate_code();
+            self.set_dummy_note_active(true);
+            return;
+        }
         let data = self.data.dyn_data_collector.collect();
         self.comms.new_dyn_data.store(Some(data));
         self.comms.julia_poll_pipe.send(()).unwrap();
@@ -359,11 +697,18 @@ impl UiThreadEngine {
         if let Some(data) = self.comms.new_feedback.take() {
             if let Some(widget) = &self.data.module_graph.borrow().current_widget {
                 let widget = Rc::clone(widget);
-                self.data.feedback_displayer.display(data, widget);
+                self.data.feedback_displayer.display(&data, widget);
             }
+            self.comms.spare_feedback.store(Some(data));
         }
     }
 
+    /// Returns the MIDI controller that was most recently changed, if any controller has been
+    /// changed since the last time this was called.
+    pub fn take_last_midi_control(&self) -> Option<u8> {
+        self.comms.last_midi_control.take()
+    }
+
     pub fn set_dummy_note_active(&self, should_be_active: bool) {
         self.comms.do_dummy_note.store(should_be_active);
     }
@@ -378,7 +723,7 @@ impl UiThreadEngine {
             .data
             .module_graph
             .borrow()
-            .borrow_modules()
+            .flattened_modules()
             .iter()
             .position(|other| Rc::ptr_eq(module, other));
         if let Some(index) = index {
@@ -387,26 +732,39 @@ impl UiThreadEngine {
     }
 
     pub fn virtual_keyboard_note(&self, index: usize, down: bool) {
-        let mut events = self.comms.note_events.lock().unwrap();
         let event = if down {
             julia_thread::NoteEvent::StartNote {
                 index,
                 velocity: 1.0,
+                offset: 0,
             }
         } else {
-            julia_thread::NoteEvent::ReleaseNote { index }
+            julia_thread::NoteEvent::ReleaseNote {
+                index,
+                velocity: julia_thread::DEFAULT_RELEASE_VELOCITY,
+                offset: 0,
+            }
         };
-        events.push(event);
+        self.comms.queue_note_event(event);
     }
 }
 
 impl AudioThreadEngine {
     // AUDIO THREAD METHODS ========================================================================
-    pub fn set_global_params(&mut self, buffer_length: usize, sample_rate: usize) {
+    /// `channels` is clamped to the range 1 to `MAX_CHANNELS`. Rendered audio is interleaved, with
+    /// channels in the order mono, stereo (L R), LCR, quad (L R Ls Rs), 5.0 (L R C Ls Rs), 5.1
+    /// (L R C LFE Ls Rs), 7.0 (L R C Ls Rs Lrs Rrs) or 7.1 (L R C LFE Ls Rs Lrs Rrs) depending on
+    /// how many there are.
+    pub fn set_global_params(&mut self, channels: usize, buffer_length: usize, sample_rate: usize) {
         let mut params = self.comms.global_params.load();
+        let channels = channels.max(1).min(MAX_CHANNELS);
 
         // Avoid recompiling if there was no change.
-        if buffer_length != params.buffer_length || sample_rate != params.sample_rate {
+        if channels != params.channels
+            || buffer_length != params.buffer_length
+            || sample_rate != params.sample_rate
+        {
+            params.channels = channels;
             params.buffer_length = buffer_length;
             params.sample_rate = sample_rate;
             self.comms.new_global_params.store(Some(()));
@@ -415,14 +773,45 @@ impl AudioThreadEngine {
         }
     }
 
-    pub fn start_note(&mut self, index: usize, velocity: f32) {
-        let mut queue = self.comms.note_events.lock().unwrap();
-        queue.push(julia_thread::NoteEvent::StartNote { index, velocity });
+    pub fn get_global_params(&self) -> GlobalParameters {
+        self.comms.global_params.load()
+    }
+
+    /// `offset` is the sample in the next rendered buffer that the note should start on.
+    pub fn start_note(&mut self, index: usize, velocity: f32, offset: usize) {
+        self.comms
+            .queue_note_event(julia_thread::NoteEvent::StartNote {
+                index,
+                velocity,
+                offset,
+            });
     }
 
-    pub fn release_note(&mut self, index: usize) {
-        let mut queue = self.comms.note_events.lock().unwrap();
-        queue.push(julia_thread::NoteEvent::ReleaseNote { index });
+    /// `offset` is the sample in the next rendered buffer that the note should be released on.
+    /// `velocity` is how fast the key was released, from 0 to 1.
+    pub fn release_note(&mut self, index: usize, velocity: f32, offset: usize) {
+        self.comms
+            .queue_note_event(julia_thread::NoteEvent::ReleaseNote {
+                index,
+                velocity,
+                offset,
+            });
+    }
+
+    /// Changes the expression of the held note at `index`, see `NoteExpression` for the range of
+    /// each value.
+    pub fn set_note_expression(
+        &mut self,
+        index: usize,
+        expression: julia_thread::NoteExpression,
+        value: f32,
+    ) {
+        self.comms
+            .queue_note_event(julia_thread::NoteEvent::Expression {
+                index,
+                expression,
+                value,
+            });
     }
 
     pub fn set_pitch_wheel(&mut self, new_pitch_wheel: f32) {
@@ -441,7 +830,32 @@ impl AudioThreadEngine {
             value
         );
         assert!(index < 128, "{} is not a valid control index.", index);
+        let old_value = self.data.global_data.controller_values[index];
         self.data.global_data.controller_values[index] = value;
+        // Pedals are also tracked by the note tracker. MIDI values of 64 and above mean the pedal
+        // is down. Like expression changes, they take effect at the start of the next buffer.
+        if let Some(pedal) = julia_thread::Pedal::from_cc(index) {
+            let down = value > 0.0;
+            if down != (old_value > 0.0) {
+                self.comms.queue_note_event(julia_thread::NoteEvent::Pedal {
+                    pedal,
+                    down,
+                    offset: 0,
+                });
+            }
+        }
+        self.comms.last_midi_control.store(Some(index as u8));
+    }
+
+    /// `value` should be between 0 and 1.
+    pub fn set_host_parameter(&mut self, index: usize, value: f32) {
+        assert!(
+            index < NUM_HOST_PARAMETERS,
+            "{} is not a valid host parameter index.",
+            index
+        );
+        self.comms.host_parameters[index].store(value.clam(0.0, 1.0));
+        self.comms.host_parameters_changed[index].store(true);
     }
 
     pub fn set_bpm(&mut self, bpm: f32) {
@@ -456,7 +870,48 @@ impl AudioThreadEngine {
         self.data.global_data.elapsed_beats = beats;
     }
 
-    pub fn render_audio(&mut self) -> Vec<f32> {
+    /// Song time only advances while the transport is playing.
+    pub fn set_transport_state(&mut self, playing: bool, recording: bool) {
+        self.data.global_data.playing = playing;
+        self.data.global_data.recording = recording;
+    }
+
+    /// `points` are the start and end of the loop in beats, None means the host is not looping.
+    pub fn set_loop_points(&mut self, points: Option<(f32, f32)>) {
+        self.data.global_data.loop_points = points;
+    }
+
+    /// `beats` is where the bar the song is currently in started.
+    pub fn set_bar_start(&mut self, beats: f32) {
+        self.data.global_data.bar_start_beats = beats;
+    }
+
+    /// Some hosts report an empty time signature when they do not know it. Those are ignored so
+    /// that the last valid one stays in effect.
+    pub fn set_time_signature(&mut self, numerator: u32, denominator: u32) {
+        if numerator == 0 || denominator == 0 {
+            return;
+        }
+        self.data.global_data.time_signature = (numerator, denominator);
+    }
+
+    /// Sets how many buffers ahead of the host audio should be rendered. Higher values make
+    /// dropouts less likely at the cost of latency.
+    pub fn set_render_latency(&mut self, buffers: usize) {
+        self.data.render_latency = buffers.max(1).min(MAX_RENDER_LATENCY);
+    }
+
+    /// Returns how many samples late rendered audio is, so the host can compensate for it.
+    pub fn get_latency_samples(&self) -> usize {
+        self.data.render_latency * self.comms.global_params.load().buffer_length
+    }
+
+    /// Writes audio which was rendered `render_latency` buffers ago to `output` and requests a new
+    /// buffer to be rendered. This never waits for the Julia thread, if the requested audio is not
+    /// ready yet silence is written instead. `host_input` is interleaved audio for the new buffer
+    /// to process, in the same format as the output. It can be empty if there is none. `output` is
+    /// resized to fit the audio, which only allocates if the buffer size grows.
+    pub fn render_audio(&mut self, host_input: &[f32], output: &mut Vec<f32>) {
         let update_feedback_data =
             self.data.last_feedback_data_update.elapsed() > FEEDBACK_UPDATE_INTERVAL;
         if update_feedback_data {
@@ -465,31 +920,71 @@ impl AudioThreadEngine {
 
         // The thread will only be marked as busy if it is doing something that takes a long time,
         // e.g. compiling code.
-        let mut ready = self.comms.julia_thread_status.load().is_ready();
-        if ready {
-            let data = self.data.global_data.clone();
+        let status = self.comms.julia_thread_status.load();
+        // Only this thread sends requests, so the pipe cannot fill up after it is checked.
+        if status.accepts_render_requests()
+            && self.data.buffers_in_flight <= self.data.render_latency
+            && !self.comms.julia_render_pipe.is_full()
+        {
+            for (value, parameter) in self
+                .data
+                .global_data
+                .host_parameters
+                .iter_mut()
+                .zip(self.comms.host_parameters.iter())
+            {
+                *value = parameter.load();
+            }
+            let mut buffers = self.data.spare_buffers.pop().unwrap_or_default();
+            buffers.host_input.clear();
+            buffers.host_input.extend_from_slice(host_input);
+            // Spare vectors have room for every event the queue can hold.
+            while let Some(event) = self.comms.note_events.pop() {
+                buffers.note_events.push(event);
+            }
             let request = julia_thread::RenderRequest {
-                data,
+                data: GlobalData {
+                    host_input: buffers.host_input,
+                    ..self.data.global_data.clone()
+                },
                 do_feedback: update_feedback_data,
+                note_events: buffers.note_events,
+                audio: buffers.audio,
             };
-            let res = self.comms.julia_render_pipe.try_send(request);
-            match res {
-                Ok(()) => (),
-                Err(TrySendError::Full(..)) => ready = false,
-                Err(TrySendError::Disconnected(..)) => panic!("Julia thread has shut down."),
+            if self.comms.julia_render_pipe.try_send(request).is_err() {
+                panic!("Julia thread has shut down.");
             }
+            self.data.buffers_in_flight += 1;
         }
 
         let params = self.comms.global_params.load();
         let buf_time = params.buffer_length as f32 / params.sample_rate as f32;
-        self.data.global_data.elapsed_time += buf_time;
-        self.data.global_data.elapsed_beats += buf_time * self.data.global_data.bpm / 60.0;
-
-        if ready {
-            self.data.audio_response_output.recv().unwrap().audio
-        } else {
-            let size = params.channels * params.buffer_length;
-            vec![0.0; size]
+        self.data.global_data.advance(buf_time);
+
+        let size = params.channels * params.buffer_length;
+        output.resize(size, 0.0);
+        // Until enough buffers are queued up, we are still filling up the lookahead.
+        if self.data.buffers_in_flight > self.data.render_latency {
+            if let Ok(response) = self.data.audio_response_output.try_recv() {
+                self.data.buffers_in_flight -= 1;
+                let buffers = response.buffers;
+                // Audio rendered before the buffer size changed is discarded.
+                let rendered = buffers.audio.len() == size;
+                if rendered {
+                    output.copy_from_slice(&buffers.audio[..]);
+                }
+                self.data.spare_buffers.push(buffers);
+                if rendered {
+                    return;
+                }
+            } else if status.accepts_render_requests() {
+                // Silence while compiling or after an error is expected, anything else is a
+                // dropout.
+                self.comms.xruns.fetch_add(1);
+            }
+        }
+        for sample in output.iter_mut() {
+            *sample = 0.0;
         }
     }
 }
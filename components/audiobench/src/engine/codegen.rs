@@ -1,7 +1,10 @@
 use crate::{
     engine::{
-        controls::AutomationSource,
-        data_transfer::{DataFormat, DynDataCollector, FeedbackDisplayer, GlobalParameters},
+        controls::{AutomationSource, Control},
+        data_transfer::{
+            DataFormat, DynDataCollector, FeedbackDisplayer, GlobalParameters, IOData, IOType,
+            NUM_HOST_PARAMETERS,
+        },
         parts::*,
     },
     gui::module_widgets::FeedbackMode,
@@ -12,6 +15,9 @@ use shared_util::prelude::*;
 
 pub(super) struct CodeGenResult {
     pub code: GeneratedCode,
+    /// False if no modules run in the effects section, in which case the mix of every voice is
+    /// the final output and `exec_effects` does not need to be called.
+    pub has_effects: bool,
     pub dyn_data_collector: DynDataCollector,
     pub feedback_displayer: FeedbackDisplayer,
     pub data_format: DataFormat,
@@ -23,31 +29,53 @@ pub struct AutomationCode {
 
 impl AutomationCode {
     pub fn value_of(&self, source: &AutomationSource) -> String {
+        // Outputs of groups are really outputs of the modules inside them.
+        let (module, output_index) = Module::resolve_output(&source.module, source.output_index);
         let module_index = self
             .ordered_modules
             .iter()
-            .position(|mod_ptr| Rc::ptr_eq(mod_ptr, &source.module))
+            .position(|mod_ptr| Rc::ptr_eq(mod_ptr, &module))
             .unwrap(); // Our list should contain all the modules that exist.
-        format!("m{}o{}", module_index, source.output_index)
+        format!("m{}o{}", module_index, output_index)
     }
 }
 
 struct CodeGenerator<'a> {
     graph: &'a ModuleGraph,
+    /// Every module that is not a group, see `ModuleGraph::flattened_modules`.
+    modules: Vec<Rcrc<Module>>,
     execution_order: Vec<usize>,
+    /// Modules whose outputs are kept around for the next buffer to break feedback loops.
+    delayed: Vec<usize>,
+    sections: Vec<ModuleSections>,
+    /// Controls which are not folded into constants even though they have no automation.
+    unfolded_controls: &'a [Rcrc<dyn Control>],
     dyn_data_types: Vec<()>, // Previously IOType
     dyn_data_parameter_defs: Vec<String>,
     feedback_data_len: usize,
 }
 
+/// Generates code for the given graph. Controls without automation are written into the code as
+/// constants unless they are listed in `unfolded_controls`, which should contain controls that are
+/// likely to be changed again soon.
 pub(super) fn generate_code(
     for_graph: &ModuleGraph,
     global_params: &GlobalParameters,
+    unfolded_controls: &[Rcrc<dyn Control>],
 ) -> Result<CodeGenResult, ()> {
-    let execution_order = for_graph.compute_execution_order()?;
+    let ExecutionOrder { order, delayed } = for_graph.compute_execution_order()?;
+    let sections = for_graph.compute_sections()?;
+    // Modules which cannot be heard are left out of the code entirely.
+    let runs = |index: &usize| sections[*index].runs();
+    let order = order.into_iter().filter(runs).collect();
+    let delayed = delayed.into_iter().filter(runs).collect();
     let generator = CodeGenerator {
         graph: for_graph,
-        execution_order,
+        modules: for_graph.flattened_modules(),
+        execution_order: order,
+        delayed,
+        sections,
+        unfolded_controls,
         dyn_data_types: Vec::new(),
         dyn_data_parameter_defs: Vec::new(),
         feedback_data_len: 0,
@@ -55,122 +83,117 @@ pub(super) fn generate_code(
     Ok(generator.generate_code(global_params))
 }
 
+/// Returns the type which the outputs of delayed modules are kept in between buffers for the given
+/// type of jack, along with a silent value of that type.
+fn delayed_output_type(typ: JackType) -> (&'static str, &'static str) {
+    match typ {
+        JackType::Audio => ("StereoAudio", "StereoAudio(0f0)"),
+        JackType::Pitch => ("ControlSignal", "ControlSignal(0f0)"),
+        JackType::Trigger => ("Trigger", "Trigger(false)"),
+        JackType::Waveform => ("Waveform", "flat_waveform"),
+    }
+}
+
 impl<'a> CodeGenerator<'a> {
-    fn generate_code(mut self, global_params: &GlobalParameters) -> CodeGenResult {
-        let mut code = "".to_owned();
-        let mut ordered_modules = Vec::new();
-        let mut ordered_controls = Vec::new();
-        let mut feedback_widget_selectors = Vec::new();
-        for module_ptr in self.graph.borrow_modules() {
-            ordered_modules.push(Rc::clone(module_ptr));
-        }
+    fn is_folded(&self, control: &Rcrc<dyn Control>) -> bool {
+        let as_address = |control: &Rcrc<dyn Control>| Rc::as_ptr(control) as *const ();
+        control.borrow().get_connected_automation().len() == 0
+            && !self
+                .unfolded_controls
+                .iter()
+                .any(|other| as_address(other) == as_address(control))
+    }
 
-        code.push_str("module Generated\n\n  using Main.Registry.Factory.Lib\n\n");
-        code.push_str("  mutable struct StaticData");
-        for (index, module) in self.graph.borrow_modules().iter().enumerate() {
+    /// Writes a struct containing the static data of every module in a particular section and
+    /// returns code which creates a new instance of it.
+    fn generate_static_data(
+        &self,
+        code: &mut String,
+        struct_name: &str,
+        in_section: fn(ModuleSections) -> bool,
+    ) -> String {
+        code.push_str(&format!("  mutable struct {}", struct_name));
+        let mut init = format!("{}(\n", struct_name);
+        for (index, module) in self.modules.iter().enumerate() {
+            if !in_section(self.sections[index]) {
+                continue;
+            }
             let module_ref = module.borrow();
             let template_ref = module_ref.template.borrow();
             code.push_str(&format!(
                 "\n    m{}::Main.Registry.{}.{}Module.StaticData",
                 index, template_ref.lib_name, template_ref.module_name
             ));
-        }
-        code.push_str("  end\n\n");
-
-        code.push_str("  const static_container = Vector{StaticData}()\n\n");
-        code.push_str("  function static_init(index::Integer)\n");
-        code.push_str("    data = StaticData(\n");
-        for (index, module) in self.graph.borrow_modules().iter().enumerate() {
-            let module_ref = module.borrow();
-            let template_ref = module_ref.template.borrow();
-            code.push_str(&format!(
-                "      Main.Registry.{}.{}Module.static_init()",
+            init.push_str(&format!(
+                "      Main.Registry.{}.{}Module.static_init(),\n",
                 template_ref.lib_name, template_ref.module_name
             ));
-            if index < self.graph.borrow_modules().len() - 1 {
-                code.push_str(",\n");
+            if !self.delayed.contains(&index) {
+                continue;
             }
-        }
-        code.push_str("\n    )\n");
-        code.push_str(concat!(
-            "    if index >= length(static_container)\n",
-            "      push!(static_container, data)\n",
-            "      if index > length(static_container)\n",
-            "        static_init(index)\n",
-            "      end\n",
-            "    else\n",
-            "      static_container[index + 1] = data\n",
-            "    end\n",
-        ));
-        code.push_str("  end # function static_init\n\n");
-
-        code.push_str("  mutable struct FeedbackData\n");
-        // code.push_str("    ");
-        for (module_index, module_ptr) in self.graph.borrow_modules().iter().enumerate() {
-            let module = module_ptr.borrow();
-            let template = module.template.borrow();
-            for (widget_index, outline) in template.widget_outlines.iter().enumerate() {
-                if outline.get_feedback_mode() != FeedbackMode::None {
-                    feedback_widget_selectors.push((Rc::clone(module_ptr), widget_index));
-                    code.push_str(&format!(
-                        "    m{}w{}::Vector{{Float32}}\n",
-                        module_index, widget_index
-                    ));
-                }
+            // What the outputs were in the last buffer, starting out silent. Each one is stored
+            // in the largest type its jack can carry so that the field has a concrete type no
+            // matter which type the module actually outputs.
+            for (output_index, output) in template_ref.outputs.iter().enumerate() {
+                let (typ, initial_value) = delayed_output_type(output.get_type());
+                code.push_str(&format!("\n    d{}o{}::{}", index, output_index, typ));
+                init.push_str(&format!("      {},\n", initial_value));
             }
         }
-        code.push_str("  end # struct FeedbackData\n\n");
+        code.push_str("\n  end\n\n");
+        init.push_str("    )");
+        init
+    }
 
+    /// Generates code that runs every module in a particular section in order. `static_data` is
+    /// an expression which evaluates to the static data struct for that section.
+    fn generate_section_body(
+        &self,
+        in_section: fn(ModuleSections) -> bool,
+        static_data: &str,
+        automation_code: &AutomationCode,
+    ) -> String {
         let mut exec_body = String::new();
-        code.push_str(concat!(
-            "  function exec(midi_controls::Vector{Float32}, pitch_wheel::Float32,\n",
-            "    bpm::Float32, elapsed_time::Float32, elapsed_beats::Float32,\n",
-            "    do_feedback::Bool, note_input::NoteInput, static_index::Integer, \n",
-            "    view_index::Integer, "
-        ));
-        exec_body.push_str(concat!(
-            "    set_zero_subnormals(true)\n",
-            "    static_index += 1\n", // grumble grumble
-            "    global_input = GlobalInput(midi_controls, pitch_wheel, bpm, elapsed_time, ",
-            "elapsed_beats)\n",
-            "    start_trigger = Trigger(reshape([note_input.start_trigger, repeat([false], buffer_length - 1)...], (1, buffer_length)))\n",
-            "    release_trigger = Trigger(reshape([note_input.release_trigger, repeat([false], buffer_length - 1)...], (1, buffer_length)))\n",
-            "    note_output = NoteOutput()\n",
-            "    context = NoteContext(global_input, note_input, note_output)\n",
-            "    view = ()\n",
-            "    feedback = FeedbackData(",
-        ));
-        for _ in 0..feedback_widget_selectors.len() {
-            exec_body.push_str("Vector{Float32}(), ");
+        // Modules which run before a delayed module see its outputs from the last buffer.
+        for &index in &self.delayed {
+            if !in_section(self.sections[index]) {
+                continue;
+            }
+            let module_ref = self.modules[index].borrow();
+            for output_index in 0..module_ref.template.borrow().outputs.len() {
+                exec_body.push_str(&format!(
+                    "    m{}o{} = {}.d{}o{}\n",
+                    index, output_index, static_data, index, output_index
+                ));
+            }
         }
-        exec_body.push_str(")\n\n    context.note_out.audio .= 0f0\n");
-        let automation_code = AutomationCode {
-            ordered_modules: ordered_modules.clone(),
-        };
-        for index in self.execution_order.clone() {
-            let module_ref = self.graph.borrow_modules()[index].borrow();
+        for index in self.execution_order.iter().cloned() {
+            if !in_section(self.sections[index]) {
+                continue;
+            }
+            let module_ref = self.modules[index].borrow();
             let template_ref = module_ref.template.borrow();
             exec_body.push_str("    \n");
 
             for (control_index, control) in module_ref.controls.iter().enumerate() {
                 let control_ptr = control.as_dyn_ptr();
                 let control = control_ptr.borrow();
-                let mut idents = Vec::new();
-                if control.get_parameter_types().len() > 0 {
-                    code.push_str("\n    ");
-                }
-                for (parameter_index, ptype) in
-                    control.get_parameter_types().into_iter().enumerate()
-                {
-                    let ident = format!("m{}c{}p{}", index, control_index, parameter_index);
-                    code.push_str(&format!(" {}::{},", ident, ptype));
-                    idents.push(ident);
-                }
+                let idents: Vec<_> = if self.is_folded(&control_ptr) {
+                    control
+                        .get_parameter_values()
+                        .iter()
+                        .map(IOData::as_julia_literal)
+                        .collect()
+                } else {
+                    (0..control.get_parameter_types().len())
+                        .map(|parameter_index| {
+                            format!("m{}c{}p{}", index, control_index, parameter_index)
+                        })
+                        .collect()
+                };
                 let ident_refs: Vec<_> = idents.iter().map(|i| &i[..]).collect();
-                let code = control.generate_code(&ident_refs[..], &automation_code);
-                drop(control);
+                let code = control.generate_code(&ident_refs[..], automation_code);
                 exec_body.push_str(&format!("    m{}c{} = {}\n", index, control_index, code));
-                ordered_controls.push(control_ptr);
             }
             let template = module_ref.template.borrow();
             let mut first = true;
@@ -194,7 +217,7 @@ impl<'a> CodeGenerator<'a> {
             for output_index in 0..template_ref.outputs.len() {
                 exec_body.push_str(&format!("m{}o{}, ", index, output_index,));
             }
-            exec_body.push_str(&format!("static_container[static_index].m{}, = \n", index));
+            exec_body.push_str(&format!("{}.m{}, = \n", static_data, index));
             exec_body.push_str(&format!(
                 "    Main.Registry.{}.{}Module.exec(\n      context, do_feedback,\n",
                 template_ref.lib_name, template_ref.module_name
@@ -214,10 +237,21 @@ impl<'a> CodeGenerator<'a> {
                     exec_body.push_str(&format!("feedback.m{}w{}, ", index, widget_index));
                 }
             }
-            exec_body.push_str(&format!(
-                "\n      static_container[static_index].m{},\n    )\n",
-                index
-            ));
+            exec_body.push_str(&format!("\n      {}.m{},\n    )\n", static_data, index));
+            if self.delayed.contains(&index) {
+                for (output_index, output) in template_ref.outputs.iter().enumerate() {
+                    // Audio is copied into the existing buffer, which also widens smaller types.
+                    let assign = if output.get_type() == JackType::Waveform {
+                        "="
+                    } else {
+                        ".="
+                    };
+                    exec_body.push_str(&format!(
+                        "    {}.d{}o{} {} m{}o{}\n",
+                        static_data, index, output_index, assign, index, output_index
+                    ));
+                }
+            }
             exec_body.push_str(&format!("    if do_feedback && view_index == {}\n", index));
             exec_body.push_str("      view = (\n");
             for (out_index, output) in template.outputs.iter().enumerate() {
@@ -232,10 +266,323 @@ impl<'a> CodeGenerator<'a> {
             exec_body.push_str("      )\n");
             exec_body.push_str("    end\n");
         }
-        code.push_str("\n  )\n");
-        code.push_str(&exec_body);
-        code.push_str("\n\n    (Array(context.note_out.audio), feedback, view)\n");
+        exec_body
+    }
+
+    fn generate_code(mut self, global_params: &GlobalParameters) -> CodeGenResult {
+        let mut code = "".to_owned();
+        let mut ordered_modules = Vec::new();
+        let mut ordered_controls: Vec<Rcrc<dyn Control>> = Vec::new();
+        let mut folded_controls = Vec::new();
+        let mut feedback_widget_selectors = Vec::new();
+        for module_ptr in &self.modules {
+            ordered_modules.push(Rc::clone(module_ptr));
+        }
+        let voices = |sections: ModuleSections| sections.voices;
+        let effects = |sections: ModuleSections| sections.effects;
+        // The output of the effects section replaces the mix of every voice, so it is only used
+        // when a Voices In module passes that mix on to an output. Otherwise the patch would go
+        // silent.
+        let has_effects =
+            self.modules
+                .iter()
+                .zip(self.sections.iter())
+                .any(|(module, sections)| {
+                    module.borrow().template.borrow().effects_only && sections.effects
+                });
+
+        code.push_str("module Generated\n\n  using Main.Registry.Factory.Lib\n\n");
+        code.push_str("  const no_voices = StereoAudio(0f0)\n\n");
+        // Inputs are copied into these before each call instead of being passed as arguments, and
+        // everything the functions below produce is written into buffers which are reused, so that
+        // rendering does not have to allocate. timing_in holds the pitch wheel, bpm, elapsed time
+        // and elapsed beats.
+        code.push_str(&format!(
+            concat!(
+                "  const timing_in = zeros(Float32, 4)\n",
+                "  const transport_in = Vector{{TransportInput}}(undef, 1)\n",
+                "  const do_feedback_in = zeros(Bool, 1)\n",
+                "  const feedback_voice_in = zeros(Int64, 1)\n",
+                "  const num_voices_in = zeros(Int64, 1)\n",
+                "  const view_index_in = zeros(Int64, 1)\n",
+                "  const midi_controls_in = zeros(Float32, 128)\n",
+                "  const host_input_in = zeros(Float32, channels, buffer_length)\n",
+                "  const host_input_audio = StereoAudio(host_input_in)\n",
+                "  const host_input_channels = [similar(MonoAudio) for _ in 1:channels]\n",
+                "  const host_parameters_in = zeros(Float32, {})\n",
+                "  const voices_in = zeros(Float32, channels, buffer_length)\n",
+                "  const voices_in_audio = StereoAudio(voices_in)\n",
+                "  const note_inputs_in = Vector{{NoteInput}}()\n",
+                "  const static_indexes_in = Vector{{Int64}}()\n",
+                "  const voices_out = Vector{{Float32}}()\n",
+                "  const start_trigger = Trigger(false)\n",
+                "  const release_trigger = Trigger(false)\n",
+                "  const note_output = NoteOutput()\n",
+                "  const effects_trigger = Trigger(false)\n",
+                "  const effects_note_output = NoteOutput()\n\n",
+            ),
+            NUM_HOST_PARAMETERS
+        ));
+        code.push_str(concat!(
+            "  function reserve_voices(count::Integer)\n",
+            "    resize!(note_inputs_in, count)\n",
+            "    resize!(static_indexes_in, count)\n",
+            "    resize!(voices_out, count * channels * buffer_length)\n",
+            "  end # function reserve_voices\n\n",
+        ));
+        let static_init = self.generate_static_data(&mut code, "StaticData", voices);
+        code.push_str("  const static_container = Vector{StaticData}()\n\n");
+        code.push_str("  function static_init(index::Integer)\n");
+        code.push_str(&format!("    data = {}\n", static_init));
+        code.push_str(concat!(
+            "    if index >= length(static_container)\n",
+            "      push!(static_container, data)\n",
+            "      if index > length(static_container)\n",
+            "        static_init(index)\n",
+            "      end\n",
+            "    else\n",
+            "      static_container[index + 1] = data\n",
+            "    end\n",
+        ));
+        code.push_str("  end # function static_init\n\n");
+
+        // The effects section only ever has one copy of its static data.
+        let effects_static_init =
+            self.generate_static_data(&mut code, "EffectsStaticData", effects);
+        code.push_str("  const effects_static_container = Vector{EffectsStaticData}()\n\n");
+        code.push_str("  function effects_static_init()\n");
+        code.push_str("    empty!(effects_static_container)\n");
+        code.push_str(&format!(
+            "    push!(effects_static_container, {})\n",
+            effects_static_init
+        ));
+        code.push_str("  end # function effects_static_init\n\n");
+
+        code.push_str("  mutable struct FeedbackData\n");
+        // The vectors keep their capacity between calls, so they are only emptied instead of being
+        // replaced.
+        let mut clear_feedback = String::from("    if do_feedback\n");
+        for (module_index, module_ptr) in self.modules.iter().enumerate() {
+            let module = module_ptr.borrow();
+            let template = module.template.borrow();
+            for (widget_index, outline) in template.widget_outlines.iter().enumerate() {
+                if outline.get_feedback_mode() != FeedbackMode::None {
+                    feedback_widget_selectors.push((Rc::clone(module_ptr), widget_index));
+                    code.push_str(&format!(
+                        "    m{}w{}::Vector{{Float32}}\n",
+                        module_index, widget_index
+                    ));
+                    clear_feedback.push_str(&format!(
+                        "      empty!(feedback.m{}w{})\n",
+                        module_index, widget_index
+                    ));
+                }
+            }
+        }
+        code.push_str("  end # struct FeedbackData\n\n");
+        let mut empty_feedback = String::from("FeedbackData(");
+        for _ in 0..feedback_widget_selectors.len() {
+            empty_feedback.push_str("Vector{Float32}(), ");
+        }
+        empty_feedback.push_str(")");
+        code.push_str(&format!("  const feedback = {}\n", empty_feedback));
+        code.push_str(&format!(
+            "  const effects_feedback = {}\n\n",
+            empty_feedback
+        ));
+        clear_feedback.push_str("    end\n\n    context.note_out.audio .= 0f0\n");
+
+        // Both sections use the dynamic data of every control so that it can be collected in a
+        // single pass. The macros of the patch come first.
+        let mut parameter_defs = String::new();
+        let mut dyn_parameters = Vec::new();
+        for (index, patch_macro) in self.graph.macros.iter().enumerate() {
+            let control = patch_macro.control.borrow();
+            parameter_defs.push_str("\n    ");
+            for (parameter_index, ptype) in control.get_parameter_types().into_iter().enumerate() {
+                let ident = format!("macro{}p{}", index, parameter_index);
+                parameter_defs.push_str(&format!(" {}::{},", ident, ptype));
+                dyn_parameters.push((ident, ptype));
+            }
+            drop(control);
+            ordered_controls.push(Rc::clone(&patch_macro.control) as _);
+        }
+        for index in self.execution_order.clone() {
+            let module_ref = self.modules[index].borrow();
+            for (control_index, control) in module_ref.controls.iter().enumerate() {
+                let control_ptr = control.as_dyn_ptr();
+                if self.is_folded(&control_ptr) {
+                    let values = control_ptr.borrow().get_parameter_values();
+                    folded_controls.push((control_ptr, values));
+                    continue;
+                }
+                let control = control_ptr.borrow();
+                if control.get_parameter_types().len() > 0 {
+                    parameter_defs.push_str("\n    ");
+                }
+                for (parameter_index, ptype) in
+                    control.get_parameter_types().into_iter().enumerate()
+                {
+                    let ident = format!("m{}c{}p{}", index, control_index, parameter_index);
+                    parameter_defs.push_str(&format!(" {}::{},", ident, ptype));
+                    dyn_parameters.push((ident, ptype));
+                }
+                drop(control);
+                ordered_controls.push(control_ptr);
+            }
+        }
+        parameter_defs.push_str("\n  )\n");
+
+        // The same parameters without types, for passing them on from exec_voices to exec.
+        let mut parameter_names = String::new();
+        // exec_voices and exec_effects read the dynamic data from these globals, which are named
+        // after the position of the data they hold. Arrays start out empty and are resized with
+        // resize_dyn_data whenever their length changes.
+        let mut dyn_data_globals = String::new();
+        let mut dyn_data_reads = String::new();
+        for (index, (ident, ptype)) in dyn_parameters.into_iter().enumerate() {
+            parameter_names.push_str(&format!(" {},", ident));
+            let global = format!("dyn{}_in", index);
+            let is_array = match ptype {
+                IOType::BoolArray | IOType::IntArray | IOType::FloatArray => true,
+                IOType::Bool | IOType::Int | IOType::Float => false,
+            };
+            if is_array {
+                code.push_str(&format!("  const {} = {}()\n", global, ptype));
+                dyn_data_reads.push_str(&format!("    {} = {}\n", ident, global));
+            } else {
+                code.push_str(&format!("  const {} = zeros({}, 1)\n", global, ptype));
+                dyn_data_reads.push_str(&format!("    {} = {}[1]\n", ident, global));
+            }
+            dyn_data_globals.push_str(&format!("{}, ", global));
+        }
+        code.push_str(&format!("  const dyn_data_in = ({})\n", dyn_data_globals));
+        code.push_str(concat!(
+            "  function resize_dyn_data(index::Integer, length::Integer)\n",
+            "    resize!(dyn_data_in[index + 1], length)\n",
+            "  end # function resize_dyn_data\n\n",
+        ));
+        let read_inputs = format!(
+            concat!(
+                "    pitch_wheel, bpm, elapsed_time, elapsed_beats = timing_in\n",
+                "    transport = transport_in[1]\n",
+                "    view_index = view_index_in[1]\n",
+                // Split once per buffer so that every voice can use the channels without copying.
+                "    for c in 1:channels\n",
+                "      host_input_channels[c].data .= @view host_input_in[c:c, :]\n",
+                "    end\n",
+                "{}",
+            ),
+            dyn_data_reads
+        );
+
+        let automation_code = AutomationCode {
+            ordered_modules: ordered_modules.clone(),
+        };
+        let mut macro_values = String::new();
+        for (index, patch_macro) in self.graph.macros.iter().enumerate() {
+            let param = format!("macro{}p0", index);
+            let value = patch_macro
+                .control
+                .borrow()
+                .generate_code(&[&param[..]], &automation_code);
+            macro_values.push_str(&format!("    macro{} = {}\n", index, value));
+        }
+        code.push_str(concat!(
+            "  function exec(pitch_wheel::Float32,\n",
+            "    bpm::Float32, elapsed_time::Float32, elapsed_beats::Float32,\n",
+            "    transport::TransportInput,\n",
+            "    do_feedback::Bool, note_input::NoteInput, static_index::Integer, \n",
+            "    view_index::Integer, "
+        ));
+        code.push_str(&parameter_defs);
+        code.push_str(concat!(
+            "    set_zero_subnormals(true)\n",
+            "    static_index += 1\n", // grumble grumble
+            // Voices which started before this code was loaded have no static data yet.
+            "    if static_index > length(static_container)\n",
+            "      static_init(static_index - 1)\n",
+            "    end\n",
+            "    global_input = GlobalInput(midi_controls_in, pitch_wheel, bpm, elapsed_time, ",
+            "elapsed_beats, host_input_audio, host_input_channels, host_parameters_in, ",
+            "transport)\n",
+            "    start_trigger.data .= false\n",
+            "    start_trigger[1, note_input.start_offset + 1] = note_input.start_trigger\n",
+            "    release_trigger.data .= false\n",
+            "    release_trigger[1, note_input.release_offset + 1] = note_input.release_trigger\n",
+            "    context = NoteContext(global_input, note_input, note_output, no_voices)\n",
+            "    view = ()\n",
+        ));
+        code.push_str(&macro_values);
+        code.push_str(&clear_feedback);
+        code.push_str(&self.generate_section_body(
+            voices,
+            "static_container[static_index]",
+            &automation_code,
+        ));
+        code.push_str("\n\n    (context.note_out.audio.data, feedback, view)\n");
         code.push_str("  end # function exec\n\n");
+
+        // Rendering every voice from one call means the global and dynamic data only have to be
+        // sent to Julia once per buffer instead of once per voice. The inputs of each voice are in
+        // note_inputs_in and static_indexes_in, and their audio is written one after another into
+        // voices_out.
+        code.push_str("  function exec_voices()\n");
+        code.push_str(&read_inputs);
+        code.push_str(concat!(
+            "    feedback_voice = feedback_voice_in[1]\n",
+            "    num_voices = num_voices_in[1]\n",
+            "    voice_length = channels * buffer_length\n",
+            "    view = ()\n",
+            "    for voice in 1:num_voices\n",
+            "      do_feedback = voice == feedback_voice\n",
+            "      audio, _, voice_view = exec(pitch_wheel, bpm, elapsed_time, elapsed_beats,\n",
+            "        transport, do_feedback, note_inputs_in[voice], static_indexes_in[voice],\n",
+            "        view_index,",
+        ));
+        code.push_str(&parameter_names);
+        code.push_str(concat!(
+            "\n      )\n",
+            "      copyto!(voices_out, (voice - 1) * voice_length + 1, audio, 1, voice_length)\n",
+            "      if do_feedback\n",
+            "        view = voice_view\n",
+            "      end\n",
+            "    end\n",
+            "    (voices_out, feedback, view)\n",
+            "  end # function exec_voices\n\n",
+        ));
+
+        // The mix of every voice is in voices_in.
+        code.push_str("  function exec_effects()\n");
+        code.push_str(&read_inputs);
+        code.push_str(concat!(
+            "    do_feedback = do_feedback_in[1]\n",
+            "    set_zero_subnormals(true)\n",
+            "    if length(effects_static_container) == 0\n",
+            "      effects_static_init()\n",
+            "    end\n",
+            "    global_input = GlobalInput(midi_controls_in, pitch_wheel, bpm, elapsed_time, ",
+            "elapsed_beats, host_input_audio, host_input_channels, host_parameters_in, ",
+            "transport)\n",
+            "    note_input = effects_note_input(global_input)\n",
+            // The effects section never has a start or release, so both triggers are always off.
+            // It gets its own buffers so that it can be compiled at the same time as exec.
+            "    start_trigger = effects_trigger\n",
+            "    release_trigger = effects_trigger\n",
+            "    feedback = effects_feedback\n",
+            "    context = NoteContext(global_input, note_input, effects_note_output, ",
+            "voices_in_audio)\n",
+            "    view = ()\n",
+        ));
+        code.push_str(&macro_values);
+        code.push_str(&clear_feedback);
+        code.push_str(&self.generate_section_body(
+            effects,
+            "effects_static_container[1]",
+            &automation_code,
+        ));
+        code.push_str("\n\n    (context.note_out.audio.data, feedback, view)\n");
+        code.push_str("  end # function exec_effects\n\n");
         code.push_str("end # module Generated\n");
         let code = GeneratedCode::from_unique_source("Generated/note_graph.jl", &code);
 
@@ -251,11 +598,12 @@ impl<'a> CodeGenerator<'a> {
             dyn_data_types,
             feedback_data_len,
         };
-        let dyn_data_collector = DynDataCollector::new(ordered_controls);
+        let dyn_data_collector = DynDataCollector::new(ordered_controls, folded_controls);
         let feedback_displayer = FeedbackDisplayer::new(feedback_widget_selectors);
 
         CodeGenResult {
             code,
+            has_effects,
             dyn_data_collector,
             feedback_displayer,
             data_format,
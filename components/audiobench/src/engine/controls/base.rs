@@ -63,6 +63,11 @@ pub trait Control: Debug {
         Vec::new()
     }
 
+    /// Like get_connected_automation, but allows changing where the automation comes from.
+    fn get_connected_automation_mut<'a>(&'a mut self) -> Vec<&'a mut AutomationSource> {
+        Vec::new()
+    }
+
     fn remove_automation_by_index(&mut self, _index: usize) {
         if self.get_connected_automation().len() == 0 {
             panic!("There is no automation to remove.");
@@ -71,6 +76,18 @@ pub trait Control: Debug {
         }
     }
 
+    /// Returns true if the patch's macros can be connected to this control. Default implementation
+    /// returns false.
+    fn accepts_macros(&self) -> bool {
+        false
+    }
+
+    /// Called when the user connects the macro with the given index to a control whose
+    /// accepts_macros returns true.
+    fn connect_macro(&mut self, _index: usize) {
+        panic!("connect_macro called on control that does not accept macros.");
+    }
+
     /// Returns a list of parameter types that should be transferred to the code for this control.
     fn get_parameter_types(&self) -> Vec<IOType>;
 
@@ -91,7 +108,9 @@ pub trait Control: Debug {
 macro_rules! any_control_enum {
     ($($control_types:ident),* $(,)?) => {
         paste! {
-            #[derive(Debug)]
+            /// Cloning this shares the control it refers to, use `deep_clone` to copy the control
+            /// itself.
+            #[derive(Clone, Debug)]
             pub enum AnyControl {
                 $($control_types (Rcrc<[<$control_types Control>]>)),*
             }
@@ -109,6 +128,12 @@ macro_rules! any_control_enum {
                             => Self::$control_types(rcrc((*ptr.borrow()).clone()))),*
                     }
                 }
+
+                /// True if both refer to the same control, rather than two equal controls.
+                pub fn is_same_control(&self, other: &Self) -> bool {
+                    Rc::as_ptr(&self.as_dyn_ptr()) as *const ()
+                        == Rc::as_ptr(&other.as_dyn_ptr()) as *const ()
+                }
             }
 
             pub fn from_yaml(name: String, mut yaml: YamlNode) -> Result<(String, AnyControl), String> {
@@ -15,12 +15,98 @@ pub struct AutomationLane {
     pub connection: AutomationSource,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MidiCurve {
+    Linear,
+    Exponential,
+    Logarithmic,
+}
+
+impl MidiCurve {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Linear => "Linear",
+            Self::Exponential => "Exponential",
+            Self::Logarithmic => "Logarithmic",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            Self::Linear => Self::Exponential,
+            Self::Exponential => Self::Logarithmic,
+            Self::Logarithmic => Self::Linear,
+        }
+    }
+
+    fn ordinal(self) -> u8 {
+        match self {
+            Self::Linear => 0,
+            Self::Exponential => 1,
+            Self::Logarithmic => 2,
+        }
+    }
+
+    fn from_ordinal(ordinal: u8) -> Result<Self, ()> {
+        match ordinal {
+            0 => Ok(Self::Linear),
+            1 => Ok(Self::Exponential),
+            2 => Ok(Self::Logarithmic),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Lets one of the patch's macros set a control. Behaves like an automation lane whose input is
+/// the value of the macro.
+#[derive(Clone, Debug)]
+pub struct MacroLane {
+    /// Which macro of the patch this lane follows.
+    pub index: usize,
+    pub range: (f32, f32),
+}
+
+/// Sets a control from a hardware MIDI controller. Behaves like an extra automation lane whose
+/// input is the controller's value.
+#[derive(Clone, Debug)]
+pub struct MidiBinding {
+    pub cc: u8,
+    pub range: (f32, f32),
+    pub curve: MidiCurve,
+}
+
+impl MidiBinding {
+    pub fn serialize(&self, ser: &mut MiniSer, control_range: (f32, f32)) {
+        ser.u7(self.cc);
+        ser.f32_in_range(self.range.0, control_range.0, control_range.1);
+        ser.f32_in_range(self.range.1, control_range.0, control_range.1);
+        ser.u2(self.curve.ordinal());
+    }
+
+    pub fn deserialize(des: &mut MiniDes, control_range: (f32, f32)) -> Result<Self, ()> {
+        Ok(Self {
+            cc: des.u7()?,
+            range: (
+                des.f32_in_range(control_range.0, control_range.1)?,
+                des.f32_in_range(control_range.0, control_range.1)?,
+            ),
+            curve: MidiCurve::from_ordinal(des.u2()?)?,
+        })
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct FloatInRangeControl {
     pub range: (f32, f32),
     pub value: f32,
     pub default: f32,
     pub automation: Vec<AutomationLane>,
+    pub macro_lanes: Vec<MacroLane>,
+    pub midi_binding: Option<MidiBinding>,
+    /// Which host automation parameter sets the value of this control, if any. Automated controls
+    /// cannot be assigned one, and if automation is added after assigning one the automation takes
+    /// over until it is removed again.
+    pub host_parameter: Option<usize>,
     pub suffix: String,
 }
 
@@ -39,13 +125,95 @@ impl FloatInRangeControl {
         } else {
             "".to_owned()
         };
-        Ok(Self {
-            range: (min, max),
+        Ok(Self::new((min, max), default, suffix))
+    }
+
+    pub fn new(range: (f32, f32), default: f32, suffix: String) -> Self {
+        Self {
+            range,
             value: default,
             default,
             automation: Vec::new(),
+            macro_lanes: Vec::new(),
+            midi_binding: None,
+            host_parameter: None,
             suffix,
-        })
+        }
+    }
+
+    /// Binds a MIDI controller to this control, covering its whole range.
+    pub fn bind_midi_cc(&mut self, cc: u8) {
+        self.midi_binding = Some(MidiBinding {
+            cc,
+            range: self.range,
+            curve: MidiCurve::Linear,
+        });
+    }
+
+    /// Converts the value of this control to the 0 to 1 range used for host parameters.
+    pub fn get_normalized_value(&self) -> f32 {
+        (self.value - self.range.0) / (self.range.1 - self.range.0)
+    }
+
+    pub fn set_normalized_value(&mut self, value: f32) {
+        self.value = value * (self.range.1 - self.range.0) + self.range.0;
+    }
+
+    /// True if the value of this control comes from something other than its manual value.
+    pub fn is_automated(&self) -> bool {
+        self.automation.len() > 0 || self.macro_lanes.len() > 0 || self.midi_binding.is_some()
+    }
+
+    /// The number of lanes shown when editing this control. This is every automation lane followed
+    /// by every macro lane and then the MIDI binding, if there is one.
+    pub fn num_lanes(&self) -> usize {
+        self.automation.len() + self.macro_lanes.len() + self.midi_binding.iter().count()
+    }
+
+    pub fn is_midi_lane(&self, lane: usize) -> bool {
+        self.midi_binding.is_some() && lane == self.automation.len() + self.macro_lanes.len()
+    }
+
+    /// Returns the position in `macro_lanes` of the given lane, if it is a macro lane.
+    fn macro_lane_position(&self, lane: usize) -> Option<usize> {
+        lane.checked_sub(self.automation.len())
+            .filter(|position| *position < self.macro_lanes.len())
+    }
+
+    /// Returns which macro the given lane follows, if it is a macro lane.
+    pub fn lane_macro(&self, lane: usize) -> Option<usize> {
+        self.macro_lane_position(lane)
+            .map(|position| self.macro_lanes[position].index)
+    }
+
+    pub fn lane_range(&self, lane: usize) -> (f32, f32) {
+        if self.is_midi_lane(lane) {
+            self.midi_binding.as_ref().unwrap().range
+        } else if let Some(position) = self.macro_lane_position(lane) {
+            self.macro_lanes[position].range
+        } else {
+            self.automation[lane].range
+        }
+    }
+
+    pub fn lane_range_mut(&mut self, lane: usize) -> &mut (f32, f32) {
+        if self.is_midi_lane(lane) {
+            &mut self.midi_binding.as_mut().unwrap().range
+        } else if let Some(position) = self.macro_lane_position(lane) {
+            &mut self.macro_lanes[position].range
+        } else {
+            &mut self.automation[lane].range
+        }
+    }
+
+    pub fn remove_lane(&mut self, lane: usize) {
+        if self.is_midi_lane(lane) {
+            self.midi_binding = None;
+        } else if let Some(position) = self.macro_lane_position(lane) {
+            self.macro_lanes.remove(position);
+        } else {
+            self.automation.remove(lane);
+        }
     }
 }
 
@@ -69,10 +237,28 @@ impl Control for FloatInRangeControl {
             .collect()
     }
 
+    fn get_connected_automation_mut<'a>(&'a mut self) -> Vec<&'a mut AutomationSource> {
+        self.automation
+            .iter_mut()
+            .map(|item| &mut item.connection)
+            .collect()
+    }
+
     fn remove_automation_by_index(&mut self, index: usize) {
         self.automation.remove(index);
     }
 
+    fn accepts_macros(&self) -> bool {
+        true
+    }
+
+    fn connect_macro(&mut self, index: usize) {
+        if !self.macro_lanes.iter().any(|lane| lane.index == index) {
+            let range = self.range;
+            self.macro_lanes.push(MacroLane { index, range });
+        }
+    }
+
     fn get_parameter_types(&self) -> Vec<IOType> {
         vec![IOType::FloatArray]
     }
@@ -88,17 +274,38 @@ impl Control for FloatInRangeControl {
             values.push(a);
             values.push(b);
         }
+        for lane in &self.macro_lanes {
+            // Macros produce values from 0 to 1 instead of -1 to 1.
+            values.push(lane.range.1 - lane.range.0);
+            values.push(lane.range.0);
+        }
+        if let Some(binding) = &self.midi_binding {
+            // The curve produces values from 0 to 1 instead of -1 to 1.
+            values.push(binding.range.1 - binding.range.0);
+            values.push(binding.range.0);
+            values.push(binding.curve.ordinal() as f32);
+        }
+        if self.host_parameter.is_some() {
+            values.push(self.range.1 - self.range.0);
+            values.push(self.range.0);
+        }
         vec![IOData::FloatArray(values)]
     }
 
     fn generate_code(&self, params: &[&str], automation_code: &AutomationCode) -> String {
-        if self.automation.len() == 0 {
+        if let (false, Some(index)) = (self.is_automated(), self.host_parameter) {
+            // Without automation, the range of the control comes right after the manual value.
+            format!(
+                "StaticControlSignal(global_input.host_parameters[{}] * {p}[2] + {p}[3])",
+                index + 1,
+                p = params[0],
+            )
+        } else if !self.is_automated() {
             format!("StaticControlSignal({}[1])", params[0])
         } else {
             let mut code = String::new();
             let mut index = 2; // Julia indexing starts at 1.
             let mut first = Some(());
-            let num_lanes = self.automation.len() as f32;
             code.push_str("(");
             for lane in &self.automation {
                 if !first.take().is_some() {
@@ -114,7 +321,36 @@ impl Control for FloatInRangeControl {
                 ));
                 index += 2;
             }
-            code.push_str(&format!(") / Float32({})", self.automation.len()));
+            for lane in &self.macro_lanes {
+                if !first.take().is_some() {
+                    code.push_str(" .+ ");
+                }
+                code.push_str(&format!(
+                    "(macro{} .* {}[{}] .+ {}[{}])",
+                    lane.index,
+                    params[0],
+                    index,
+                    params[0],
+                    index + 1,
+                ));
+                index += 2;
+            }
+            if let Some(binding) = &self.midi_binding {
+                if !first.take().is_some() {
+                    code.push_str(" .+ ");
+                }
+                code.push_str(&format!(
+                    "(midi_binding_curve(global_input.midi_controls[{}], {}[{}]) .* {}[{}] .+ {}[{}])",
+                    binding.cc as usize + 1,
+                    params[0],
+                    index + 2,
+                    params[0],
+                    index,
+                    params[0],
+                    index + 1,
+                ));
+            }
+            code.push_str(&format!(") / Float32({})", self.num_lanes()));
             code
         }
     }
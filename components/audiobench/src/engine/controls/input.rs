@@ -9,12 +9,23 @@ use crate::{
 };
 use shared_util::mini_serde::{MiniDes, MiniSer};
 
+/// Replaced with the index of the MIDI controller an input is set to read from.
+const MIDI_CC_PLACEHOLDER: &str = "$cc";
+pub const NUM_MIDI_CCS: u8 = 128;
+
 pub struct DefaultInputDescription {
     pub name: &'static str,
     pub code: &'static str,
     pub icon: &'static str,
 }
 
+impl DefaultInputDescription {
+    /// True if this default reads a MIDI controller chosen per input.
+    pub fn uses_midi_cc(&self) -> bool {
+        self.code.contains(MIDI_CC_PLACEHOLDER)
+    }
+}
+
 fn default_option_descriptions_for(typ: JackType) -> &'static [DefaultInputDescription] {
     match typ {
         JackType::Pitch => &[DefaultInputDescription {
@@ -44,11 +55,68 @@ fn default_option_descriptions_for(typ: JackType) -> &'static [DefaultInputDescr
                 icon: "Factory:sine_wave",
             },
         ],
-        JackType::Audio => &[DefaultInputDescription {
-            name: "Silence",
-            code: "StaticMonoAudio(0f0)",
-            icon: "Factory:nothing",
-        }],
+        JackType::Audio => &[
+            DefaultInputDescription {
+                name: "Silence",
+                code: "StaticMonoAudio(0f0)",
+                icon: "Factory:nothing",
+            },
+            DefaultInputDescription {
+                name: "Note Pressure",
+                code: "StaticControlSignal(note_input.pressure)",
+                icon: "Factory:note_down",
+            },
+            DefaultInputDescription {
+                name: "Note Timbre",
+                code: "StaticControlSignal(note_input.timbre)",
+                icon: "Factory:treble_clef",
+            },
+            DefaultInputDescription {
+                name: "Host Input",
+                code: "global_input.host_input",
+                icon: "Factory:audio",
+            },
+            DefaultInputDescription {
+                name: "Host Input (L)",
+                code: "host_input_channel(global_input, 1)",
+                icon: "Factory:audio",
+            },
+            DefaultInputDescription {
+                name: "Host Input (R)",
+                code: "host_input_channel(global_input, 2)",
+                icon: "Factory:audio",
+            },
+            DefaultInputDescription {
+                name: "Note Velocity",
+                code: "StaticControlSignal(note_input.velocity)",
+                icon: "Factory:note_down",
+            },
+            DefaultInputDescription {
+                name: "Key Tracking",
+                code: "StaticControlSignal(key_tracking(note_input))",
+                icon: "Factory:note",
+            },
+            DefaultInputDescription {
+                name: "Pitch Wheel",
+                code: "StaticControlSignal(global_input.pitch_wheel)",
+                icon: "Factory:pitch",
+            },
+            DefaultInputDescription {
+                name: "MIDI CC",
+                code: "StaticControlSignal(global_input.midi_controls[$cc])",
+                icon: "Factory:tune",
+            },
+            DefaultInputDescription {
+                name: "Note Number",
+                code: "StaticControlSignal(note_number(note_input))",
+                icon: "Factory:note",
+            },
+            DefaultInputDescription {
+                name: "Release Velocity",
+                code: "StaticControlSignal(note_input.release_velocity)",
+                icon: "Factory:note_up",
+            },
+        ],
         JackType::Trigger => &[
             DefaultInputDescription {
                 name: "Note Start",
@@ -73,6 +141,8 @@ fn default_option_descriptions_for(typ: JackType) -> &'static [DefaultInputDescr
 pub struct InputControl {
     typ: JackType,
     default: usize,
+    /// Which controller the "MIDI CC" default reads, 0-127.
+    midi_cc: u8,
     connection: Option<AutomationSource>,
 }
 
@@ -93,6 +163,7 @@ impl InputControl {
         Ok(Self {
             typ,
             default,
+            midi_cc: 1,
             connection: None,
         })
     }
@@ -114,6 +185,25 @@ impl InputControl {
         self.default = (self.default + 1) % default_option_descriptions_for(self.get_type()).len();
     }
 
+    pub fn get_midi_cc(&self) -> u8 {
+        self.midi_cc
+    }
+
+    pub fn set_midi_cc(&mut self, midi_cc: u8) {
+        assert!(midi_cc < NUM_MIDI_CCS);
+        self.midi_cc = midi_cc;
+    }
+
+    /// Name of the default being used, including which MIDI controller it reads if applicable.
+    pub fn get_used_default_name(&self) -> Option<String> {
+        let default = self.get_used_default()?;
+        Some(if default.uses_midi_cc() {
+            format!("{} {}", default.name, self.midi_cc)
+        } else {
+            default.name.to_owned()
+        })
+    }
+
     pub fn disconnect(&mut self) {
         self.connection = None;
     }
@@ -129,6 +219,9 @@ impl Control for InputControl {
     fn get_connected_automation<'a>(&'a self) -> Vec<&'a AutomationSource> {
         self.connection.iter().collect()
     }
+    fn get_connected_automation_mut<'a>(&'a mut self) -> Vec<&'a mut AutomationSource> {
+        self.connection.iter_mut().collect()
+    }
     fn remove_automation_by_index(&mut self, index: usize) {
         assert_eq!(index, 0);
         self.connection = None;
@@ -140,15 +233,28 @@ impl Control for InputControl {
         if let Some(connection) = &self.connection {
             automation_code.value_of(connection)
         } else {
-            self.get_used_default().unwrap().code.to_owned()
+            let code = self.get_used_default().unwrap().code;
+            // Julia arrays start at 1.
+            code.replace(MIDI_CC_PLACEHOLDER, &format!("{}", self.midi_cc as usize + 1))
         }
     }
     fn serialize(&self, ser: &mut MiniSer) {
         assert!(self.default < 16);
         ser.u4(self.default as _);
+        if default_option_descriptions_for(self.typ)[self.default].uses_midi_cc() {
+            ser.u7(self.midi_cc);
+        }
     }
     fn deserialize(&mut self, des: &mut MiniDes) -> Result<(), ()> {
-        self.default = des.u4()? as _;
+        let default = des.u4()? as usize;
+        let options = default_option_descriptions_for(self.typ);
+        if default >= options.len() {
+            return Err(());
+        }
+        self.default = default;
+        if options[default].uses_midi_cc() {
+            self.midi_cc = des.u7()?;
+        }
         Ok(())
     }
 }
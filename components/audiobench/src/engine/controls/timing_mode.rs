@@ -8,13 +8,45 @@ use crate::{
 };
 use shared_util::mini_serde::{MiniDes, MiniSer};
 
+/// What time is measured in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimingUnits {
+    Seconds,
+    Beats,
+    /// Uses the time signature of the host, so a bar starts on each of the host's bar lines.
+    Bars,
+}
+
+impl TimingUnits {
+    pub const ALL: [TimingUnits; 3] = [Self::Seconds, Self::Beats, Self::Bars];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Seconds => "seconds",
+            Self::Beats => "beats",
+            Self::Bars => "bars",
+        }
+    }
+
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Self::Seconds => "s",
+            Self::Beats => "b",
+            Self::Bars => "m",
+        }
+    }
+
+    fn ordinal(&self) -> u8 {
+        Self::ALL.iter().position(|u| u == self).unwrap() as u8
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct TimingModeControl {
     /// True if time should be measured against how long the song has been running, false if time
     /// should be measured against how long the note has been running.
     use_elapsed_time: bool,
-    /// True if time should be measured in seconds, false if time should be measured in beats.
-    beat_synchronized: bool,
+    units: TimingUnits,
 }
 
 impl TimingModeControl {
@@ -24,21 +56,20 @@ impl TimingModeControl {
         } else {
             false
         };
-        let beat_synchronized = if let Ok(child) = yaml.map_entry("default_units") {
-            child.parse_enumerated(&["seconds", "beats"])? == 1
+        let units = if let Ok(child) = yaml.map_entry("default_units") {
+            TimingUnits::ALL[child.parse_enumerated(&["seconds", "beats", "bars"])?]
         } else {
-            false
+            TimingUnits::Seconds
         };
         Ok(Self {
             use_elapsed_time,
-            beat_synchronized,
+            units,
         })
     }
 
     fn get_raw_value(&self) -> u8 {
         let source_flag = if self.use_elapsed_time { 0b1 } else { 0b0 };
-        let unit_flag = if self.beat_synchronized { 0b10 } else { 0b00 };
-        source_flag | unit_flag
+        source_flag | self.units.ordinal() << 1
     }
 
     pub fn uses_elapsed_time(&self) -> bool {
@@ -50,12 +81,14 @@ impl TimingModeControl {
         UpdateRequest::UpdateDynData
     }
 
-    pub fn is_beat_synchronized(&self) -> bool {
-        self.beat_synchronized
+    pub fn get_units(&self) -> TimingUnits {
+        self.units
     }
 
-    pub fn toggle_units(&mut self) -> UpdateRequest {
-        self.beat_synchronized = !self.beat_synchronized;
+    /// Switches to the next kind of units, going from seconds to beats to bars and back again.
+    pub fn cycle_units(&mut self) -> UpdateRequest {
+        let next = (self.units.ordinal() as usize + 1) % TimingUnits::ALL.len();
+        self.units = TimingUnits::ALL[next];
         UpdateRequest::UpdateDynData
     }
 }
@@ -73,11 +106,8 @@ impl Control for TimingModeControl {
     }
     fn deserialize(&mut self, des: &mut MiniDes) -> Result<(), ()> { 
         let raw_value = des.u8()?;
-        if raw_value > 0b11 {
-            return Err(());
-        }
         self.use_elapsed_time = raw_value & 0b1 == 0b1;
-        self.beat_synchronized = raw_value & 0b10 == 0b10;
+        self.units = *TimingUnits::ALL.get((raw_value >> 1) as usize).ok_or(())?;
         Ok(())
     }
 }
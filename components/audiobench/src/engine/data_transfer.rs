@@ -2,7 +2,8 @@ use crate::{
     engine::{controls::Control, parts::Module},
     gui::top_level::graph::ModuleGraph,
 };
-use julia_helper::{Frame, JlrsResult, Value};
+use jlrs_derive::IntoJulia;
+use julia_helper::JuliaStruct;
 use shared_util::prelude::*;
 use std::fmt::{Display, Formatter};
 
@@ -20,6 +21,9 @@ pub struct DataFormat {
     pub feedback_data_len: usize,
 }
 
+/// How many parameters are shown to the host for automation. Hosts expect this to never change.
+pub const NUM_HOST_PARAMETERS: usize = 16;
+
 #[derive(Clone, PartialEq)]
 pub struct GlobalData {
     // MIDI specifies each MIDI Channel has 128 controls.
@@ -29,6 +33,36 @@ pub struct GlobalData {
     pub bpm: f32,
     pub elapsed_time: f32,
     pub elapsed_beats: f32,
+    /// Interleaved audio the host passed in to be processed, in the same format as rendered audio.
+    /// Treated as silence if it is the wrong size.
+    pub host_input: Vec<f32>,
+    /// The value of every host automation parameter, from 0 to 1.
+    pub host_parameters: [f32; NUM_HOST_PARAMETERS],
+    /// False while the host's transport is stopped, in which case the song position does not
+    /// advance.
+    pub playing: bool,
+    pub recording: bool,
+    /// The start and end of the section the host is looping, in beats.
+    pub loop_points: Option<(f32, f32)>,
+    /// Where the bar the song is currently in started, in beats. Used to line bars up with the
+    /// host even if the time signature changed earlier in the song.
+    pub bar_start_beats: f32,
+    /// Numerator and denominator.
+    pub time_signature: (u32, u32),
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, JuliaStruct, IntoJulia)]
+#[jlrs(julia_type = "Main.Registry.Factory.Lib.TransportInput")]
+pub(super) struct TransportInput {
+    pub playing: bool,
+    pub recording: bool,
+    pub looping: bool,
+    pub loop_start: f32,
+    pub loop_end: f32,
+    pub elapsed_bars: f32,
+    pub time_signature_numerator: i32,
+    pub time_signature_denominator: i32,
 }
 
 impl GlobalData {
@@ -39,20 +73,58 @@ impl GlobalData {
             bpm: 120.0,
             elapsed_time: 0.0,
             elapsed_beats: 0.0,
+            host_input: Vec::new(),
+            host_parameters: [0.0; NUM_HOST_PARAMETERS],
+            // Hosts which don't report their transport get a song that is always playing.
+            playing: true,
+            recording: false,
+            loop_points: None,
+            bar_start_beats: 0.0,
+            time_signature: (4, 4),
+        }
+    }
+
+    /// How many beats (quarter notes) there are in each bar.
+    pub fn beats_per_bar(&self) -> f32 {
+        let (numerator, denominator) = self.time_signature;
+        numerator as f32 * 4.0 / denominator as f32
+    }
+
+    pub fn elapsed_bars(&self) -> f32 {
+        let beats_per_bar = self.beats_per_bar();
+        let bar_index = (self.bar_start_beats / beats_per_bar).round();
+        bar_index + (self.elapsed_beats - self.bar_start_beats) / beats_per_bar
+    }
+
+    /// Moves the song position forward by `seconds` if the transport is playing, jumping back to
+    /// the start of the loop if it passes the end.
+    pub fn advance(&mut self, seconds: f32) {
+        if !self.playing {
+            return;
+        }
+        self.elapsed_time += seconds;
+        self.elapsed_beats += seconds * self.bpm / 60.0;
+        if let Some((start, end)) = self.loop_points {
+            if end > start && self.elapsed_beats >= end {
+                let loop_beats = end - start;
+                self.elapsed_beats -= loop_beats;
+                self.elapsed_time -= loop_beats * 60.0 / self.bpm;
+            }
         }
     }
 
-    pub fn as_julia_values<'f>(
-        &self,
-        frame: &mut impl Frame<'f>,
-    ) -> JlrsResult<Vec<Value<'f, 'f>>> {
-        Ok(vec![
-            Value::move_array(frame, self.controller_values.to_vec(), (128,))?,
-            Value::new(frame, self.pitch_wheel)?,
-            Value::new(frame, self.bpm)?,
-            Value::new(frame, self.elapsed_time)?,
-            Value::new(frame, self.elapsed_beats)?,
-        ])
+    pub(super) fn transport_input(&self) -> TransportInput {
+        let (loop_start, loop_end) = self.loop_points.unwrap_or((0.0, 0.0));
+        TransportInput {
+            playing: self.playing,
+            recording: self.recording,
+            looping: self.loop_points.is_some(),
+            loop_start,
+            loop_end,
+            elapsed_bars: self.elapsed_bars(),
+            time_signature_numerator: self.time_signature.0 as i32,
+            time_signature_denominator: self.time_signature.1 as i32,
+        }
     }
 }
 
@@ -60,9 +132,22 @@ impl GlobalData {
 #[derive(Clone, Copy, Debug)]
 pub struct NoteData {
     pub pitch: f32,
+    /// The MIDI key the note is playing. Unlike the pitch, this does not depend on the tuning.
+    pub key: usize,
     pub velocity: f32,
+    /// Zero until the note is released.
+    pub release_velocity: f32,
+    /// Samples elapsed from the start of the buffer the note started in.
     pub elapsed_samples: usize,
     pub elapsed_beats: f32,
+    /// Per-note expression, see `NoteExpression`.
+    pub pitch_bend: f32,
+    pub pressure: f32,
+    pub timbre: f32,
+    /// Which sample in its first buffer the note started on.
+    pub start_offset: usize,
+    /// Which sample in the current buffer the note was released on.
+    pub release_offset: usize,
     pub start_trigger: bool,
     pub release_trigger: bool,
 }
@@ -74,6 +159,31 @@ pub struct FeedbackData {
     pub output_view_module_index: usize,
 }
 
+impl FeedbackData {
+    /// Fills in anything this data is missing using feedback from a different section of the
+    /// generated code. Widgets of modules which did not run in a section get no feedback. The data
+    /// is copied so that the vectors of both sides can be reused.
+    pub fn merge(&mut self, other: &FeedbackData) {
+        if self.widget_feeback.len() < other.widget_feeback.len() {
+            self.widget_feeback
+                .resize(other.widget_feeback.len(), Vec::new());
+        }
+        for (own, other) in self.widget_feeback.iter_mut().zip(&other.widget_feeback) {
+            if own.len() == 0 {
+                own.extend_from_slice(other);
+            }
+        }
+        if self.output_view.len() == 0 {
+            self.output_view.resize(other.output_view.len(), Vec::new());
+            for (own, other) in self.output_view.iter_mut().zip(&other.output_view) {
+                own.clear();
+                own.extend_from_slice(other);
+            }
+            self.output_view_module_index = other.output_view_module_index;
+        }
+    }
+}
+
 /// Represents the data type of a variable which is either an input or output in the generated
 /// program. E.G. `IOType::FloatArray(20)` would be the type of `input [20]FLOAT some_data;`.
 #[derive(Eq, PartialEq, Clone, Copy)]
@@ -111,15 +221,30 @@ pub enum IOData {
 }
 
 impl IOData {
-    pub fn as_julia_value<'f>(&self, frame: &mut impl Frame<'f>) -> JlrsResult<Value<'f, 'f>> {
+    /// Returns Julia code which evaluates to this data, used to write the values of controls
+    /// directly into generated code.
+    pub fn as_julia_literal(&self) -> String {
+        fn float(value: f32) -> String {
+            if value.is_nan() {
+                "NaN32".to_owned()
+            } else if value.is_infinite() {
+                if value > 0.0 { "Inf32" } else { "-Inf32" }.to_owned()
+            } else {
+                format!("{:?}", value)
+            }
+        }
+        fn array<T>(typ: &str, items: &[T], item: impl Fn(&T) -> String) -> String {
+            let items: Vec<_> = items.iter().map(item).collect();
+            format!("{}[{}]", typ, items.join(", "))
+        }
         use IOData::*;
         match self {
-            Bool(v) => Value::new(frame, *v),
-            Int(v) => Value::new(frame, *v),
-            Float(v) => Value::new(frame, *v),
-            BoolArray(v) => Value::move_array(frame, v.clone(), (v.len(),)),
-            IntArray(v) => Value::move_array(frame, v.clone(), (v.len(),)),
-            FloatArray(v) => Value::move_array(frame, v.clone(), (v.len(),)),
+            Bool(v) => format!("{}", v),
+            Int(v) => format!("Int32({})", v),
+            Float(v) => format!("Float32({})", float(*v)),
+            BoolArray(v) => array("Bool", v, |v| format!("{}", v)),
+            IntArray(v) => array("Int32", v, |v| format!("{}", v)),
+            FloatArray(v) => array("Float32", v, |v| float(*v)),
         }
     }
 }
@@ -127,6 +252,9 @@ impl IOData {
 #[scones::make_constructor]
 pub struct DynDataCollector {
     controls: Vec<Rcrc<dyn Control>>,
+    /// Controls whose values were written into the generated code as constants, along with the
+    /// values that were written.
+    folded_controls: Vec<(Rcrc<dyn Control>, Vec<IOData>)>,
 }
 
 impl DynDataCollector {
@@ -137,6 +265,16 @@ impl DynDataCollector {
         }
         result
     }
+
+    /// Returns every folded control whose value has changed since the code was generated. The
+    /// code has to be generated again for those changes to be heard.
+    pub fn changed_folded_controls(&self) -> Vec<Rcrc<dyn Control>> {
+        self.folded_controls
+            .iter()
+            .filter(|(control, values)| control.borrow().get_parameter_values() != *values)
+            .map(|(control, _)| Rc::clone(control))
+            .collect()
+    }
 }
 
 #[scones::make_constructor]
@@ -146,20 +284,28 @@ pub struct FeedbackDisplayer {
 }
 
 impl FeedbackDisplayer {
-    pub fn display(&self, data: FeedbackData, on: Rc<ModuleGraph>) {
+    pub fn display(&self, data: &FeedbackData, on: Rc<ModuleGraph>) {
         if data.widget_feeback.len() != self.widget_selectors.len() {
             return;
         }
         for (index, (module, widget_index)) in self.widget_selectors.iter().enumerate() {
-            let module_widget = on.get_widget_for_module(module).unwrap();
-            module_widget.take_feedback_data(data.widget_feeback[index].clone(), *widget_index);
+            if data.widget_feeback[index].len() == 0 {
+                // The module did not run in the section that produced this data.
+                continue;
+            }
+            // Modules inside groups only have widgets while the group is open.
+            if let Some(module_widget) = on.get_widget_for_module(module) {
+                module_widget.take_feedback_data(data.widget_feeback[index].clone(), *widget_index);
+            }
         }
         let real_graph_ptr: Rcrc<crate::engine::parts::ModuleGraph> = on.get_real_graph();
         let real_graph = real_graph_ptr.borrow();
-        if data.output_view_module_index < real_graph.borrow_modules().len() {
-            let module = &real_graph.borrow_modules()[data.output_view_module_index];
-            let module_widget = on.get_widget_for_module(module).unwrap();
-            module_widget.take_output_view_data(data.output_view);
+        let modules = real_graph.flattened_modules();
+        if data.output_view.len() > 0 && data.output_view_module_index < modules.len() {
+            let module = &modules[data.output_view_module_index];
+            if let Some(module_widget) = on.get_widget_for_module(module) {
+                module_widget.take_output_view_data(data.output_view.clone());
+            }
         }
     }
 }
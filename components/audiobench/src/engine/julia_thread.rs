@@ -5,13 +5,19 @@ use crate::engine::{
 };
 use crossbeam_channel::{Receiver, Sender};
 use julia_helper::GeneratedCode;
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
+
+/// How often to check on code compiling in the background when there is nothing else to do.
+const STAGED_CODE_POLL_INTERVAL: Duration = Duration::from_millis(10);
 
 #[derive(Clone, Copy, Eq, PartialEq)]
 pub enum Status {
     Ready,
     Busy,
     Rendering,
+    /// Something went wrong, e.g. new code failed to compile, but the last code that worked keeps
+    /// playing. This stays until new code loads successfully.
+    RecoverableError,
     Error,
 }
 
@@ -19,21 +25,119 @@ impl Status {
     pub fn is_ready(&self) -> bool {
         self == &Self::Ready
     }
+
+    /// True if the thread will get to new render requests without a long delay, I.E. it is not
+    /// compiling code or stuck on an error.
+    pub fn accepts_render_requests(&self) -> bool {
+        self == &Self::Ready || self == &Self::Rendering || self == &Self::RecoverableError
+    }
 }
 
+/// MIDI uses a release velocity of 64 for keyboards which can't measure how fast keys are released.
+pub const DEFAULT_RELEASE_VELOCITY: f32 = 0.5;
+
+/// Offsets are measured in samples from the start of the buffer the event should happen in.
+#[derive(Clone, Copy, Debug)]
 pub enum NoteEvent {
-    StartNote { index: usize, velocity: f32 },
-    ReleaseNote { index: usize },
+    StartNote {
+        index: usize,
+        velocity: f32,
+        offset: usize,
+    },
+    ReleaseNote {
+        index: usize,
+        /// How fast the key was released, from 0 to 1.
+        velocity: f32,
+        offset: usize,
+    },
+    /// Changes one of the per-note expression values of a held note, E.G. from an MPE controller.
+    /// Takes effect at the start of the next buffer.
+    Expression {
+        index: usize,
+        expression: NoteExpression,
+        value: f32,
+    },
+    /// Presses or lifts one of the pedals which keep notes sounding after their keys are released.
+    Pedal {
+        pedal: Pedal,
+        down: bool,
+        offset: usize,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoteExpression {
+    /// Measured in semitones.
+    PitchBend,
+    /// Ranges from 0 to 1.
+    Pressure,
+    /// Ranges from 0 to 1, also called slide or Y axis. Usually sent as MIDI CC 74.
+    Timbre,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pedal {
+    /// Keeps every released note sounding while it is down. MIDI CC 64.
+    Sustain,
+    /// Keeps only the notes which were held when it was pressed sounding. MIDI CC 66.
+    Sostenuto,
+}
+
+impl Pedal {
+    pub fn from_cc(cc: usize) -> Option<Self> {
+        match cc {
+            64 => Some(Self::Sustain),
+            66 => Some(Self::Sostenuto),
+            _ => None,
+        }
+    }
 }
 
 pub struct RenderRequest {
     pub data: GlobalData,
     pub do_feedback: bool,
+    /// Events which should be applied before rendering this buffer. These travel with the request
+    /// so that rendering ahead of time does not change when the events are heard.
+    pub note_events: Vec<NoteEvent>,
+    /// Where the audio should be rendered to, resized as needed.
+    pub audio: Vec<f32>,
 }
 
-pub struct AudioResponse {
+impl RenderRequest {
+    /// Sends the buffers of this request back to the audio thread along with the audio that was
+    /// rendered into them.
+    pub fn into_response(self) -> AudioResponse {
+        let Self {
+            data,
+            mut note_events,
+            audio,
+            ..
+        } = self;
+        let mut host_input = data.host_input;
+        host_input.clear();
+        note_events.clear();
+        AudioResponse {
+            buffers: RenderBuffers {
+                audio,
+                host_input,
+                note_events,
+            },
+        }
+    }
+}
+
+/// Every vector that travels between the audio thread and the Julia thread. They are sent back
+/// and forth instead of being dropped so that rendering does not allocate.
+#[derive(Default)]
+pub struct RenderBuffers {
     pub audio: Vec<f32>,
-    // feedback_data: Option<Vec<f32>>
+    pub host_input: Vec<f32>,
+    pub note_events: Vec<NoteEvent>,
+}
+
+pub struct AudioResponse {
+    /// Contains the rendered audio, the other buffers are empty.
+    pub buffers: RenderBuffers,
 }
 
 pub(super) fn entry(
@@ -41,6 +145,7 @@ pub(super) fn entry(
     global_params: GlobalParameters,
     registry_source: GeneratedCode,
     default_patch_code: GeneratedCode,
+    default_patch_has_effects: bool,
     dyn_data: Vec<IOData>,
     render_pipe: Receiver<RenderRequest>,
     poll_pipe: Receiver<()>,
@@ -62,17 +167,17 @@ pub(super) fn entry(
         }
     };
     let res = executor
-        .change_generated_code(default_patch_code)
+        .change_generated_code(default_patch_code, default_patch_has_effects)
         .map_err(|err| {
             format!(
                 "Default patch failed to compile! (See message log for details.)\n\n{}",
                 err
             )
         });
+    let has_error = res.is_err();
     if let Err(err) = res {
+        // The executor will produce silence until a patch that works is loaded.
         error_report_pipe.send(err).unwrap();
-        comms.julia_thread_status.store(Status::Error);
-        panic!("Unrecoverable error.");
     }
 
     let mut thread = JuliaThread {
@@ -81,10 +186,12 @@ pub(super) fn entry(
         global_params,
         dyn_data,
         notes: NoteTracker::new(),
+        queued_code: None,
         render_pipe,
         poll_pipe,
         audio_response_pipe,
         error_report_pipe,
+        has_error,
     };
     thread.entry();
 }
@@ -95,14 +202,24 @@ struct JuliaThread {
     global_params: GlobalParameters,
     dyn_data: Vec<IOData>,
     notes: NoteTracker,
+    /// New code which has to wait for the previous new code to finish being swapped in.
+    queued_code: Option<(GeneratedCode, bool, Vec<IOData>)>,
     render_pipe: Receiver<RenderRequest>,
     poll_pipe: Receiver<()>,
     audio_response_pipe: Sender<AudioResponse>,
     error_report_pipe: Sender<String>,
+    /// True if a recoverable error was reported since new code last loaded successfully.
+    has_error: bool,
 }
 
 impl JuliaThread {
+    /// Recoverable errors stay visible while the thread keeps rendering, otherwise they would be
+    /// replaced by the next status in a few milliseconds.
     fn set_status(&self, status: Status) {
+        let status = match status {
+            Status::Ready | Status::Rendering if self.has_error => Status::RecoverableError,
+            _ => status,
+        };
         self.comms.julia_thread_status.store(status);
     }
 
@@ -112,7 +229,7 @@ impl JuliaThread {
             crossbeam_channel::select! {
                 recv(self.render_pipe) -> msg => {
                     if let Ok(request) = msg {
-                        self.render(request.data, request.do_feedback);
+                        self.render(request);
                     } else {
                         break;
                     }
@@ -123,7 +240,10 @@ impl JuliaThread {
                     }
                     self.poll_comms();
                 }
+                default(STAGED_CODE_POLL_INTERVAL) => (),
             }
+            self.check_staged_code();
+            self.stage_queued_code();
             self.set_status(Status::Ready);
         }
         self.set_status(Status::Error);
@@ -131,40 +251,98 @@ impl JuliaThread {
 
     fn report_julia_error(&mut self, message: String) {
         self.error_report_pipe.send(message).unwrap();
-        self.set_status(Status::Error);
+        self.has_error = true;
+        self.set_status(Status::RecoverableError);
+    }
+
+    /// The executor keeps running whatever code it had before, so this error is "recoverable".
+    fn report_code_error(&mut self, err: String) {
+        let message = format!(
+            "Failed to load new patch code, see message log for details.\n\n{}",
+            err
+        );
+        eprintln!("{}", err);
+        self.report_julia_error(message);
     }
 
+    /// Swaps in code that was compiling in the background if it is ready.
+    fn check_staged_code(&mut self) {
+        let res = self
+            .executor
+            .poll_staged_code(&mut self.notes, &mut self.dyn_data);
+        match res {
+            Ok(true) => self.has_error = false,
+            Ok(false) => (),
+            Err(err) => self.report_code_error(err),
+        }
+    }
+
+    /// Starts compiling the most recent new code once any previous code is done being swapped in.
+    fn stage_queued_code(&mut self) {
+        if self.executor.is_swapping_code() {
+            return;
+        }
+        if let Some((code, has_effects, dyn_data)) = self.queued_code.take() {
+            // The current code keeps playing until the new code is compiled.
+            let res = self
+                .executor
+                .stage_generated_code(code, has_effects, dyn_data);
+            if let Err(err) = res {
+                self.report_code_error(err);
+            }
+        }
+    }
+
+    /// Handles everything that was sent since the last poll. Each message on the poll pipe only
+    /// means that something changed, so several changes can be picked up by a single message.
     fn poll_comms(&mut self) {
         if let Some(_) = self.comms.new_global_params.take() {
             self.set_status(Status::Busy);
+            // The parameters change every piece of code, so get the new code in first.
+            let was_staging = self.executor.is_staging_code();
+            let res = self
+                .executor
+                .finish_swapping_code(&mut self.notes, &mut self.dyn_data);
+            match res {
+                Ok(()) if was_staging => self.has_error = false,
+                Ok(()) => (),
+                Err(err) => self.report_code_error(err),
+            }
             let params = self.comms.global_params.load();
             let result = self.executor.change_parameters(&params);
+            // Audio has to be rendered in the new format regardless of whether this worked.
+            self.global_params = params;
             if let Err(err) = result {
                 let message = format!(
                     "Failed to load new parameter code, see message log for details.\n\n{}",
                     err
                 );
+                eprintln!("{}", err);
+                // This error is "recoverable", the patch will be silent until new code is loaded.
                 self.report_julia_error(message);
-                panic!("Unrecoverable error.");
             }
-            self.global_params = params;
             self.preheat();
-        } else if let Some((code, dyn_data)) = self.comms.new_note_graph_code.take() {
-            self.set_status(Status::Busy);
-            self.notes.silence_all();
-            self.dyn_data = dyn_data;
-            let res = self.executor.change_generated_code(code);
-            if let Err(err) = res {
-                let message = format!(
-                    "Failed to load new patch code, see message log for details.\n\n{}",
-                    err
-                );
-                self.report_julia_error(message);
-                panic!("Unrecoverable error.");
+        }
+        if let Some(new_code) = self.comms.new_note_graph_code.take() {
+            // If several changes arrive while compiling, only the most recent one matters.
+            self.queued_code = Some(new_code);
+            self.stage_queued_code();
+        }
+        if let Some(data) = self.comms.new_dyn_data.take() {
+            // Dynamic data always belongs to the most recent code.
+            if let Some((_, _, queued_data)) = &mut self.queued_code {
+                *queued_data = data;
+            } else if self.executor.is_staging_code() {
+                self.executor.set_staged_dyn_data(data);
+            } else {
+                self.dyn_data = data;
             }
-            self.preheat();
-        } else if let Some(data) = self.comms.new_dyn_data.take() {
-            self.dyn_data = data;
+        }
+        if let Some(settings) = self.comms.new_voice_settings.take() {
+            self.notes.set_voice_settings(settings);
+        }
+        if let Some(frequencies) = self.comms.new_key_frequencies.take() {
+            self.notes.set_key_frequencies(frequencies);
         }
     }
 
@@ -181,32 +359,30 @@ impl JuliaThread {
         }
     }
 
-    fn render(&mut self, global_data: GlobalData, do_feedback: bool) {
+    fn render(&mut self, mut request: RenderRequest) {
         self.set_status(Status::Rendering);
         let view_index = self.comms.module_view_index.load();
-        let mut nel = self.comms.note_events.lock().unwrap();
-        let note_events = std::mem::take(&mut *nel);
-        drop(nel);
         self.notes.set_dummy_note_active(
             self.comms.do_dummy_note.load() || self.comms.do_dummy_note_once.load(),
         );
-        for event in note_events {
-            match event {
-                NoteEvent::StartNote { index, velocity } => self.notes.start_note(index, velocity),
-                NoteEvent::ReleaseNote { index } => self.notes.release_note(index),
-            }
+        let buffer_length = self.global_params.buffer_length;
+        for event in request.note_events.drain(..) {
+            self.notes.apply_event(event, buffer_length);
         }
 
-        let mut output = vec![0.0; self.global_params.channels * self.global_params.buffer_length];
+        request.audio.resize(
+            self.global_params.channels * self.global_params.buffer_length,
+            0.0,
+        );
         let result = self.executor.execute(
-            do_feedback,
+            request.do_feedback,
             view_index,
-            &global_data,
+            &request.data,
             &mut self.notes,
             &self.dyn_data[..],
-            &mut output[..],
+            &mut request.audio[..],
         );
-        let new_feedback_data = match result {
+        let has_feedback = match result {
             Ok(v) => v,
             Err(err) => {
                 let message = format!(
@@ -216,15 +392,20 @@ impl JuliaThread {
                 eprintln!("{}", err);
                 self.report_julia_error(message);
                 // This error is "recoverable"
-                None
+                false
             }
         };
-        if new_feedback_data.is_some() {
+        if has_feedback {
             self.comms.do_dummy_note_once.store(false);
-            self.comms.new_feedback.store(new_feedback_data);
+            let spare = self.comms.spare_feedback.take().unwrap_or_default();
+            let feedback = self.executor.replace_feedback(spare);
+            // Data the GUI has not displayed yet is out of date, so its vectors can be reused.
+            if let Some(old) = self.comms.new_feedback.swap(Some(feedback)) {
+                self.comms.spare_feedback.store(Some(old));
+            }
         }
         self.audio_response_pipe
-            .send(AudioResponse { audio: output })
+            .send(request.into_response())
             .unwrap();
     }
 }
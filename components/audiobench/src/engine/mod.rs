@@ -3,8 +3,10 @@ mod codegen;
 pub mod controls;
 pub mod data_transfer;
 mod julia_thread;
+mod offline;
 pub mod parts;
 mod program_wrapper;
 
 pub use base::*;
-pub use julia_thread::Status;
+pub use julia_thread::{NoteEvent, NoteExpression, Pedal, Status};
+pub use offline::*;
@@ -0,0 +1,393 @@
+use crate::{
+    engine::{
+        base::MAX_CHANNELS,
+        codegen::{self, CodeGenResult},
+        data_transfer::{GlobalData, GlobalParameters, IOData},
+        julia_thread::NoteEvent,
+        parts::ModuleGraph,
+        program_wrapper::{AudiobenchExecutor, NoteTracker},
+    },
+    registry::{save_data::Patch, Registry},
+};
+use std::{io::Write, path::Path};
+
+/// A note event which should happen a certain number of seconds after an offline render starts.
+#[derive(Clone, Copy, Debug)]
+pub struct TimedNoteEvent {
+    pub time: f32,
+    pub event: NoteEvent,
+}
+
+/// How samples should be encoded when writing a WAV file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WavFormat {
+    Int16,
+    Int24,
+    Float32,
+}
+
+impl WavFormat {
+    fn bits_per_sample(self) -> u16 {
+        match self {
+            Self::Int16 => 16,
+            Self::Int24 => 24,
+            Self::Float32 => 32,
+        }
+    }
+
+    fn format_tag(self) -> u16 {
+        match self {
+            // WAVE_FORMAT_PCM
+            Self::Int16 | Self::Int24 => 0x0001,
+            // WAVE_FORMAT_IEEE_FLOAT
+            Self::Float32 => 0x0003,
+        }
+    }
+
+    /// Integer samples are clipped to the range they can represent. Float samples are written as
+    /// they are, so audio louder than full scale can still be turned down after rendering.
+    fn write_sample(self, sample: f32, to: &mut Vec<u8>) {
+        match self {
+            Self::Int16 => {
+                let value = (sample.max(-1.0).min(1.0) * std::i16::MAX as f32) as i16;
+                to.extend_from_slice(&value.to_le_bytes());
+            }
+            Self::Int24 => {
+                let value = (sample.max(-1.0).min(1.0) * 0x7F_FFFF as f32) as i32;
+                to.extend_from_slice(&value.to_le_bytes()[..3]);
+            }
+            Self::Float32 => to.extend_from_slice(&sample.to_le_bytes()),
+        }
+    }
+}
+
+/// Renders patches as fast as the Julia executor allows instead of in real time. This drives the
+/// executor directly on the calling thread, so it cannot be used while an `Instance` exists in
+/// the same process (Julia can only be initialized once.)
+pub struct OfflineRenderer {
+    registry: Registry,
+    executor: AudiobenchExecutor,
+    params: GlobalParameters,
+    notes: NoteTracker,
+    global_data: GlobalData,
+    dyn_data: Vec<IOData>,
+    /// The most recently rendered buffer, of which the first `buffer_read` samples per channel
+    /// have been copied to the output.
+    buffer: Vec<f32>,
+    buffer_read: usize,
+    /// How many buffers of the loaded patch have been rendered.
+    num_buffers: usize,
+}
+
+impl OfflineRenderer {
+    /// `channels` is clamped and laid out the same way as in `AudioThreadEngine::set_global_params`.
+    pub fn new(channels: usize, buffer_length: usize, sample_rate: usize) -> Result<Self, String> {
+        let registry = Registry::new()?;
+        let params = GlobalParameters {
+            channels: channels.max(1).min(MAX_CHANNELS),
+            buffer_length,
+            sample_rate,
+        };
+        let registry_source = codegen::generate_registry_code(&registry)?;
+        let executor = AudiobenchExecutor::new(registry_source, &params)?;
+        Ok(Self {
+            registry,
+            executor,
+            notes: NoteTracker::new(),
+            global_data: GlobalData::new(),
+            dyn_data: Vec::new(),
+            buffer: vec![0.0; params.channels * buffer_length],
+            buffer_read: buffer_length,
+            num_buffers: 0,
+            params,
+        })
+    }
+
+    pub fn get_channels(&self) -> usize {
+        self.params.channels
+    }
+
+    pub fn get_sample_rate(&self) -> usize {
+        self.params.sample_rate
+    }
+
+    /// Renders `duration` seconds of the patch stored in `patch_data` (in the same format used by
+    /// the clipboard and the plugin state) while playing the specified note events. Returns
+    /// interleaved audio containing `get_channels()` channels.
+    pub fn render(
+        &mut self,
+        patch_data: &[u8],
+        events: &[TimedNoteEvent],
+        duration: f32,
+    ) -> Result<Vec<f32>, String> {
+        self.load_patch(patch_data)?;
+        let mut events = Vec::from(events);
+        events.sort_by(|a, b| a.time.total_cmp(&b.time));
+        let total_samples = (duration * self.params.sample_rate as f32).ceil().max(0.0) as usize;
+        let mut output = vec![0.0; total_samples * self.params.channels];
+        self.render_into(&events[..], &mut output[..])?;
+        Ok(output)
+    }
+
+    /// Compiles the patch stored in `patch_data` and starts rendering it from the beginning, with
+    /// no notes playing.
+    pub fn load_patch(&mut self, patch_data: &[u8]) -> Result<(), String> {
+        let patch = Patch::load_readable("offline".to_owned(), patch_data)?;
+        let mut graph = ModuleGraph::new();
+        patch
+            .restore_note_graph(&mut graph, &self.registry)
+            .map_err(|_| "ERROR: Patch data is corrupt.".to_owned())?;
+        let CodeGenResult {
+            code,
+            has_effects,
+            dyn_data_collector,
+            ..
+        } = codegen::generate_code(&graph, &self.params, &[]).map_err(|_| {
+            "ERROR: Module graph contains connections to missing modules.".to_owned()
+        })?;
+        self.dyn_data = dyn_data_collector.collect();
+        self.executor.change_generated_code(code, has_effects)?;
+
+        self.notes = NoteTracker::new();
+        self.notes.set_voice_settings(graph.voice_settings.clone());
+        self.notes
+            .set_key_frequencies(graph.tuning.key_frequencies(&self.registry)?);
+        self.global_data = GlobalData::new();
+        // There is no host to automate them, so host parameters keep the values saved in the patch.
+        self.global_data.host_parameters = graph.host_parameter_values();
+        self.buffer_read = self.params.buffer_length;
+        self.num_buffers = 0;
+        Ok(())
+    }
+
+    /// Continues rendering the loaded patch, filling `output` with interleaved audio containing
+    /// `get_channels()` channels. `events` are timed from when the patch was loaded and have to be
+    /// sorted by time. Each event happens in the buffer its time falls in, events in buffers which
+    /// were already rendered are ignored. Apart from what the patch itself does, this does not
+    /// allocate.
+    pub fn render_into(
+        &mut self,
+        events: &[TimedNoteEvent],
+        output: &mut [f32],
+    ) -> Result<(), String> {
+        let channels = self.params.channels;
+        let buffer_length = self.params.buffer_length;
+        assert!(output.len() % channels == 0);
+        let mut written = 0;
+        while written < output.len() {
+            if self.buffer_read == buffer_length {
+                self.render_buffer(events)?;
+                self.buffer_read = 0;
+            }
+            let frames =
+                (buffer_length - self.buffer_read).min((output.len() - written) / channels);
+            let start = self.buffer_read * channels;
+            let samples = &self.buffer[start..start + frames * channels];
+            output[written..written + samples.len()].copy_from_slice(samples);
+            written += samples.len();
+            self.buffer_read += frames;
+        }
+        Ok(())
+    }
+
+    fn render_buffer(&mut self, events: &[TimedNoteEvent]) -> Result<(), String> {
+        let sample_rate = self.params.sample_rate as f32;
+        let buffer_length = self.params.buffer_length;
+        let buffer_start = self.num_buffers * buffer_length;
+        let event_sample = |event: &TimedNoteEvent| (event.time.max(0.0) * sample_rate) as usize;
+        let first_event = events.partition_point(|event| event_sample(event) < buffer_start);
+        for event in &events[first_event..] {
+            let offset = event_sample(event) - buffer_start;
+            if offset >= buffer_length {
+                break;
+            }
+            let event = match event.event {
+                NoteEvent::StartNote {
+                    index, velocity, ..
+                } => NoteEvent::StartNote {
+                    index,
+                    velocity,
+                    offset,
+                },
+                NoteEvent::ReleaseNote {
+                    index, velocity, ..
+                } => NoteEvent::ReleaseNote {
+                    index,
+                    velocity,
+                    offset,
+                },
+                NoteEvent::Pedal { pedal, down, .. } => NoteEvent::Pedal {
+                    pedal,
+                    down,
+                    offset,
+                },
+                // Expression changes are only applied at buffer boundaries.
+                expression @ NoteEvent::Expression { .. } => expression,
+            };
+            self.notes.apply_event(event, buffer_length);
+        }
+        self.executor.execute(
+            false,
+            0,
+            &self.global_data,
+            &mut self.notes,
+            &self.dyn_data[..],
+            &mut self.buffer[..],
+        )?;
+        self.global_data.advance(buffer_length as f32 / sample_rate);
+        self.num_buffers += 1;
+        Ok(())
+    }
+}
+
+/// Encodes interleaved audio as the contents of a WAV file using the specified sample format.
+fn encode_wav(
+    audio: &[f32],
+    channels: usize,
+    sample_rate: usize,
+    format: WavFormat,
+) -> Result<Vec<u8>, String> {
+    let bytes_per_sample = (format.bits_per_sample() / 8) as usize;
+    let data_len = audio.len() * bytes_per_sample;
+    let is_float = format == WavFormat::Float32;
+    // Non-PCM formats are supposed to include a fact chunk and an extension size in the fmt chunk.
+    let fmt_len: usize = if is_float { 18 } else { 16 };
+    let fact_len: usize = if is_float { 12 } else { 0 };
+    // Chunks have to start at even offsets, so an odd amount of 24 bit samples needs padding.
+    let padding = data_len % 2;
+    let riff_len = 4 + (8 + fmt_len) + fact_len + (8 + data_len + padding);
+    // Every other size in the header is smaller than this one.
+    if riff_len > std::u32::MAX as usize {
+        return Err(format!(
+            "ERROR: The audio is too long to fit in a WAV file ({} bytes of sample data.)",
+            data_len
+        ));
+    }
+
+    let mut contents = Vec::with_capacity(8 + riff_len);
+    contents.extend_from_slice(b"RIFF");
+    contents.extend_from_slice(&(riff_len as u32).to_le_bytes());
+    contents.extend_from_slice(b"WAVE");
+
+    contents.extend_from_slice(b"fmt ");
+    contents.extend_from_slice(&(fmt_len as u32).to_le_bytes());
+    contents.extend_from_slice(&format.format_tag().to_le_bytes());
+    contents.extend_from_slice(&(channels as u16).to_le_bytes());
+    contents.extend_from_slice(&(sample_rate as u32).to_le_bytes());
+    let block_align = channels * bytes_per_sample;
+    contents.extend_from_slice(&((sample_rate * block_align) as u32).to_le_bytes());
+    contents.extend_from_slice(&(block_align as u16).to_le_bytes());
+    contents.extend_from_slice(&format.bits_per_sample().to_le_bytes());
+    if is_float {
+        contents.extend_from_slice(&0u16.to_le_bytes());
+        contents.extend_from_slice(b"fact");
+        contents.extend_from_slice(&4u32.to_le_bytes());
+        let frames = audio.len() / channels.max(1);
+        contents.extend_from_slice(&(frames as u32).to_le_bytes());
+    }
+
+    contents.extend_from_slice(b"data");
+    contents.extend_from_slice(&(data_len as u32).to_le_bytes());
+    for sample in audio {
+        format.write_sample(*sample, &mut contents);
+    }
+    if padding != 0 {
+        contents.push(0);
+    }
+    Ok(contents)
+}
+
+/// Writes interleaved audio to a WAV file using the specified sample format.
+pub fn write_wav(
+    path: &Path,
+    audio: &[f32],
+    channels: usize,
+    sample_rate: usize,
+    format: WavFormat,
+) -> Result<(), String> {
+    let contents = encode_wav(audio, channels, sample_rate, format)?;
+    let file = std::fs::File::create(path)
+        .map_err(|err| format!("ERROR: Failed to create {:?}, caused by:\n{}", path, err))?;
+    let mut writer = std::io::BufWriter::new(file);
+    writer
+        .write_all(&contents[..])
+        .map_err(|err| format!("ERROR: Failed to write to {:?}, caused by:\n{}", path, err))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at(data: &[u8], index: usize) -> u16 {
+        u16::from_le_bytes([data[index], data[index + 1]])
+    }
+
+    fn u32_at(data: &[u8], index: usize) -> u32 {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&data[index..index + 4]);
+        u32::from_le_bytes(bytes)
+    }
+
+    #[test]
+    fn pcm_header() {
+        let audio = [0.0, 1.0, -1.0, 0.5, 0.25, -0.25];
+        let data = encode_wav(&audio, 2, 44100, WavFormat::Int16).unwrap();
+        assert_eq!(data.len(), 44 + 12);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32_at(&data, 4) as usize, data.len() - 8);
+        assert_eq!(&data[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(&data, 16), 16);
+        assert_eq!(u16_at(&data, 20), 1);
+        assert_eq!(u16_at(&data, 22), 2);
+        assert_eq!(u32_at(&data, 24), 44100);
+        assert_eq!(u32_at(&data, 28), 44100 * 4);
+        assert_eq!(u16_at(&data, 32), 4);
+        assert_eq!(u16_at(&data, 34), 16);
+        assert_eq!(&data[36..40], b"data");
+        assert_eq!(u32_at(&data, 40), 12);
+        assert_eq!(u16_at(&data, 46), std::i16::MAX as u16);
+    }
+
+    #[test]
+    fn int24_data_length() {
+        let audio = [0.0; 5];
+        let data = encode_wav(&audio, 1, 48000, WavFormat::Int24).unwrap();
+        // The data chunk is followed by a padding byte which is not part of its size.
+        assert_eq!(data.len(), 44 + 15 + 1);
+        assert_eq!(u32_at(&data, 4) as usize, data.len() - 8);
+        assert_eq!(u16_at(&data, 32), 3);
+        assert_eq!(u32_at(&data, 40), 15);
+    }
+
+    #[test]
+    fn float_header() {
+        let audio = [0.5; 8];
+        let data = encode_wav(&audio, 4, 48000, WavFormat::Float32).unwrap();
+        assert_eq!(data.len(), 58 + 32);
+        assert_eq!(u32_at(&data, 4) as usize, data.len() - 8);
+        assert_eq!(u32_at(&data, 16), 18);
+        assert_eq!(u16_at(&data, 20), 3);
+        assert_eq!(u16_at(&data, 22), 4);
+        assert_eq!(u16_at(&data, 34), 32);
+        assert_eq!(u16_at(&data, 36), 0);
+        assert_eq!(&data[38..42], b"fact");
+        assert_eq!(u32_at(&data, 42), 4);
+        // The fact chunk holds the number of frames, not samples.
+        assert_eq!(u32_at(&data, 46), 2);
+        assert_eq!(&data[50..54], b"data");
+        assert_eq!(u32_at(&data, 54), 32);
+        assert_eq!(&data[58..62], &0.5f32.to_le_bytes());
+    }
+
+    #[test]
+    fn only_integer_samples_are_clipped() {
+        let audio = [1.5, -2.0];
+        let data = encode_wav(&audio, 2, 44100, WavFormat::Int16).unwrap();
+        assert_eq!(u16_at(&data, 44), std::i16::MAX as u16);
+        assert_eq!(u16_at(&data, 46), -std::i16::MAX as u16);
+        let data = encode_wav(&audio, 2, 44100, WavFormat::Float32).unwrap();
+        assert_eq!(&data[58..62], &1.5f32.to_le_bytes());
+        assert_eq!(&data[62..66], &(-2.0f32).to_le_bytes());
+    }
+}
@@ -1,10 +1,22 @@
 use crate::{
-    engine::controls::AnyControl,
-    gui::top_level::graph::ModuleGraph as ModuleGraphWidget,
-    registry::{module_template::ModuleTemplate, yaml::YamlNode},
+    engine::{
+        controls::{AnyControl, FloatInRangeControl},
+        data_transfer::NUM_HOST_PARAMETERS,
+    },
+    gui::{constants::coord, top_level::graph::ModuleGraph as ModuleGraphWidget},
+    registry::{
+        module_template::ModuleTemplate,
+        tuning::{KeyFrequencies, KeyboardMapping},
+        yaml::YamlNode,
+        Registry,
+    },
 };
-use shared_util::prelude::*;
-use std::collections::HashSet;
+use scui::Vec2D;
+use shared_util::{
+    mini_serde::{MiniDes, MiniSer},
+    prelude::*,
+};
+use std::collections::{HashMap, HashSet};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum JackType {
@@ -95,11 +107,92 @@ impl IOJack {
     }
 }
 
+/// A module which contains other modules. Chosen controls and outputs of the modules inside it
+/// are shown on its face, so that it can be used like any other module.
+#[derive(Debug, Default)]
+pub struct ModuleGroup {
+    pub name: String,
+    pub modules: Vec<Rcrc<Module>>,
+    /// Controls shown on the face of the group, identified by which module in the group they
+    /// belong to and their index in that module.
+    pub exposed_controls: Vec<(Rcrc<Module>, usize)>,
+    /// The outputs of the group, identified by which module in the group produces them and
+    /// their index in that module.
+    pub exposed_outputs: Vec<(Rcrc<Module>, usize)>,
+}
+
+impl ModuleGroup {
+    fn contains(&self, module: &Rcrc<Module>) -> bool {
+        self.modules.iter().any(|other| Rc::ptr_eq(other, module))
+    }
+
+    /// Creates the template and controls for the face of this group. Exposed widgets keep their
+    /// layout from the module they come from, stacked in the order the modules are in.
+    fn create_face(&self) -> (ModuleTemplate, Vec<AnyControl>) {
+        let mut default_controls = Vec::new();
+        let mut widget_outlines = Vec::new();
+        let mut size = (2, 0);
+        for module in &self.modules {
+            let module_ref = module.borrow();
+            let template = module_ref.template.borrow();
+            let mut new_indexes = HashMap::new();
+            for (exposed, index) in &self.exposed_controls {
+                if Rc::ptr_eq(exposed, module) {
+                    new_indexes.insert(*index, default_controls.len());
+                    let name = template.default_controls[*index].0.clone();
+                    default_controls.push((name, module_ref.controls[*index].clone()));
+                }
+            }
+            if new_indexes.len() == 0 {
+                continue;
+            }
+            let offset = Vec2D::new(0.0, coord(size.1) - coord(0));
+            for outline in &template.widget_outlines {
+                let remap = |index: usize| new_indexes.get(&index).cloned();
+                if let Some(outline) = outline.remapped(&remap, offset) {
+                    widget_outlines.push(outline);
+                }
+            }
+            size.0 = size.0.max(template.size.0);
+            size.1 += template.size.1;
+        }
+        let outputs: Vec<_> = self
+            .exposed_outputs
+            .iter()
+            .map(|(module, index)| module.borrow().template.borrow().outputs[*index].clone())
+            .collect();
+        size.1 = size.1.max(outputs.len() as i32).max(1);
+        let controls = default_controls.imc(|(_, control)| control.clone());
+        let template = ModuleTemplate {
+            lib_name: "User".to_owned(),
+            module_name: self.name.clone(),
+            save_id: 0,
+            effects_only: false,
+
+            label: self.name.clone(),
+            category: "Groups".to_owned(),
+            tooltip: format!(
+                "A group of {} modules, double-click it to see what is inside",
+                self.modules.len()
+            ),
+            size,
+            widget_outlines,
+
+            default_controls,
+            outputs,
+        };
+        (template, controls)
+    }
+}
+
 #[derive(Debug)]
 pub struct Module {
     pub template: Rcrc<ModuleTemplate>,
     pub controls: Vec<AnyControl>,
     pub pos: (f32, f32),
+    /// Present if this module is a group of other modules, in which case its template and
+    /// controls describe the face of the group. The controls are shared with the modules inside.
+    pub group: Option<ModuleGroup>,
 }
 
 impl Module {
@@ -112,13 +205,54 @@ impl Module {
             template,
             controls,
             pos: (0.0, 0.0),
+            group: None,
+        }
+    }
+
+    pub fn create_group(group: ModuleGroup, pos: (f32, f32)) -> Self {
+        let (template, controls) = group.create_face();
+        Self {
+            template: rcrc(template),
+            controls,
+            pos,
+            group: Some(group),
+        }
+    }
+
+    /// Call this after changing what a group contains or exposes.
+    pub fn rebuild_group_face(&mut self) {
+        let (template, controls) = self.group.as_ref().unwrap().create_face();
+        self.template = rcrc(template);
+        self.controls = controls;
+    }
+
+    /// Returns the module that actually produces the specified output, following the outputs of
+    /// groups to the module inside them that they come from.
+    pub fn resolve_output(module: &Rcrc<Module>, output_index: usize) -> (Rcrc<Module>, usize) {
+        let mut current = (Rc::clone(module), output_index);
+        loop {
+            let next = match &current.0.borrow().group {
+                Some(group) => {
+                    let (module, index) = &group.exposed_outputs[current.1];
+                    (Rc::clone(module), *index)
+                }
+                None => break,
+            };
+            current = next;
         }
+        current
     }
 
     /// Removes all inputs and controls. Use this before removing a module to avoid memory leaks.
     /// It is still required to manually remove references to this module that exist in other
     /// modules.
     pub fn sever(&mut self) {
+        if let Some(group) = &mut self.group {
+            for module in &group.modules {
+                module.borrow_mut().sever();
+            }
+            *group = ModuleGroup::default();
+        }
         for control in &self.controls {
             let control_ptr = control.as_dyn_ptr();
             let mut control = control_ptr.borrow_mut();
@@ -152,8 +286,258 @@ impl Module {
     }
 }
 
+/// Decides which voice gets cut off when a note is started while the patch is already using its
+/// maximum number of voices.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StealingPolicy {
+    Oldest,
+    Quietest,
+    Lowest,
+    Highest,
+    ReleaseFirst,
+}
+
+impl StealingPolicy {
+    pub const ALL: [StealingPolicy; 5] = [
+        Self::Oldest,
+        Self::Quietest,
+        Self::Lowest,
+        Self::Highest,
+        Self::ReleaseFirst,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Oldest => "Oldest",
+            Self::Quietest => "Quietest",
+            Self::Lowest => "Lowest",
+            Self::Highest => "Highest",
+            Self::ReleaseFirst => "Release First",
+        }
+    }
+
+    fn ordinal(&self) -> u8 {
+        Self::ALL.iter().position(|p| p == self).unwrap() as u8
+    }
+}
+
+/// Decides which of the held keys a monophonic patch plays.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NotePriority {
+    Last,
+    Low,
+    High,
+}
+
+impl NotePriority {
+    pub const ALL: [NotePriority; 3] = [Self::Last, Self::Low, Self::High];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Last => "Last",
+            Self::Low => "Lowest",
+            Self::High => "Highest",
+        }
+    }
+
+    fn ordinal(&self) -> u8 {
+        Self::ALL.iter().position(|p| p == self).unwrap() as u8
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VoiceSettings {
+    /// How many voices (held or decaying) can play at once.
+    pub max_voices: usize,
+    pub stealing_policy: StealingPolicy,
+    /// If true, only one note plays at a time no matter how many keys are held.
+    pub mono: bool,
+    pub note_priority: NotePriority,
+    /// If true, changing notes in mono mode changes the pitch without firing the start trigger.
+    pub legato: bool,
+    /// How many seconds it takes to slide from one pitch to the next in mono mode.
+    pub glide_time: f32,
+    /// How many voices each key plays at once.
+    pub unison_voices: usize,
+    /// How many cents apart the highest and lowest unison voices are tuned.
+    pub unison_detune: f32,
+    /// How far apart the unison voices are panned, from 0 (all centered) to 1 (hard left to hard
+    /// right.)
+    pub unison_spread: f32,
+    /// If true, each unison voice starts at a random point in its waveform instead of all of them
+    /// starting in phase.
+    pub unison_random_phase: bool,
+}
+
+impl Default for VoiceSettings {
+    fn default() -> Self {
+        Self {
+            max_voices: Self::MAX_VOICES,
+            stealing_policy: StealingPolicy::Oldest,
+            mono: false,
+            note_priority: NotePriority::Last,
+            legato: false,
+            glide_time: 0.0,
+            unison_voices: 1,
+            unison_detune: 20.0,
+            unison_spread: 0.5,
+            unison_random_phase: true,
+        }
+    }
+}
+
+impl VoiceSettings {
+    pub const MAX_VOICES: usize = 128;
+    pub const MAX_GLIDE_TIME: f32 = 10.0;
+    pub const MAX_UNISON_VOICES: usize = 16;
+    pub const MAX_UNISON_DETUNE: f32 = 100.0;
+
+    pub fn serialize_polyphony(&self, ser: &mut MiniSer) {
+        ser.note("max_voices ");
+        ser.u8((self.max_voices - 1) as u8);
+        ser.note("policy ");
+        ser.u3(self.stealing_policy.ordinal());
+    }
+
+    pub fn deserialize_polyphony(&mut self, des: &mut MiniDes) -> Result<(), ()> {
+        let max_voices = des.u8()? as usize + 1;
+        if max_voices > Self::MAX_VOICES {
+            return Err(());
+        }
+        self.max_voices = max_voices;
+        self.stealing_policy = *StealingPolicy::ALL.get(des.u3()? as usize).ok_or(())?;
+        Ok(())
+    }
+
+    pub fn serialize_mono(&self, ser: &mut MiniSer) {
+        ser.note("mono ");
+        ser.bool(self.mono);
+        ser.note("priority ");
+        ser.u2(self.note_priority.ordinal());
+        ser.note("legato ");
+        ser.bool(self.legato);
+        ser.note("glide ");
+        ser.f32(self.glide_time);
+    }
+
+    pub fn deserialize_mono(&mut self, des: &mut MiniDes) -> Result<(), ()> {
+        self.mono = des.bool()?;
+        self.note_priority = *NotePriority::ALL.get(des.u2()? as usize).ok_or(())?;
+        self.legato = des.bool()?;
+        let glide_time = des.f32()?;
+        if !(glide_time >= 0.0 && glide_time <= Self::MAX_GLIDE_TIME) {
+            return Err(());
+        }
+        self.glide_time = glide_time;
+        Ok(())
+    }
+
+    pub fn serialize_unison(&self, ser: &mut MiniSer) {
+        ser.note("voices ");
+        ser.u4((self.unison_voices - 1) as u8);
+        ser.note("detune ");
+        ser.f32(self.unison_detune);
+        ser.note("spread ");
+        ser.f32(self.unison_spread);
+        ser.note("random_phase ");
+        ser.bool(self.unison_random_phase);
+    }
+
+    pub fn deserialize_unison(&mut self, des: &mut MiniDes) -> Result<(), ()> {
+        self.unison_voices = des.u4()? as usize + 1;
+        let detune = des.f32()?;
+        if !(detune >= 0.0 && detune <= Self::MAX_UNISON_DETUNE) {
+            return Err(());
+        }
+        self.unison_detune = detune;
+        let spread = des.f32()?;
+        if !(spread >= 0.0 && spread <= 1.0) {
+            return Err(());
+        }
+        self.unison_spread = spread;
+        self.unison_random_phase = des.bool()?;
+        Ok(())
+    }
+}
+
+/// Which Scala files from the registry a patch uses to decide the pitch of each key. None means
+/// twelve tone equal temperament for the scale and the default Scala mapping for the keyboard
+/// mapping.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TuningSettings {
+    pub scale: Option<String>,
+    pub keyboard_mapping: Option<String>,
+}
+
+impl TuningSettings {
+    pub fn key_frequencies(&self, registry: &Registry) -> Result<KeyFrequencies, String> {
+        let scale = if let Some(name) = &self.scale {
+            registry
+                .borrow_scale(name)
+                .ok_or_else(|| format!("ERROR: Could not find the scale {}.", name))?
+        } else if self.keyboard_mapping.is_none() {
+            return Ok(KeyFrequencies::default());
+        } else {
+            return Err("ERROR: A keyboard mapping cannot be used without a scale.".to_owned());
+        };
+        let default_mapping = KeyboardMapping::default();
+        let mapping = if let Some(name) = &self.keyboard_mapping {
+            registry
+                .borrow_keyboard_mapping(name)
+                .ok_or_else(|| format!("ERROR: Could not find the keyboard mapping {}.", name))?
+        } else {
+            &default_mapping
+        };
+        KeyFrequencies::new(scale, mapping)
+    }
+
+    pub fn serialize(&self, ser: &mut MiniSer) {
+        for name in &[&self.scale, &self.keyboard_mapping] {
+            if let Some(name) = name {
+                ser.bool(true);
+                ser.str(name);
+            } else {
+                ser.bool(false);
+            }
+        }
+    }
+
+    pub fn deserialize(&mut self, des: &mut MiniDes) -> Result<(), ()> {
+        self.scale = if des.bool()? { Some(des.str()?) } else { None };
+        self.keyboard_mapping = if des.bool()? { Some(des.str()?) } else { None };
+        Ok(())
+    }
+}
+
+/// A knob that belongs to the patch instead of a module. It can be connected to any number of
+/// knobs and sliders, which it then moves through their own ranges.
+#[derive(Debug)]
+pub struct Macro {
+    pub name: String,
+    /// Always ranges from 0 to 1.
+    pub control: Rcrc<FloatInRangeControl>,
+}
+
+impl Macro {
+    pub const NUM_MACROS: usize = 8;
+
+    pub fn new(index: usize) -> Self {
+        Self {
+            name: format!("Macro {}", index + 1),
+            control: rcrc(FloatInRangeControl::new((0.0, 1.0), 0.0, "".to_owned())),
+        }
+    }
+
+    pub fn default_macros() -> Vec<Self> {
+        (0..Self::NUM_MACROS).map(Self::new).collect()
+    }
+}
+
 pub struct ModuleGraph {
     modules: Vec<Rcrc<Module>>,
+    pub voice_settings: VoiceSettings,
+    pub tuning: TuningSettings,
+    pub macros: Vec<Macro>,
     pub current_widget: Option<Rc<ModuleGraphWidget>>,
 }
 
@@ -161,6 +545,9 @@ impl ModuleGraph {
     pub fn new() -> Self {
         Self {
             modules: Vec::new(),
+            voice_settings: Default::default(),
+            tuning: Default::default(),
+            macros: Macro::default_macros(),
             current_widget: None,
         }
     }
@@ -169,28 +556,56 @@ impl ModuleGraph {
         self.modules.push(module);
     }
 
+    /// Adds a module inside the given group, or to the top level of the graph if there is no
+    /// group.
+    pub fn add_module_to(&mut self, group: Option<&Rcrc<Module>>, module: Rcrc<Module>) {
+        if let Some(group) = group {
+            let mut group_ref = group.borrow_mut();
+            group_ref.group.as_mut().unwrap().modules.push(module);
+            drop(group_ref);
+            self.refresh_group_face(group);
+        } else {
+            self.add_module(module);
+        }
+    }
+
     pub fn set_modules(&mut self, modules: Vec<Rcrc<Module>>) {
         self.clear();
         self.modules = modules;
     }
 
-    fn remove_index(&mut self, index: usize) {
-        let module = self.modules.remove(index);
+    pub fn remove_module(&mut self, module: &Rcrc<Module>) {
+        if let Some(group) = self.group_containing(module) {
+            let mut group_ref = group.borrow_mut();
+            let group_data = group_ref.group.as_mut().unwrap();
+            group_data
+                .modules
+                .retain(|other| !Rc::ptr_eq(other, module));
+            group_data
+                .exposed_controls
+                .retain(|(other, _)| !Rc::ptr_eq(other, module));
+            let old_outputs = std::mem::take(&mut group_data.exposed_outputs);
+            let mut new_indexes = Vec::new();
+            for output in old_outputs {
+                if Rc::ptr_eq(&output.0, module) {
+                    new_indexes.push(None);
+                } else {
+                    new_indexes.push(Some(group_data.exposed_outputs.len()));
+                    group_data.exposed_outputs.push(output);
+                }
+            }
+            drop(group_ref);
+            self.refresh_group_face(&group);
+            self.retarget_group_outputs(&group, &new_indexes);
+        } else {
+            self.modules.retain(|other| !Rc::ptr_eq(other, module));
+        }
         module.borrow_mut().sever();
-        for other in &self.modules {
-            other.borrow_mut().sever_connections_from(&module);
+        for other in self.all_modules() {
+            other.borrow_mut().sever_connections_from(module);
         }
     }
 
-    pub fn remove_module(&mut self, module: &Rcrc<Module>) {
-        let index = self
-            .modules
-            .iter()
-            .position(|e| std::ptr::eq(e.as_ref(), module.as_ref()))
-            .unwrap();
-        self.remove_index(index);
-    }
-
     pub fn clear(&mut self) {
         for module in &self.modules {
             module.borrow_mut().sever();
@@ -204,62 +619,745 @@ impl ModuleGraph {
         }
     }
 
+    /// Returns the modules at the top level of the graph, see `flattened_modules` for a list
+    /// containing modules inside groups as well.
     pub fn borrow_modules(&self) -> &[Rcrc<Module>] {
         &self.modules[..]
     }
 
-    pub fn index_of_module(&self, module: &Rcrc<Module>) -> Option<usize> {
-        self.modules
+    fn collect_modules(
+        modules: &[Rcrc<Module>],
+        include_groups: bool,
+        into: &mut Vec<Rcrc<Module>>,
+    ) {
+        for module in modules {
+            if let Some(group) = &module.borrow().group {
+                if include_groups {
+                    into.push(Rc::clone(module));
+                }
+                Self::collect_modules(&group.modules, include_groups, into);
+            } else {
+                into.push(Rc::clone(module));
+            }
+        }
+    }
+
+    /// Returns every module in the graph, including groups and the modules inside them. Groups
+    /// come before the modules inside them.
+    pub fn all_modules(&self) -> Vec<Rcrc<Module>> {
+        let mut result = Vec::new();
+        Self::collect_modules(&self.modules, true, &mut result);
+        result
+    }
+
+    /// Returns every module in the graph which is not a group, including modules inside groups.
+    /// This is the list of modules that actually run.
+    pub fn flattened_modules(&self) -> Vec<Rcrc<Module>> {
+        let mut result = Vec::new();
+        Self::collect_modules(&self.modules, false, &mut result);
+        result
+    }
+
+    /// Every control assigned to a host parameter, along with the parameter's index and the name
+    /// the host should show for it.
+    pub fn host_parameter_controls(&self) -> Vec<(usize, String, Rcrc<FloatInRangeControl>)> {
+        let mut result = Vec::new();
+        for patch_macro in &self.macros {
+            if let Some(parameter) = patch_macro.control.borrow().host_parameter {
+                result.push((
+                    parameter,
+                    patch_macro.name.clone(),
+                    Rc::clone(&patch_macro.control),
+                ));
+            }
+        }
+        for module in self.flattened_modules() {
+            let module = module.borrow();
+            let template = module.template.borrow();
+            for (index, control) in module.controls.iter().enumerate() {
+                if let AnyControl::FloatInRange(control) = control {
+                    if let Some(parameter) = control.borrow().host_parameter {
+                        let name =
+                            format!("{} {}", template.label, template.default_controls[index].0);
+                        result.push((parameter, name, Rc::clone(control)));
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// The value the host would report for each host parameter, which is the normalized value of
+    /// the control assigned to it. Unassigned parameters are zero.
+    pub fn host_parameter_values(&self) -> [f32; NUM_HOST_PARAMETERS] {
+        let mut values = [0.0; NUM_HOST_PARAMETERS];
+        for (index, _, control) in self.host_parameter_controls() {
+            values[index] = control.borrow().get_normalized_value();
+        }
+        values
+    }
+
+    pub fn contains_module(&self, module: &Rcrc<Module>) -> bool {
+        self.all_modules()
+            .iter()
+            .any(|other| Rc::ptr_eq(other, module))
+    }
+
+    /// Returns the group the given module is directly inside of, or None if the module is at the
+    /// top level of the graph.
+    pub fn group_containing(&self, module: &Rcrc<Module>) -> Option<Rcrc<Module>> {
+        self.all_modules().into_iter().find(|group| {
+            if let Some(group) = &group.borrow().group {
+                group.contains(module)
+            } else {
+                false
+            }
+        })
+    }
+
+    /// Changes which output of a group everything connected to it uses, after the outputs of the
+    /// group change. `new_indexes` contains the new index of each old output, or None if that
+    /// output was removed, in which case anything using it is disconnected.
+    fn retarget_group_outputs(&self, group: &Rcrc<Module>, new_indexes: &[Option<usize>]) {
+        for module in self.flattened_modules() {
+            for control in &module.borrow().controls {
+                let control_ptr = control.as_dyn_ptr();
+                let mut control = control_ptr.borrow_mut();
+                let mut condemned = Vec::new();
+                for (index, source) in control
+                    .get_connected_automation_mut()
+                    .into_iter()
+                    .enumerate()
+                {
+                    if !Rc::ptr_eq(&source.module, group) {
+                        continue;
+                    }
+                    if let Some(new_index) = new_indexes[source.output_index] {
+                        source.output_index = new_index;
+                    } else {
+                        condemned.push(index);
+                    }
+                }
+                for index in condemned.into_iter().rev() {
+                    control.remove_automation_by_index(index);
+                }
+            }
+        }
+        // The group containing this one might be using its outputs for its own outputs.
+        if let Some(parent) = self.group_containing(group) {
+            let mut parent_ref = parent.borrow_mut();
+            let parent_data = parent_ref.group.as_mut().unwrap();
+            let old_outputs = std::mem::take(&mut parent_data.exposed_outputs);
+            let mut parent_indexes = Vec::new();
+            for (module, index) in old_outputs {
+                if !Rc::ptr_eq(&module, group) {
+                    parent_indexes.push(Some(parent_data.exposed_outputs.len()));
+                    parent_data.exposed_outputs.push((module, index));
+                } else if let Some(new_index) = new_indexes[index] {
+                    parent_indexes.push(Some(parent_data.exposed_outputs.len()));
+                    parent_data.exposed_outputs.push((module, new_index));
+                } else {
+                    parent_indexes.push(None);
+                }
+            }
+            parent_ref.rebuild_group_face();
+            drop(parent_ref);
+            self.retarget_group_outputs(&parent, &parent_indexes);
+        }
+    }
+
+    /// Shows or hides a control of a module inside a group on the face of that group. Hiding a
+    /// control disconnects anything outside of the group that was connected to it.
+    pub fn toggle_exposed_control(
+        &mut self,
+        group: &Rcrc<Module>,
+        module: &Rcrc<Module>,
+        control_index: usize,
+    ) {
+        let mut group_ref = group.borrow_mut();
+        let group_data = group_ref.group.as_mut().unwrap();
+        let is_this = |(other, index): &(Rcrc<Module>, usize)| {
+            Rc::ptr_eq(other, module) && *index == control_index
+        };
+        if group_data.exposed_controls.iter().any(is_this) {
+            group_data.exposed_controls.retain(|item| !is_this(item));
+            let mut inside = Vec::new();
+            Self::collect_modules(&group_data.modules, true, &mut inside);
+            let control_ptr = module.borrow().controls[control_index].as_dyn_ptr();
+            let mut control = control_ptr.borrow_mut();
+            let num_sources = control.get_connected_automation().len();
+            for index in (0..num_sources).rev() {
+                let source = Rc::clone(&control.get_connected_automation()[index].module);
+                if !inside.iter().any(|other| Rc::ptr_eq(other, &source)) {
+                    control.remove_automation_by_index(index);
+                }
+            }
+        } else {
+            group_data
+                .exposed_controls
+                .push((Rc::clone(module), control_index));
+        }
+        drop(group_ref);
+        self.refresh_group_face(group);
+    }
+
+    /// Rebuilds the face of a group after what it contains or exposes changes. Groups containing
+    /// it refer to its controls by index, so those references are updated as well, dropping any
+    /// to controls which are no longer on its face.
+    fn refresh_group_face(&self, group: &Rcrc<Module>) {
+        let old_controls = group.borrow().controls.clone();
+        group.borrow_mut().rebuild_group_face();
+        let parent = if let Some(parent) = self.group_containing(group) {
+            parent
+        } else {
+            return;
+        };
+        let group_ref = group.borrow();
+        let mut parent_ref = parent.borrow_mut();
+        let exposed = &mut parent_ref.group.as_mut().unwrap().exposed_controls;
+        let old_exposed = std::mem::take(exposed);
+        for (module, index) in old_exposed {
+            if !Rc::ptr_eq(&module, group) {
+                exposed.push((module, index));
+                continue;
+            }
+            let new_index = group_ref
+                .controls
+                .iter()
+                .position(|control| control.is_same_control(&old_controls[index]));
+            if let Some(new_index) = new_index {
+                exposed.push((module, new_index));
+            }
+        }
+        drop(parent_ref);
+        drop(group_ref);
+        self.refresh_group_face(&parent);
+    }
+
+    /// Shows or hides an output of a module inside a group as an output of that group. Hiding an
+    /// output disconnects anything that was using it.
+    pub fn toggle_exposed_output(
+        &mut self,
+        group: &Rcrc<Module>,
+        module: &Rcrc<Module>,
+        output_index: usize,
+    ) {
+        let mut group_ref = group.borrow_mut();
+        let exposed = &mut group_ref.group.as_mut().unwrap().exposed_outputs;
+        let position = exposed
+            .iter()
+            .position(|(other, index)| Rc::ptr_eq(other, module) && *index == output_index);
+        if let Some(position) = position {
+            let new_indexes: Vec<_> = (0..exposed.len())
+                .map(|index| match index {
+                    _ if index < position => Some(index),
+                    _ if index > position => Some(index - 1),
+                    _ => None,
+                })
+                .collect();
+            exposed.remove(position);
+            group_ref.rebuild_group_face();
+            drop(group_ref);
+            self.retarget_group_outputs(group, &new_indexes);
+        } else {
+            exposed.push((Rc::clone(module), output_index));
+            group_ref.rebuild_group_face();
+        }
+    }
+
+    /// Moves the given modules, which must all be directly inside the same group or at the top
+    /// level of the graph, into a new group. Outputs used by modules outside the new group and
+    /// controls connected to modules outside it are exposed on its face so that the patch still
+    /// sounds the same.
+    pub fn group_modules(&mut self, modules: &[Rcrc<Module>], name: String) -> Rcrc<Module> {
+        let is_member = |module: &Rcrc<Module>| modules.iter().any(|m| Rc::ptr_eq(m, module));
+        let mut inside = Vec::new();
+        Self::collect_modules(modules, true, &mut inside);
+        let outside: Vec<_> = self
+            .flattened_modules()
+            .into_iter()
+            .filter(|module| !inside.iter().any(|other| Rc::ptr_eq(other, module)))
+            .collect();
+        let container = self.group_containing(&modules[0]);
+
+        let mut group_data = ModuleGroup {
+            name,
+            modules: modules.to_vec(),
+            exposed_controls: Vec::new(),
+            exposed_outputs: Vec::new(),
+        };
+        let mut used_outputs = Vec::new();
+        for module in &outside {
+            for control in &module.borrow().controls {
+                for source in control.as_dyn_ptr().borrow().get_connected_automation() {
+                    if is_member(&source.module) {
+                        used_outputs.push((Rc::clone(&source.module), source.output_index));
+                    }
+                }
+            }
+        }
+        if let Some(container) = &container {
+            for (module, index) in &container.borrow().group.as_ref().unwrap().exposed_outputs {
+                if is_member(module) {
+                    used_outputs.push((Rc::clone(module), *index));
+                }
+            }
+        }
+        for (module, index) in used_outputs {
+            let exposed = &group_data.exposed_outputs;
+            if !exposed
+                .iter()
+                .any(|(m, i)| Rc::ptr_eq(m, &module) && *i == index)
+            {
+                group_data.exposed_outputs.push((module, index));
+            }
+        }
+        for member in modules {
+            for (index, control) in member.borrow().controls.iter().enumerate() {
+                let from_outside = control
+                    .as_dyn_ptr()
+                    .borrow()
+                    .get_connected_automation()
+                    .iter()
+                    .any(|source| !inside.iter().any(|m| Rc::ptr_eq(m, &source.module)));
+                let shown_by_container = container.as_ref().map_or(false, |container| {
+                    let container = container.borrow();
+                    let exposed = &container.group.as_ref().unwrap().exposed_controls;
+                    exposed
+                        .iter()
+                        .any(|(m, i)| Rc::ptr_eq(m, member) && *i == index)
+                });
+                if from_outside || shown_by_container {
+                    group_data.exposed_controls.push((Rc::clone(member), index));
+                }
+            }
+        }
+
+        let count = modules.len() as f32;
+        let pos = modules.iter().fold((0.0, 0.0), |sum, module| {
+            let pos = module.borrow().pos;
+            (sum.0 + pos.0 / count, sum.1 + pos.1 / count)
+        });
+        let group = rcrc(Module::create_group(group_data, pos));
+        let group_ref = group.borrow();
+        let group_data = group_ref.group.as_ref().unwrap();
+        let output_of_group = |module: &Rcrc<Module>, index: usize| {
+            let exposed = &group_data.exposed_outputs;
+            exposed
+                .iter()
+                .position(|(m, i)| Rc::ptr_eq(m, module) && *i == index)
+        };
+        for module in &outside {
+            for control in &module.borrow().controls {
+                let control_ptr = control.as_dyn_ptr();
+                for source in control_ptr.borrow_mut().get_connected_automation_mut() {
+                    if is_member(&source.module) {
+                        let index = output_of_group(&source.module, source.output_index);
+                        source.output_index = index.unwrap();
+                        source.module = Rc::clone(&group);
+                    }
+                }
+            }
+        }
+        if let Some(container) = &container {
+            let mut container_ref = container.borrow_mut();
+            let container_data = container_ref.group.as_mut().unwrap();
+            for output in &mut container_data.exposed_outputs {
+                if is_member(&output.0) {
+                    *output = (
+                        Rc::clone(&group),
+                        output_of_group(&output.0, output.1).unwrap(),
+                    );
+                }
+            }
+            for (module, index) in &mut container_data.exposed_controls {
+                if is_member(module) {
+                    let control = module.borrow().controls[*index].clone();
+                    *index = group_ref
+                        .controls
+                        .iter()
+                        .position(|other| other.is_same_control(&control))
+                        .unwrap();
+                    *module = Rc::clone(&group);
+                }
+            }
+            container_data.modules.retain(|module| !is_member(module));
+            container_data.modules.push(Rc::clone(&group));
+            drop(container_ref);
+            self.refresh_group_face(container);
+        } else {
+            self.modules.retain(|module| !is_member(module));
+            self.modules.push(Rc::clone(&group));
+        }
+        drop(group_ref);
+        group
+    }
+
+    /// Returns the indexes of every module in `modules` that the specified module takes inputs
+    /// from.
+    fn dependencies_of(modules: &[Rcrc<Module>], module: &Rcrc<Module>) -> Result<Vec<usize>, ()> {
+        let module_ref = module.borrow();
+        let mut dependencies = HashSet::new();
+        for control in &module_ref.controls {
+            let ptr = control.as_dyn_ptr();
+            let control_ref = ptr.borrow();
+            for sauce in control_ref.get_connected_automation() {
+                let (source, _) = Module::resolve_output(&sauce.module, sauce.output_index);
+                let index = modules.iter().position(|other| Rc::ptr_eq(other, &source));
+                dependencies.insert(index.ok_or(())?);
+            }
+        }
+        Ok(dependencies.into_iter().collect())
+    }
+
+    /// Decides what order modules should run in so that modules run after the modules they take
+    /// inputs from. Feedback loops are broken by delaying some modules, whose outputs are read
+    /// from the previous buffer by any module that runs before them.
+    pub fn compute_execution_order(&self) -> Result<ExecutionOrder, ()> {
+        let modules = self.flattened_modules();
+        let mut dependencies = Vec::new();
+        for module in &modules {
+            dependencies.push(Self::dependencies_of(&modules, module)?);
+        }
+        Ok(ExecutionOrder::from_dependencies(dependencies))
+    }
+
+    /// Decides which sections of the generated code each module runs in, indexed the same way as
+    /// `flattened_modules`. Effects-only modules and everything connected after them run in
+    /// the effects section. Everything else runs per voice, as well as in the effects section if
+    /// an effect uses its outputs. Modules which cannot be heard do not run in any section.
+    pub fn compute_sections(&self) -> Result<Vec<ModuleSections>, ()> {
+        let modules = self.flattened_modules();
+        let mut dependencies = Vec::new();
+        for module in &modules {
+            dependencies.push(Self::dependencies_of(&modules, module)?);
+        }
+        let templates: Vec<_> = modules
+            .iter()
+            .map(|module| Rc::clone(&module.borrow().template))
+            .collect();
+        // Modules without outputs, like Note Output, are the only ones which can affect what is
+        // heard.
+        let is_output: Vec<_> = templates
             .iter()
-            .position(|other| Rc::ptr_eq(module, other))
+            .map(|template| template.borrow().outputs.len() == 0)
+            .collect();
+        let effects_only: Vec<_> = templates
+            .iter()
+            .map(|template| template.borrow().effects_only)
+            .collect();
+        Ok(ModuleSections::from_dependencies(
+            &dependencies[..],
+            &is_output[..],
+            &effects_only[..],
+        ))
     }
+}
 
-    pub fn compute_execution_order(&self) -> Result<Vec<usize>, ()> {
-        let mut execution_order = Vec::new();
+/// The result of `ModuleGraph::compute_execution_order`.
+#[derive(Clone, Debug)]
+pub struct ExecutionOrder {
+    /// Indexes into `ModuleGraph::flattened_modules` in the order those modules should run.
+    pub order: Vec<usize>,
+    /// Indexes of modules which run after something that uses their outputs because they are
+    /// part of a feedback loop. Modules which run before them receive their outputs from the
+    /// previous buffer instead.
+    pub delayed: Vec<usize>,
+}
+
+impl ExecutionOrder {
+    /// `dependencies` contains the indexes of the modules each module takes inputs from.
+    fn from_dependencies(dependencies: Vec<Vec<usize>>) -> Self {
+        let mut order = Vec::new();
+        let mut delayed = HashSet::new();
         struct ModuleRepr {
             dependencies: Vec<usize>,
             satisfied: bool,
         }
-        let mut module_reprs = Vec::new();
-        for module in self.modules.iter() {
-            let module_ref = module.borrow();
-            let mut dependencies = HashSet::new();
-            for control in &module_ref.controls {
-                let ptr = control.as_dyn_ptr();
-                let control_ref = ptr.borrow();
-                for sauce in control_ref.get_connected_automation() {
-                    dependencies.insert(self.index_of_module(&sauce.module).ok_or(())?);
-                }
-            }
-            let flat_dependencies = dependencies.iter().cloned().collect();
-            module_reprs.push(ModuleRepr {
-                dependencies: flat_dependencies,
+        let mut module_reprs: Vec<_> = dependencies
+            .into_iter()
+            .map(|dependencies| ModuleRepr {
+                dependencies,
                 satisfied: false,
-            });
-        }
-        let mut progress = true;
-        while progress {
-            progress = false;
+            })
+            .collect();
+        let unsatisfied_dependencies = |reprs: &[ModuleRepr], index: usize| {
+            reprs[index]
+                .dependencies
+                .iter()
+                .filter(|dep| !reprs[**dep].satisfied)
+                .count()
+        };
+        while order.len() < module_reprs.len() {
+            let mut progress = false;
             for index in 0..module_reprs.len() {
                 if module_reprs[index].satisfied {
                     continue;
                 }
-                // Dependencies met if there is no dependency that is not satisfied.
-                let dependencies_met = !module_reprs[index]
-                    .dependencies
-                    .iter()
-                    .any(|depi| !module_reprs[*depi].satisfied);
-                if dependencies_met {
-                    execution_order.push(index);
+                if unsatisfied_dependencies(&module_reprs, index) == 0 {
+                    order.push(index);
                     module_reprs[index].satisfied = true;
                     progress = true;
                 }
             }
+            if progress {
+                continue;
+            }
+            // Every module left is part of or after a feedback loop. Run whichever one is waiting
+            // on the fewest other modules and delay those modules to break the loop.
+            let index = (0..module_reprs.len())
+                .filter(|index| !module_reprs[*index].satisfied)
+                .min_by_key(|index| unsatisfied_dependencies(&module_reprs, *index))
+                .unwrap();
+            for &dep in &module_reprs[index].dependencies {
+                if !module_reprs[dep].satisfied {
+                    delayed.insert(dep);
+                }
+            }
+            order.push(index);
+            module_reprs[index].satisfied = true;
         }
-        if execution_order.len() == module_reprs.len() {
-            Ok(execution_order)
-        } else {
-            Err(())
+        let mut delayed: Vec<_> = delayed.into_iter().collect();
+        delayed.sort();
+        Self { order, delayed }
+    }
+}
+
+/// Which sections of the generated code a module runs in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ModuleSections {
+    /// Runs once for every voice.
+    pub voices: bool,
+    /// Runs once per buffer on the mix of every voice.
+    pub effects: bool,
+}
+
+impl ModuleSections {
+    /// `dependencies` contains the indexes of the modules each module takes inputs from,
+    /// `is_output` is true for modules that send audio to the speakers and `effects_only` is true
+    /// for modules which can only run in the effects section. An effects section is only produced
+    /// if one of those modules is connected to an output.
+    pub fn from_dependencies(
+        dependencies: &[Vec<usize>],
+        is_output: &[bool],
+        effects_only: &[bool],
+    ) -> Vec<ModuleSections> {
+        let num_modules = dependencies.len();
+        let mut used = vec![false; num_modules];
+        for &dep in dependencies.iter().flatten() {
+            used[dep] = true;
+        }
+        // Everything which is not an output is only worth running if an output depends on it.
+        let mut audible = is_output.to_owned();
+        let mut to_visit: Vec<_> = (0..num_modules).filter(|i| audible[*i]).collect();
+        while let Some(index) = to_visit.pop() {
+            for &dep in &dependencies[index] {
+                if !audible[dep] {
+                    audible[dep] = true;
+                    to_visit.push(dep);
+                }
+            }
+        }
+        // Feedback loops mean information can flow in any direction, so keep spreading it until
+        // nothing changes.
+        let mut after_effect = effects_only.to_owned();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for index in 0..num_modules {
+                if !after_effect[index] && dependencies[index].iter().any(|dep| after_effect[*dep])
+                {
+                    after_effect[index] = true;
+                    changed = true;
+                }
+            }
+        }
+        let mut sections = vec![ModuleSections::default(); num_modules];
+        for index in 0..num_modules {
+            if !audible[index] {
+                continue;
+            } else if after_effect[index] {
+                sections[index].effects = true;
+            } else if !used[index] {
+                sections[index].voices = true;
+            }
+        }
+        // Every module runs in each section that something using its outputs runs in.
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for index in 0..num_modules {
+                let own = sections[index];
+                for &dep in &dependencies[index] {
+                    let before = sections[dep];
+                    sections[dep].voices |= own.voices;
+                    sections[dep].effects |= own.effects;
+                    changed |= sections[dep] != before;
+                }
+            }
         }
+        sections
+    }
+
+    /// False if the module does not run at all because nothing it does can be heard.
+    pub fn runs(self) -> bool {
+        self.voices || self.effects
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that every module runs exactly once, and that each of its dependencies either ran
+    /// before it or is delayed.
+    fn check_order(dependencies: &[Vec<usize>], result: &ExecutionOrder) {
+        let mut sorted = result.order.clone();
+        sorted.sort();
+        assert_eq!(sorted, (0..dependencies.len()).collect::<Vec<_>>());
+        for (position, &index) in result.order.iter().enumerate() {
+            for dep in &dependencies[index] {
+                let ran_before = result.order[..position].contains(dep);
+                assert!(ran_before || result.delayed.contains(dep));
+            }
+        }
+    }
+
+    fn order(dependencies: Vec<Vec<usize>>) -> ExecutionOrder {
+        let result = ExecutionOrder::from_dependencies(dependencies.clone());
+        check_order(&dependencies[..], &result);
+        result
+    }
+
+    #[test]
+    fn chain_is_not_delayed() {
+        let result = order(vec![vec![1], vec![2], vec![]]);
+        assert_eq!(result.order, vec![2, 1, 0]);
+        assert_eq!(result.delayed, Vec::<usize>::new());
+    }
+
+    #[test]
+    fn simple_loop() {
+        // 0 -> 1 -> 2 -> 1, 3 uses the output of the loop.
+        let result = order(vec![vec![], vec![0, 2], vec![1], vec![2]]);
+        assert_eq!(result.order, vec![0, 1, 2, 3]);
+        assert_eq!(result.delayed, vec![2]);
+    }
+
+    #[test]
+    fn nested_loops() {
+        // 1 is part of a loop with 0 and another loop with 2.
+        let result = order(vec![vec![1], vec![0, 2], vec![1]]);
+        assert_eq!(result.order, vec![0, 1, 2]);
+        assert_eq!(result.delayed, vec![1, 2]);
+    }
+
+    #[test]
+    fn breaks_loop_at_module_waiting_on_fewest() {
+        // 0 waits on both 1 and 2, so the loop is broken by running 1 first and delaying 0
+        // instead of delaying two modules.
+        let result = order(vec![vec![1, 2], vec![0], vec![1]]);
+        assert_eq!(result.order, vec![1, 2, 0]);
+        assert_eq!(result.delayed, vec![0]);
+    }
+
+    const VOICES: ModuleSections = ModuleSections {
+        voices: true,
+        effects: false,
+    };
+    const EFFECTS: ModuleSections = ModuleSections {
+        voices: false,
+        effects: true,
+    };
+    const NONE: ModuleSections = ModuleSections {
+        voices: false,
+        effects: false,
+    };
+
+    #[test]
+    fn unconnected_voices_in_adds_no_effects() {
+        // 0 is an oscillator playing through note output 1, 2 is a Voices In module which is not
+        // connected to anything.
+        let sections = ModuleSections::from_dependencies(
+            &[vec![], vec![0], vec![]],
+            &[false, true, false],
+            &[false, false, true],
+        );
+        assert_eq!(sections, vec![VOICES, VOICES, NONE]);
+    }
+
+    #[test]
+    fn voices_in_runs_effects_after_it() {
+        // 0 -> note output 1 per voice, Voices In 2 -> delay 3 -> note output 4 on the mix. 5 is
+        // an LFO used by both the oscillator and the delay.
+        let sections = ModuleSections::from_dependencies(
+            &[vec![5], vec![0], vec![], vec![2, 5], vec![3], vec![]],
+            &[false, true, false, false, true, false],
+            &[false, false, true, false, false, false],
+        );
+        let both = ModuleSections {
+            voices: true,
+            effects: true,
+        };
+        assert_eq!(
+            sections,
+            vec![VOICES, VOICES, EFFECTS, EFFECTS, EFFECTS, both]
+        );
+    }
+
+    #[test]
+    fn effects_not_reaching_an_output_do_not_run() {
+        // Voices In 2 feeds a delay 3 which is not connected to an output.
+        let sections = ModuleSections::from_dependencies(
+            &[vec![], vec![0], vec![], vec![2]],
+            &[false, true, false, false],
+            &[false, false, true, false],
+        );
+        assert_eq!(sections, vec![VOICES, VOICES, NONE, NONE]);
+    }
+
+    const KNOB: &str = "
+save_id: 0
+outputs:
+controls:
+  amount:
+    type: FloatInRange
+    min: -1
+    max: 1
+    default: 0
+gui:
+  label: Knob
+  category: Test
+  tooltip: Has a knob
+  width: 1
+  height: 1
+  widgets:
+";
+
+    #[test]
+    fn host_parameter_values_come_from_assigned_controls() {
+        let registry = Registry::for_tests(&[("Knob.module.yaml", KNOB)]).unwrap();
+        let id = ("Factory".to_owned(), 0);
+        let template = registry.borrow_template_by_serialized_id(&id).unwrap();
+        let module = Module::create(Rc::clone(template));
+        let mut graph = ModuleGraph::new();
+        if let AnyControl::FloatInRange(control) = &module.controls[0] {
+            let mut control = control.borrow_mut();
+            control.value = 0.5;
+            control.host_parameter = Some(3);
+        }
+        graph.add_module(rcrc(module));
+        {
+            let mut control = graph.macros[1].control.borrow_mut();
+            control.value = 0.25;
+            control.host_parameter = Some(0);
+        }
+        graph.macros[2].control.borrow_mut().value = 1.0;
+
+        let values = graph.host_parameter_values();
+        let mut expected = [0.0; NUM_HOST_PARAMETERS];
+        expected[0] = 0.25;
+        expected[3] = 0.75;
+        assert_eq!(values, expected);
     }
 }
@@ -1,8 +1,18 @@
-use crate::engine::data_transfer::{FeedbackData, GlobalData, GlobalParameters, IOData, NoteData};
+use crate::{
+    engine::{
+        data_transfer::{FeedbackData, GlobalData, GlobalParameters, IOData, NoteData},
+        julia_thread::{NoteEvent, NoteExpression, Pedal, DEFAULT_RELEASE_VELOCITY},
+        parts::{NotePriority, StealingPolicy, VoiceSettings},
+    },
+    registry::tuning::KeyFrequencies,
+};
 use array_macro::array;
 use jlrs_derive::IntoJulia;
-use julia_helper::{DataType, ExecutionEngine, GeneratedCode, JuliaStruct, TypedArray, Value};
-use std::collections::HashSet;
+use julia_helper::{
+    BackgroundJob, DataType, ExecutionEngine, Frame, GeneratedCode, JlrsResult, JuliaStruct,
+    TypedArray, Value,
+};
+use std::{collections::HashSet, time::Duration};
 
 /// The MIDI protocol can provide notes at 128 different pitches.
 const NUM_MIDI_NOTES: usize = 128;
@@ -10,6 +20,14 @@ const NUM_MIDI_NOTES: usize = 128;
 const MIN_SILENT_TIME: f32 = 0.1;
 /// Notes must have every sample be of this magnitude or less to be considered silent.
 const SILENT_CUTOFF: f32 = 1e-5;
+/// How long it takes for a stolen voice to fade out completely.
+const STEAL_FADE_TIME: f32 = 0.005;
+/// MPE specifies that timbre rests in the middle of its range until a controller changes it.
+const DEFAULT_TIMBRE: f32 = 0.5;
+/// How many notes NoteTracker makes room for up front, so that playing notes does not allocate.
+/// Notes which are fading out after being stolen are not limited by the voice limit, so there is
+/// room for twice as many.
+const NOTE_CAPACITY: usize = VoiceSettings::MAX_VOICES * 2;
 
 #[repr(C)]
 #[derive(Clone, Copy, JuliaStruct, IntoJulia)]
@@ -17,30 +35,184 @@ const SILENT_CUTOFF: f32 = 1e-5;
 struct NoteInput {
     pub pitch: f32,
     pub velocity: f32,
+    pub release_velocity: f32,
     pub elapsed_time: f32,
     pub elapsed_beats: f32,
+    pub pitch_bend: f32,
+    pub pressure: f32,
+    pub timbre: f32,
+    pub phase: f32,
+    pub key: i32,
+    pub start_offset: i32,
+    pub release_offset: i32,
     pub start_trigger: bool,
     pub release_trigger: bool,
 }
 
 impl NoteInput {
-    fn from(other: &NoteData, params: &GlobalParameters, pitch_mul: f32) -> Self {
+    fn from(
+        other: &NoteData,
+        params: &GlobalParameters,
+        global_data: &GlobalData,
+        pitch_mul: f32,
+        voice: &UnisonVoice,
+    ) -> Self {
+        let sample_rate = params.sample_rate as f32;
+        // Time is measured from the exact sample the note started on, so it is negative for the
+        // part of the first buffer before the note actually starts.
+        let elapsed_samples = other.elapsed_samples as f32 - other.start_offset as f32;
+        let elapsed_beats = if other.start_trigger {
+            -(other.start_offset as f32) / sample_rate * global_data.bpm / 60.0
+        } else {
+            other.elapsed_beats
+        };
+        let bend_mul = (2.0f32).powf(other.pitch_bend / 12.0);
         Self {
-            pitch: other.pitch * pitch_mul,
+            pitch: other.pitch * pitch_mul * bend_mul * voice.detune,
             velocity: other.velocity,
-            elapsed_time: other.elapsed_samples as f32 / params.sample_rate as f32,
-            elapsed_beats: other.elapsed_beats,
+            release_velocity: other.release_velocity,
+            elapsed_time: elapsed_samples / sample_rate,
+            elapsed_beats,
+            pitch_bend: other.pitch_bend,
+            pressure: other.pressure,
+            timbre: other.timbre,
+            phase: voice.phase,
+            key: other.key as i32,
+            start_offset: other.start_offset as i32,
+            release_offset: other.release_offset as i32,
             start_trigger: other.start_trigger,
             release_trigger: other.release_trigger,
         }
     }
 }
 
+/// One of the voices a note plays. Every voice of a note shares its timing and expression, but has
+/// its own static data so that unison voices can drift apart from each other.
+#[derive(Clone, Copy, Debug)]
+struct UnisonVoice {
+    static_index: usize,
+    /// Multiplied with the pitch of the note.
+    detune: f32,
+    /// From -1 (left) to 1 (right).
+    pan: f32,
+    /// Where in their cycle oscillators start, from 0 to 1.
+    phase: f32,
+    /// One over the square root of the number of voices. Detuned voices are uncorrelated, so their
+    /// powers add up, and `channel_gain` gives a voice the same power wherever it is panned. This
+    /// keeps a note as loud as a single voice no matter how many voices it has or how far apart
+    /// they are spread.
+    gain: f32,
+}
+
+impl UnisonVoice {
+    fn centered(static_index: usize) -> Self {
+        Self {
+            static_index,
+            detune: 1.0,
+            pan: 0.0,
+            phase: 0.0,
+            gain: 1.0,
+        }
+    }
+
+    /// How loud this voice should be in `channel`, taking its pan position into account. Every
+    /// layout with more than one channel starts with the front left and right speakers, so voices
+    /// are spread between those two using a constant power pan law. Other channels are left alone,
+    /// and nothing is panned in mono.
+    fn channel_gain(&self, channel: usize, channels: usize) -> f32 {
+        let pan_gain = if channels < 2 || channel > 1 {
+            1.0
+        } else {
+            // From 0 when panned hard left to pi / 2 when panned hard right. The gains are scaled
+            // so that a centered voice plays at its original volume in both channels.
+            let angle = (self.pan + 1.0) * std::f32::consts::FRAC_PI_4;
+            let gain = if channel == 0 {
+                angle.cos()
+            } else {
+                angle.sin()
+            };
+            gain * std::f32::consts::SQRT_2
+        };
+        pan_gain * self.gain
+    }
+}
+
+/// The voices of a note, stored inline so that starting a note does not allocate.
+#[derive(Debug)]
+struct UnisonVoices {
+    voices: [UnisonVoice; VoiceSettings::MAX_UNISON_VOICES],
+    len: usize,
+}
+
+impl UnisonVoices {
+    fn new() -> Self {
+        Self {
+            voices: [UnisonVoice::centered(0); VoiceSettings::MAX_UNISON_VOICES],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, voice: UnisonVoice) {
+        self.voices[self.len] = voice;
+        self.len += 1;
+    }
+}
+
+impl std::ops::Deref for UnisonVoices {
+    type Target = [UnisonVoice];
+
+    fn deref(&self) -> &[UnisonVoice] {
+        &self.voices[..self.len]
+    }
+}
+
 #[derive(Debug)]
 struct CompleteNoteData {
     data: NoteData,
+    voices: UnisonVoices,
+    /// How long every voice of the note has been silent for.
     silent_samples: usize,
-    static_index: usize,
+    /// The loudest sample any voice of the note produced in the last buffer it was rendered in.
+    /// Infinite until the note is rendered for the first time, so that notes which just started
+    /// are not mistaken for the quietest ones.
+    peak: f32,
+    /// If the voice was stolen, how many samples of its fade out have been rendered so far.
+    fade_progress: Option<usize>,
+    glide: Option<Glide>,
+}
+
+impl CompleteNoteData {
+    fn new(data: NoteData, voices: UnisonVoices) -> Self {
+        Self {
+            data,
+            voices,
+            silent_samples: 0,
+            peak: f32::INFINITY,
+            fade_progress: None,
+            glide: None,
+        }
+    }
+
+    /// Only one voice of a note can be shown in the module graph, so this picks the middle one
+    /// because it is the least detuned and panned. With an odd number of voices, it plays the
+    /// exact pitch of the note.
+    fn feedback_voice(&self) -> &UnisonVoice {
+        &self.voices[self.voices.len() / 2]
+    }
+}
+
+/// A slide from one pitch to another, used by portamento in mono mode.
+#[derive(Debug)]
+struct Glide {
+    from: f32,
+    to: f32,
+    elapsed_time: f32,
+}
+
+#[derive(Clone, Copy)]
+enum VoiceLocation {
+    Held(usize),
+    Decaying(usize),
 }
 
 pub struct NoteTracker {
@@ -49,6 +221,20 @@ pub struct NoteTracker {
     decaying_notes: Vec<CompleteNoteData>,
     reserved_static_indexes: HashSet<usize>,
     static_indexes_to_reset: Vec<usize>,
+    voice_settings: VoiceSettings,
+    /// Keys that are held down in mono mode along with their velocities, in the order they were
+    /// pressed.
+    held_keys: Vec<(usize, f32)>,
+    /// Which slot in held_notes the mono voice is currently stored in.
+    mono_key: Option<usize>,
+    key_frequencies: KeyFrequencies,
+    sustain_down: bool,
+    sostenuto_down: bool,
+    /// Which keys were held when the sostenuto pedal was pressed.
+    sostenuto_keys: [bool; NUM_MIDI_NOTES],
+    /// For keys which have been released but whose notes are kept sounding by a pedal, the
+    /// velocity they were released with.
+    sustained_keys: [Option<f32>; NUM_MIDI_NOTES],
 }
 
 impl NoteTracker {
@@ -56,9 +242,113 @@ impl NoteTracker {
         Self {
             dummy_note: None,
             held_notes: array![None; NUM_MIDI_NOTES],
-            decaying_notes: Vec::new(),
-            reserved_static_indexes: HashSet::new(),
-            static_indexes_to_reset: Vec::new(),
+            decaying_notes: Vec::with_capacity(NOTE_CAPACITY),
+            reserved_static_indexes: HashSet::with_capacity(
+                NOTE_CAPACITY * VoiceSettings::MAX_UNISON_VOICES,
+            ),
+            static_indexes_to_reset: Vec::with_capacity(
+                NOTE_CAPACITY * VoiceSettings::MAX_UNISON_VOICES,
+            ),
+            voice_settings: Default::default(),
+            held_keys: Vec::with_capacity(NUM_MIDI_NOTES),
+            mono_key: None,
+            key_frequencies: Default::default(),
+            sustain_down: false,
+            sostenuto_down: false,
+            sostenuto_keys: [false; NUM_MIDI_NOTES],
+            sustained_keys: [None; NUM_MIDI_NOTES],
+        }
+    }
+
+    /// Only affects notes started after this is called.
+    pub fn set_key_frequencies(&mut self, key_frequencies: KeyFrequencies) {
+        self.key_frequencies = key_frequencies;
+    }
+
+    pub fn set_voice_settings(&mut self, settings: VoiceSettings) {
+        if settings.mono != self.voice_settings.mono {
+            // Held notes mean different things in each mode, so release everything instead of
+            // trying to convert between them.
+            for index in 0..NUM_MIDI_NOTES {
+                self.release_voice(index, DEFAULT_RELEASE_VELOCITY, 0);
+            }
+            self.held_keys.clear();
+            self.mono_key = None;
+            self.sostenuto_keys = [false; NUM_MIDI_NOTES];
+            self.sustained_keys = [None; NUM_MIDI_NOTES];
+        }
+        self.voice_settings = settings;
+        while self.num_voices() > self.voice_settings.max_voices {
+            if !self.steal_voice() {
+                break;
+            }
+        }
+    }
+
+    /// Counts every voice that is still playing, not including voices which are fading out after
+    /// being stolen or the dummy note. Each unison voice counts separately.
+    fn num_voices(&self) -> usize {
+        let held = self.held_notes.iter().filter_map(|n| n.as_ref());
+        let decaying = self
+            .decaying_notes
+            .iter()
+            .filter(|n| n.fade_progress.is_none());
+        held.chain(decaying).map(|n| n.voices.len()).sum()
+    }
+
+    /// Starts fading out whichever note the current stealing policy picks, along with all of its
+    /// unison voices. Returns false if there was no note that could be stolen.
+    fn steal_voice(&mut self) -> bool {
+        let policy = self.voice_settings.stealing_policy;
+        // Larger values are stolen first.
+        let priority = |note: &CompleteNoteData, held: bool| -> (u8, f32) {
+            match policy {
+                StealingPolicy::Oldest => (0, note.data.elapsed_samples as f32),
+                StealingPolicy::Quietest => (0, -note.peak),
+                StealingPolicy::Lowest => (0, -note.data.pitch),
+                StealingPolicy::Highest => (0, note.data.pitch),
+                StealingPolicy::ReleaseFirst => {
+                    (if held { 0 } else { 1 }, note.data.elapsed_samples as f32)
+                }
+            }
+        };
+        let held = self
+            .held_notes
+            .iter()
+            .enumerate()
+            .filter_map(|(index, note)| note.as_ref().map(|n| (VoiceLocation::Held(index), n)));
+        let decaying = self
+            .decaying_notes
+            .iter()
+            .enumerate()
+            .filter(|(_, note)| note.fade_progress.is_none())
+            .map(|(index, note)| (VoiceLocation::Decaying(index), note));
+        let victim = held
+            .chain(decaying)
+            .map(|(location, note)| {
+                let is_held = if let VoiceLocation::Held(..) = location {
+                    true
+                } else {
+                    false
+                };
+                (location, priority(note, is_held))
+            })
+            .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        match victim {
+            Some((VoiceLocation::Held(index), _)) => {
+                if self.mono_key == Some(index) {
+                    self.mono_key = None;
+                }
+                let mut note = self.held_notes[index].take().unwrap();
+                note.fade_progress = Some(0);
+                self.decaying_notes.push(note);
+                true
+            }
+            Some((VoiceLocation::Decaying(index), _)) => {
+                self.decaying_notes[index].fade_progress = Some(0);
+                true
+            }
+            None => false,
         }
     }
 
@@ -72,27 +362,77 @@ impl NoteTracker {
         static_index
     }
 
+    /// Reserves static data for every unison voice a new note should play, spreading their pitch
+    /// and pan evenly across the ranges set in the voice settings.
+    fn reserve_unison_voices(&mut self) -> UnisonVoices {
+        let settings = &self.voice_settings;
+        let count = self.unison_voices_per_note();
+        let (detune, spread, random_phase) = (
+            settings.unison_detune,
+            settings.unison_spread,
+            settings.unison_random_phase,
+        );
+        let mut voices = UnisonVoices::new();
+        for index in 0..count {
+            let mut voice = UnisonVoice::centered(self.reserve_static_index());
+            if count > 1 {
+                // From -1 for the first voice to 1 for the last one.
+                let position = index as f32 / (count - 1) as f32 * 2.0 - 1.0;
+                voice.detune = (2.0f32).powf(position * detune / 2.0 / 1200.0);
+                voice.pan = position * spread;
+                voice.gain = 1.0 / (count as f32).sqrt();
+                if random_phase {
+                    voice.phase = rand::random();
+                }
+            }
+            voices.push(voice);
+        }
+        voices
+    }
+
+    /// Notes never have more unison voices than the voice limit allows.
+    fn unison_voices_per_note(&self) -> usize {
+        let settings = &self.voice_settings;
+        settings
+            .unison_voices
+            .min(settings.max_voices)
+            .min(VoiceSettings::MAX_UNISON_VOICES)
+            .max(1)
+    }
+
+    fn free_static_indexes(&mut self, note: &CompleteNoteData) {
+        for voice in note.voices.iter() {
+            assert!(self.reserved_static_indexes.remove(&voice.static_index));
+        }
+    }
+
     pub fn start_dummy_note(&mut self) {
         if self.dummy_note.is_none() {
             let static_index = self.reserve_static_index();
-            self.dummy_note = Some(CompleteNoteData {
-                data: NoteData {
-                    pitch: 440.0,
-                    velocity: 1.0,
-                    elapsed_samples: 0,
-                    elapsed_beats: 0.0,
-                    start_trigger: true,
-                    release_trigger: false,
-                },
-                silent_samples: 0,
-                static_index,
-            });
+            let data = NoteData {
+                pitch: 440.0,
+                key: 69,
+                velocity: 1.0,
+                release_velocity: 0.0,
+                elapsed_samples: 0,
+                elapsed_beats: 0.0,
+                pitch_bend: 0.0,
+                pressure: 0.0,
+                timbre: DEFAULT_TIMBRE,
+                start_offset: 0,
+                release_offset: 0,
+                start_trigger: true,
+                release_trigger: false,
+            };
+            let mut voices = UnisonVoices::new();
+            voices.push(UnisonVoice::centered(static_index));
+            self.dummy_note = Some(CompleteNoteData::new(data, voices));
         }
     }
 
     pub fn stop_dummy_note(&mut self) {
         if let Some(note) = self.dummy_note.take() {
-            self.reserved_static_indexes.remove(&note.static_index);
+            self.free_static_indexes(&note);
         }
     }
 
@@ -110,35 +450,238 @@ impl NoteTracker {
         self.held_notes = array![None; NUM_MIDI_NOTES];
         self.decaying_notes.clear();
         self.reserved_static_indexes.clear();
+        self.held_keys.clear();
+        self.mono_key = None;
+        // The pedals themselves are still physically down, only the notes they hold are gone.
+        self.sostenuto_keys = [false; NUM_MIDI_NOTES];
+        self.sustained_keys = [None; NUM_MIDI_NOTES];
+    }
+
+    /// Offsets past the end of the buffer are clamped to the last sample in the buffer.
+    pub fn apply_event(&mut self, event: NoteEvent, buffer_length: usize) {
+        let last_sample = buffer_length.max(1) - 1;
+        match event {
+            NoteEvent::StartNote {
+                index,
+                velocity,
+                offset,
+            } => self.start_note(index, velocity, offset.min(last_sample)),
+            NoteEvent::ReleaseNote {
+                index,
+                velocity,
+                offset,
+            } => self.release_note(index, velocity, offset.min(last_sample)),
+            NoteEvent::Expression {
+                index,
+                expression,
+                value,
+            } => self.set_expression(index, expression, value),
+            NoteEvent::Pedal {
+                pedal,
+                down,
+                offset,
+            } => self.set_pedal(pedal, down, offset.min(last_sample)),
+        }
+    }
+
+    /// Only affects notes which are still held. In mono mode, only the key the voice is currently
+    /// playing can change its expression.
+    pub fn set_expression(&mut self, index: usize, expression: NoteExpression, value: f32) {
+        let note = if let Some(Some(note)) = self.held_notes.get_mut(index) {
+            note
+        } else {
+            return;
+        };
+        match expression {
+            NoteExpression::PitchBend => note.data.pitch_bend = value,
+            NoteExpression::Pressure => note.data.pressure = value.max(0.0).min(1.0),
+            NoteExpression::Timbre => note.data.timbre = value.max(0.0).min(1.0),
+        }
+    }
+
+    pub fn start_note(&mut self, index: usize, velocity: f32, offset: usize) {
+        // Keys which the current tuning does not map to a frequency are ignored.
+        if self.key_frequencies.get(index).is_none() {
+            return;
+        }
+        let sustained_release = self.sustained_keys[index].take();
+        if self.voice_settings.mono {
+            self.held_keys.retain(|(key, _)| *key != index);
+            self.held_keys.push((index, velocity));
+            self.update_mono_voice(offset);
+        } else {
+            if let Some(release_velocity) = sustained_release {
+                // Re-striking a key which is only sounding because of a pedal cuts off the old
+                // note and plays a new one, like hitting the same piano string again.
+                self.release_voice(index, release_velocity, offset);
+            }
+            self.start_voice(index, velocity, offset);
+        }
+    }
+
+    /// `velocity` is how fast the key was released, from 0 to 1.
+    pub fn release_note(&mut self, index: usize, velocity: f32, offset: usize) {
+        let velocity = velocity.max(0.0).min(1.0);
+        if self.voice_settings.mono {
+            self.held_keys.retain(|(key, _)| *key != index);
+            if self.held_keys.len() > 0 {
+                self.update_mono_voice(offset);
+            } else if let Some(key) = self.mono_key {
+                if self.is_held_by_pedal(key) {
+                    self.sustained_keys[key] = Some(velocity);
+                } else {
+                    self.mono_key = None;
+                    self.release_voice(key, velocity, offset);
+                }
+            }
+        } else if self.held_notes[index].is_some() && self.is_held_by_pedal(index) {
+            self.sustained_keys[index] = Some(velocity);
+        } else {
+            self.release_voice(index, velocity, offset);
+        }
     }
 
-    fn equal_tempered_tuning(index: usize) -> f32 {
-        // MIDI note 69 is 440Hz. 12 notes is an octave (double / half frequency).
-        440.0 * (2.0f32).powf((index as i32 - 69) as f32 / 12.0)
+    fn is_held_by_pedal(&self, index: usize) -> bool {
+        self.sustain_down || (self.sostenuto_down && self.sostenuto_keys[index])
     }
 
-    pub fn start_note(&mut self, index: usize, velocity: f32) {
+    pub fn set_pedal(&mut self, pedal: Pedal, down: bool, offset: usize) {
+        match pedal {
+            Pedal::Sustain => self.sustain_down = down,
+            Pedal::Sostenuto => {
+                if down && !self.sostenuto_down {
+                    // Only keys which are physically held at the moment the pedal is pressed are
+                    // caught, notes which are already being sustained are not.
+                    self.sostenuto_keys = [false; NUM_MIDI_NOTES];
+                    if self.voice_settings.mono {
+                        for &(key, _) in &self.held_keys {
+                            self.sostenuto_keys[key] = true;
+                        }
+                    } else {
+                        for index in 0..NUM_MIDI_NOTES {
+                            self.sostenuto_keys[index] = self.held_notes[index].is_some()
+                                && self.sustained_keys[index].is_none();
+                        }
+                    }
+                } else if !down {
+                    self.sostenuto_keys = [false; NUM_MIDI_NOTES];
+                }
+                self.sostenuto_down = down;
+            }
+        }
+        if down {
+            return;
+        }
+        for key in 0..NUM_MIDI_NOTES {
+            let velocity = match self.sustained_keys[key] {
+                Some(velocity) if !self.is_held_by_pedal(key) => velocity,
+                _ => continue,
+            };
+            self.sustained_keys[key] = None;
+            if !self.voice_settings.mono {
+                self.release_voice(key, velocity, offset);
+            } else if self.held_keys.is_empty() && self.mono_key == Some(key) {
+                self.mono_key = None;
+                self.release_voice(key, velocity, offset);
+            }
+        }
+    }
+
+    fn pick_mono_key(&self) -> Option<(usize, f32)> {
+        let keys = self.held_keys.iter().cloned();
+        match self.voice_settings.note_priority {
+            NotePriority::Last => keys.last(),
+            NotePriority::Low => keys.min_by_key(|(key, _)| *key),
+            NotePriority::High => keys.max_by_key(|(key, _)| *key),
+        }
+    }
+
+    /// Makes the mono voice play whichever held key has priority, either by moving the existing
+    /// voice to the new pitch or by starting a new voice if there is none.
+    fn update_mono_voice(&mut self, offset: usize) {
+        let (key, velocity) = if let Some(value) = self.pick_mono_key() {
+            value
+        } else {
+            return;
+        };
+        if self.mono_key == Some(key) {
+            return;
+        }
+        let old_key = if let Some(old_key) = self.mono_key {
+            old_key
+        } else {
+            self.start_voice(key, velocity, offset);
+            self.mono_key = Some(key);
+            return;
+        };
+        let mut note = self.held_notes[old_key].take().unwrap();
+        // The tuning may have changed since the key was pressed.
+        let pitch = self.key_frequencies.get(key).unwrap_or(note.data.pitch);
+        if self.voice_settings.glide_time > 0.0 {
+            note.glide = Some(Glide {
+                from: note.data.pitch,
+                to: pitch,
+                elapsed_time: 0.0,
+            });
+        } else {
+            note.data.pitch = pitch;
+        }
+        note.data.key = key;
+        if !self.voice_settings.legato {
+            note.data.velocity = velocity;
+            note.data.elapsed_samples = 0;
+            note.data.elapsed_beats = 0.0;
+            note.data.start_offset = offset;
+            note.data.start_trigger = true;
+        }
+        self.held_notes[key] = Some(note);
+        self.mono_key = Some(key);
+    }
+
+    fn start_voice(&mut self, index: usize, velocity: f32, offset: usize) {
+        let pitch = if let Some(pitch) = self.key_frequencies.get(index) {
+            pitch
+        } else {
+            return;
+        };
         if self.held_notes[index].is_some() {
             return;
         }
-        let static_index = self.reserve_static_index();
-        self.held_notes[index] = Some(CompleteNoteData {
-            data: NoteData {
-                pitch: Self::equal_tempered_tuning(index),
-                velocity,
-                elapsed_samples: 0,
-                elapsed_beats: 0.0,
-                start_trigger: true,
-                release_trigger: false,
-            },
-            silent_samples: 0,
-            static_index,
-        });
+        let unison_voices = self.unison_voices_per_note();
+        while self.num_voices() + unison_voices > self.voice_settings.max_voices {
+            if !self.steal_voice() {
+                break;
+            }
+        }
+        let voices = self.reserve_unison_voices();
+        let data = NoteData {
+            pitch,
+            key: index,
+            velocity,
+            release_velocity: 0.0,
+            elapsed_samples: 0,
+            elapsed_beats: 0.0,
+            pitch_bend: 0.0,
+            pressure: 0.0,
+            timbre: DEFAULT_TIMBRE,
+            start_offset: offset,
+            release_offset: 0,
+            start_trigger: true,
+            release_trigger: false,
+        };
+        self.held_notes[index] = Some(CompleteNoteData::new(data, voices));
     }
 
-    pub fn release_note(&mut self, index: usize) {
+    fn release_voice(&mut self, index: usize, velocity: f32, offset: usize) {
         if let Some(mut note) = self.held_notes[index].take() {
-            note.data.start_trigger = false;
+            note.data.release_velocity = velocity;
+            // If the note started in this same buffer, it has not been rendered yet so the start
+            // trigger must be kept. The release can't happen before the start.
+            note.data.release_offset = if note.data.start_trigger {
+                offset.max(note.data.start_offset)
+            } else {
+                offset
+            };
             note.data.release_trigger = true;
             self.decaying_notes.push(note);
         }
@@ -148,31 +691,64 @@ impl NoteTracker {
         let sample_rate = global_params.sample_rate as f32;
         let buffer_len = global_params.buffer_length;
         let min_silent_samples = (MIN_SILENT_TIME * sample_rate) as usize;
+        let fade_samples = Self::steal_fade_samples(global_params);
+        let glide_time = self.voice_settings.glide_time;
+        let buffer_time = buffer_len as f32 / sample_rate;
         let buffer_beats = global_data.bpm / 60.0 * buffer_len as f32 / sample_rate;
+        let advance = |data: &mut NoteData| {
+            data.elapsed_samples += buffer_len;
+            if data.start_trigger {
+                // Only count the part of the first buffer after the note actually started.
+                let fraction = (buffer_len - data.start_offset) as f32 / buffer_len as f32;
+                data.elapsed_beats += buffer_beats * fraction;
+            } else {
+                data.elapsed_beats += buffer_beats;
+            }
+            data.start_trigger = false;
+        };
+        let advance_glide = |note: &mut CompleteNoteData| {
+            if let Some(glide) = &mut note.glide {
+                glide.elapsed_time += buffer_time;
+                let progress = if glide_time > 0.0 {
+                    (glide.elapsed_time / glide_time).min(1.0)
+                } else {
+                    1.0
+                };
+                // Glide linearly in terms of octaves rather than hertz.
+                note.data.pitch = glide.from * (glide.to / glide.from).powf(progress);
+                if progress >= 1.0 {
+                    note.glide = None;
+                }
+            }
+        };
         for index in (0..self.decaying_notes.len()).rev() {
             let note = &mut self.decaying_notes[index];
-            if note.silent_samples >= min_silent_samples {
-                assert!(self.reserved_static_indexes.remove(&note.static_index));
-                self.decaying_notes.remove(index);
+            let faded_out = note.fade_progress.map(|p| p >= fade_samples) == Some(true);
+            if note.silent_samples >= min_silent_samples || faded_out {
+                let note = self.decaying_notes.remove(index);
+                self.free_static_indexes(&note);
             } else {
-                note.data.elapsed_samples += buffer_len;
-                note.data.elapsed_beats += buffer_beats;
-                note.data.start_trigger = false;
+                advance(&mut note.data);
+                advance_glide(note);
                 note.data.release_trigger = false;
+                if let Some(progress) = &mut note.fade_progress {
+                    *progress += buffer_len;
+                }
             }
         }
         for note in self.held_notes.iter_mut().filter_map(|o| o.as_mut()) {
-            note.data.elapsed_samples += buffer_len;
-            note.data.elapsed_beats += buffer_beats;
-            note.data.start_trigger = false;
+            advance(&mut note.data);
+            advance_glide(note);
         }
         if let Some(note) = &mut self.dummy_note {
-            note.data.elapsed_samples += buffer_len;
-            note.data.elapsed_beats += buffer_beats;
-            note.data.start_trigger = false;
+            advance(&mut note.data);
         }
     }
 
+    fn steal_fade_samples(global_params: &GlobalParameters) -> usize {
+        ((STEAL_FADE_TIME * global_params.sample_rate as f32) as usize).max(1)
+    }
+
     fn recommend_note_for_feedback(&self) -> Option<usize> {
         let mut youngest_time = std::usize::MAX;
         for note in self.held_notes.iter().filter_map(|o| o.as_ref()) {
@@ -186,16 +762,16 @@ impl NoteTracker {
         }
         for note in self.held_notes.iter().filter_map(|o| o.as_ref()) {
             if note.data.elapsed_samples == youngest_time {
-                return Some(note.static_index);
+                return Some(note.feedback_voice().static_index);
             }
         }
         for note in &self.decaying_notes {
             if note.data.elapsed_samples == youngest_time {
-                return Some(note.static_index);
+                return Some(note.feedback_voice().static_index);
             }
         }
         if let Some(note) = &self.dummy_note {
-            Some(note.static_index)
+            Some(note.feedback_voice().static_index)
         } else {
             None
         }
@@ -209,12 +785,123 @@ impl NoteTracker {
     }
 }
 
+/// Reads the feedback and output view data returned by exec_voices or exec_effects into `into`,
+/// reusing the vectors it already has.
+fn read_feedback_data<'f>(
+    frame: &mut impl Frame<'f>,
+    output: Value<'f, 'f>,
+    view_index: usize,
+    into: &mut FeedbackData,
+) -> JlrsResult<Result<(), String>> {
+    let julia_feedback = match output.get_nth_field(frame, 1) {
+        Ok(v) => v,
+        Err(err) => {
+            return Ok(Err(format!(
+                "ERROR: Failed to retrieve feedback data, caused by:\n{:?}",
+                err
+            )))
+        }
+    };
+    into.widget_feeback
+        .resize(julia_feedback.n_fields(), Vec::new());
+    for index in 0..julia_feedback.n_fields() {
+        let field = julia_feedback.get_nth_field(frame, index)?;
+        let field = field.cast::<TypedArray<'_, '_, f32>>()?;
+        let field = field.inline_data(frame)?.into_slice();
+        into.widget_feeback[index].clear();
+        into.widget_feeback[index].extend_from_slice(field);
+    }
+    let julia_view_data = match output.get_nth_field(frame, 2) {
+        Ok(v) => v,
+        Err(err) => {
+            return Ok(Err(format!(
+                "ERROR: Failed to retrieve output view data, caused by:\n{:?}",
+                err
+            )))
+        }
+    };
+    into.output_view
+        .resize(julia_view_data.n_fields(), Vec::new());
+    for index in 0..julia_view_data.n_fields() {
+        let field = julia_view_data.get_nth_field(frame, index)?;
+        let field = field.cast::<TypedArray<'_, '_, f32>>()?;
+        let field = field.inline_data(frame)?.into_slice();
+        into.output_view[index].clear();
+        into.output_view[index].extend_from_slice(field);
+    }
+    into.output_view_module_index = view_index;
+    Ok(Ok(()))
+}
+
+/// Generated code is loaded into one of these modules at a time so that the previous code can keep
+/// running from the other one while new code compiles.
+const CODE_SLOTS: [&str; 2] = ["CodeSlotA", "CodeSlotB"];
+/// How long it takes to crossfade from replaced generated code to the code that replaced it.
+const CODE_CROSSFADE_TIME: f32 = 0.02;
+/// The least number of voices the buffers of generated code are made to hold, so that playing a
+/// few notes does not make them grow several times in a row.
+const MIN_VOICE_CAPACITY: usize = 16;
+
+/// Generated code which has been loaded into one of the code slots.
+#[derive(Clone, Copy)]
+struct LoadedCode {
+    slot: usize,
+    /// Whether the code has an effects section that needs to run after the voices.
+    has_effects: bool,
+}
+
+impl LoadedCode {
+    /// The path of a function or global variable defined by the code.
+    fn path(&self, name: &'static str) -> [&'static str; 4] {
+        ["Main", CODE_SLOTS[self.slot], "Generated", name]
+    }
+}
+
+/// New generated code which is being compiled in the background.
+struct StagedCode {
+    code: LoadedCode,
+    source: GeneratedCode,
+    dyn_data: Vec<IOData>,
+    jobs: Vec<BackgroundJob>,
+}
+
+/// Generated code which was replaced and is fading out.
+struct FadingCode {
+    code: LoadedCode,
+    dyn_data: Vec<IOData>,
+    /// How many samples of the crossfade have been rendered so far.
+    progress: usize,
+}
+
 pub(super) struct AudiobenchExecutor {
     base: ExecutionEngine,
     parameters: GlobalParameters,
     registry_source: GeneratedCode,
+    /// The last generated code that loaded successfully.
     generated_source: GeneratedCode,
+    current_code: LoadedCode,
+    staged_code: Option<StagedCode>,
+    fading_code: Option<FadingCode>,
+    /// False if there is no working code to run, in which case only silence is produced.
     loaded: bool,
+    /// How many voices the buffers of the code in each slot have room for.
+    voice_capacity: [usize; 2],
+    /// The inputs of every voice being rendered, kept between buffers so that they do not have to
+    /// be allocated again.
+    note_inputs: Vec<NoteInput>,
+    static_indexes: Vec<i64>,
+    /// Used in place of the host's input when it did not provide any.
+    silence: Vec<f32>,
+    /// Where the code which is fading out renders its audio before it is mixed into the output.
+    faded_output: Vec<f32>,
+    /// The names of the globals generated code keeps each item of dynamic data in.
+    dyn_data_names: Vec<String>,
+    /// How long each array of dynamic data is in the code in each slot.
+    dyn_data_lengths: [Vec<usize>; 2],
+    /// The most recent feedback data, see `replace_feedback`.
+    feedback: FeedbackData,
+    /// Feedback from the effects section, which is merged into `feedback`.
+    effects_feedback: FeedbackData,
 }
 
 impl AudiobenchExecutor {
@@ -249,18 +936,39 @@ impl AudiobenchExecutor {
             },
             registry_source,
             generated_source: GeneratedCode::from_unique_source("blank", ""),
+            current_code: LoadedCode {
+                slot: 0,
+                has_effects: false,
+            },
+            staged_code: None,
+            fading_code: None,
             loaded: false,
+            voice_capacity: [0; 2],
+            note_inputs: Vec::new(),
+            static_indexes: Vec::new(),
+            silence: Vec::new(),
+            faded_output: Vec::new(),
+            dyn_data_names: Vec::new(),
+            dyn_data_lengths: [Vec::new(), Vec::new()],
+            feedback: FeedbackData::default(),
+            effects_feedback: FeedbackData::default(),
         };
         this.change_parameters(parameters)?;
         Ok(this)
     }
 
+    /// Any code which is still being swapped in is discarded, so `finish_swapping_code` should be
+    /// used before this if that code is needed. If the current code does not work with the new
+    /// parameters, it is unloaded so that the executor can keep running.
     pub fn change_parameters(&mut self, parameters: &GlobalParameters) -> Result<(), String> {
         if &self.parameters == parameters {
             return Ok(());
         }
-        self.loaded = false;
         self.parameters = parameters.clone();
+        self.staged_code = None;
+        self.fading_code = None;
+        self.silence = vec![0.0; parameters.channels * parameters.buffer_length];
+        self.faded_output = vec![0.0; parameters.channels * parameters.buffer_length];
         let parameter_code = format!(
             concat!(
                 "module Parameters\n",
@@ -282,15 +990,28 @@ impl AudiobenchExecutor {
         self.base
             .add_global_code(self.registry_source.clone())
             .map_err(Self::beautify_stack_trace)?;
-        // Redefine the Generated module to be blank because it may have been previously compiled
-        // with old parameters.
-        self.base
-            .add_global_code(self.generated_source.clone())
-            .map_err(Self::beautify_stack_trace)?;
+        // Redefine the current generated code because it may have been previously compiled with
+        // old parameters.
+        let res = self.load_into_slot(self.current_code.slot, self.generated_source.clone());
+        if let Err(err) = res {
+            self.loaded = false;
+            self.generated_source = GeneratedCode::from_unique_source("blank", "");
+            self.current_code.has_effects = false;
+            return Err(Self::beautify_stack_trace(err));
+        }
+        self.reset_effects_static_data()?;
         Ok(())
     }
 
-    pub fn change_generated_code(&mut self, generated_code: GeneratedCode) -> Result<(), String> {
+    /// Loads code into one of the code slots, replacing whatever was there before along with its
+    /// buffers.
+    fn load_into_slot(&mut self, slot: usize, code: GeneratedCode) -> Result<(), String> {
+        self.voice_capacity[slot] = 0;
+        self.dyn_data_lengths[slot].clear();
+        self.base.add_code_in_module(CODE_SLOTS[slot], code)
+    }
+
+    fn write_debug_copy(generated_code: &GeneratedCode) -> Result<(), String> {
         let mut temp_file = std::env::temp_dir();
         temp_file.push("audiobench_note_graph_code.jl");
         if std::fs::write(temp_file.clone(), generated_code.as_str()).is_err() {
@@ -299,17 +1020,323 @@ impl AudiobenchExecutor {
                 temp_file
             ));
         }
-        self.generated_source = generated_code.clone();
-        self.base
-            .add_global_code(generated_code)
+        Ok(())
+    }
+
+    /// Immediately replaces the current code, without compiling it first or fading between the
+    /// old and new code. If the new code fails to load, the previous code is restored.
+    pub fn change_generated_code(
+        &mut self,
+        generated_code: GeneratedCode,
+        has_effects: bool,
+    ) -> Result<(), String> {
+        Self::write_debug_copy(&generated_code)?;
+        self.staged_code = None;
+        self.fading_code = None;
+        let slot = self.current_code.slot;
+        if let Err(err) = self.load_into_slot(slot, generated_code.clone()) {
+            // The failed code may have partially replaced the module, so load the last working
+            // code again.
+            if self.loaded {
+                self.loaded = self
+                    .load_into_slot(slot, self.generated_source.clone())
+                    .is_ok();
+            }
+            return Err(Self::beautify_stack_trace(err));
+        }
+        self.generated_source = generated_code;
+        self.current_code.has_effects = has_effects;
+        self.loaded = true;
+        self.reset_effects_static_data()?;
+        Ok(())
+    }
+
+    /// Starts compiling new code in the background while the current code keeps running. Once
+    /// `poll_staged_code` reports it is ready, the new code replaces the current code and the two
+    /// are crossfaded. `dyn_data` is the dynamic data the new code should run with.
+    pub fn stage_generated_code(
+        &mut self,
+        generated_code: GeneratedCode,
+        has_effects: bool,
+        dyn_data: Vec<IOData>,
+    ) -> Result<(), String> {
+        assert!(!self.is_swapping_code());
+        Self::write_debug_copy(&generated_code)?;
+        let code = LoadedCode {
+            slot: 1 - self.current_code.slot,
+            has_effects,
+        };
+        self.load_into_slot(code.slot, generated_code.clone())
             .map_err(Self::beautify_stack_trace)?;
+
+        // Compile the new code by running each section once with a note that is never heard. This
+        // uses static index 0 of the new code, which is reset before the new code is swapped in.
+        let mut note = NoteTracker::new();
+        note.start_dummy_note();
+        let note = note.dummy_note.unwrap();
+        let global_data = GlobalData::new();
+        self.set_single_voice(&note, &global_data);
+        self.write_global_inputs(code, &global_data)?;
+        self.write_voice_inputs(code)?;
+        self.write_dyn_data(code, &dyn_data[..])?;
+        self.write_options(code, false, 0, 1, 0)?;
+        let mut jobs = Vec::new();
+        let job = self.base.spawn_fn(&code.path("exec_voices"), |_, _| Ok(()));
+        jobs.push(job.map_err(Self::beautify_stack_trace)?);
+        if has_effects {
+            // The effects section starts out with silence as its input.
+            let job = self
+                .base
+                .spawn_fn(&code.path("exec_effects"), |_, _| Ok(()));
+            jobs.push(job.map_err(Self::beautify_stack_trace)?);
+        }
+        self.staged_code = Some(StagedCode {
+            code,
+            source: generated_code,
+            dyn_data,
+            jobs,
+        });
+        Ok(())
+    }
+
+    /// Returns true if new code is still compiling or old code is still fading out.
+    pub fn is_swapping_code(&self) -> bool {
+        self.staged_code.is_some() || self.fading_code.is_some()
+    }
+
+    /// Returns true if new code is still compiling.
+    pub fn is_staging_code(&self) -> bool {
+        self.staged_code.is_some()
+    }
+
+    /// Changes the dynamic data that staged code will start running with. Does nothing if no code
+    /// is staged.
+    pub fn set_staged_dyn_data(&mut self, dyn_data: Vec<IOData>) {
+        if let Some(staged) = &mut self.staged_code {
+            staged.dyn_data = dyn_data;
+        }
+    }
+
+    /// Checks if staged code has finished compiling. If it has, it replaces the current code and
+    /// `dyn_data` is replaced with the dynamic data of the new code. Returns true if this
+    /// happened. If compiling failed, the staged code is discarded and the current code keeps
+    /// running.
+    pub fn poll_staged_code(
+        &mut self,
+        notes: &mut NoteTracker,
+        dyn_data: &mut Vec<IOData>,
+    ) -> Result<bool, String> {
+        let Self {
+            base, staged_code, ..
+        } = self;
+        let staged = if let Some(staged) = staged_code {
+            staged
+        } else {
+            return Ok(false);
+        };
+        while let Some(job) = staged.jobs.last() {
+            match base.poll_job(job) {
+                Ok(true) => {
+                    staged.jobs.pop();
+                }
+                Ok(false) => return Ok(false),
+                Err(err) => {
+                    *staged_code = None;
+                    return Err(Self::beautify_stack_trace(err));
+                }
+            }
+        }
+
+        let staged = self.staged_code.take().unwrap();
+        self.fading_code = Some(FadingCode {
+            code: self.current_code,
+            dyn_data: std::mem::replace(dyn_data, staged.dyn_data),
+            progress: 0,
+        });
+        self.current_code = staged.code;
+        self.generated_source = staged.source;
         self.loaded = true;
+        // Notes which are already playing should not continue from whatever state compiling
+        // the code left behind. The replaced code keeps its state so that it fades out smoothly.
+        for &index in &notes.reserved_static_indexes {
+            self.reset_static_data(self.current_code, index)?;
+        }
+        Ok(true)
+    }
+
+    /// Blocks until staged code is done compiling and has replaced the current code, skipping
+    /// the crossfade.
+    pub fn finish_swapping_code(
+        &mut self,
+        notes: &mut NoteTracker,
+        dyn_data: &mut Vec<IOData>,
+    ) -> Result<(), String> {
+        while self.is_staging_code() {
+            if !self.poll_staged_code(notes, dyn_data)? {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+        self.fading_code = None;
+        Ok(())
+    }
+
+    /// Copies `global_data` into the buffers of `code`.
+    fn write_global_inputs(
+        &mut self,
+        code: LoadedCode,
+        global_data: &GlobalData,
+    ) -> Result<(), String> {
+        let host_input = if global_data.host_input.len() == self.silence.len() {
+            &global_data.host_input[..]
+        } else {
+            &self.silence[..]
+        };
+        let timing = [
+            global_data.pitch_wheel,
+            global_data.bpm,
+            global_data.elapsed_time,
+            global_data.elapsed_beats,
+        ];
+        self.base
+            .write_global_array(&code.path("timing_in"), &timing[..])?;
+        self.base.write_global_array(
+            &code.path("transport_in"),
+            &[global_data.transport_input()][..],
+        )?;
+        self.base.write_global_array(
+            &code.path("midi_controls_in"),
+            &global_data.controller_values[..],
+        )?;
+        self.base
+            .write_global_array(&code.path("host_input_in"), host_input)?;
+        self.base.write_global_array(
+            &code.path("host_parameters_in"),
+            &global_data.host_parameters[..],
+        )
+    }
+
+    /// Copies `dyn_data` into the buffers of `code`. Arrays are resized first if their length
+    /// changed, which only happens when a control is edited.
+    fn write_dyn_data(&mut self, code: LoadedCode, dyn_data: &[IOData]) -> Result<(), String> {
+        while self.dyn_data_names.len() < dyn_data.len() {
+            let name = format!("dyn{}_in", self.dyn_data_names.len());
+            self.dyn_data_names.push(name);
+        }
+        // Arrays in newly loaded code start out empty.
+        self.dyn_data_lengths[code.slot].resize(dyn_data.len(), 0);
+        for (index, item) in dyn_data.iter().enumerate() {
+            let array_len = match item {
+                IOData::BoolArray(v) => Some(v.len()),
+                IOData::IntArray(v) => Some(v.len()),
+                IOData::FloatArray(v) => Some(v.len()),
+                IOData::Bool(..) | IOData::Int(..) | IOData::Float(..) => None,
+            };
+            if let Some(len) =
+                array_len.filter(|&len| len != self.dyn_data_lengths[code.slot][index])
+            {
+                self.base.call_fn(
+                    &code.path("resize_dyn_data"),
+                    |frame, inputs| {
+                        inputs.push(Value::new(frame, index)?);
+                        inputs.push(Value::new(frame, len)?);
+                        Ok(())
+                    },
+                    |_, _| Ok(()),
+                )?;
+                self.dyn_data_lengths[code.slot][index] = len;
+            }
+            let path = [
+                "Main",
+                CODE_SLOTS[code.slot],
+                "Generated",
+                &self.dyn_data_names[index][..],
+            ];
+            match item {
+                IOData::Bool(v) => self.base.write_global_array(&path, &[*v][..]),
+                IOData::Int(v) => self.base.write_global_array(&path, &[*v][..]),
+                IOData::Float(v) => self.base.write_global_array(&path, &[*v][..]),
+                IOData::BoolArray(v) => self.base.write_global_array(&path, &v[..]),
+                IOData::IntArray(v) => self.base.write_global_array(&path, &v[..]),
+                IOData::FloatArray(v) => self.base.write_global_array(&path, &v[..]),
+            }?;
+        }
         Ok(())
     }
 
-    fn reset_static_data(&mut self, index: usize) -> Result<(), String> {
+    /// Sets what the next call to `exec_voices` or `exec_effects` in `code` should do.
+    /// `feedback_voice` and `num_voices` are only used by `exec_voices`, and `do_feedback` is only
+    /// used by `exec_effects`.
+    fn write_options(
+        &mut self,
+        code: LoadedCode,
+        do_feedback: bool,
+        feedback_voice: usize,
+        num_voices: usize,
+        view_index: usize,
+    ) -> Result<(), String> {
+        self.base
+            .write_global_array(&code.path("do_feedback_in"), &[do_feedback][..])?;
+        self.base.write_global_array(
+            &code.path("feedback_voice_in"),
+            &[feedback_voice as i64][..],
+        )?;
+        self.base
+            .write_global_array(&code.path("num_voices_in"), &[num_voices as i64][..])?;
+        self.base
+            .write_global_array(&code.path("view_index_in"), &[view_index as i64][..])
+    }
+
+    /// Copies `note_inputs` and `static_indexes` into the buffers of `code`, making room for more
+    /// voices first if they do not fit.
+    fn write_voice_inputs(&mut self, code: LoadedCode) -> Result<(), String> {
+        let num_voices = self.static_indexes.len();
+        if num_voices > self.voice_capacity[code.slot] {
+            let capacity = (num_voices * 2).max(MIN_VOICE_CAPACITY);
+            self.base.call_fn(
+                &code.path("reserve_voices"),
+                |frame, inputs| {
+                    inputs.push(Value::new(frame, capacity)?);
+                    Ok(())
+                },
+                |_, _| Ok(()),
+            )?;
+            self.voice_capacity[code.slot] = capacity;
+        }
+        self.base
+            .write_global_array(&code.path("note_inputs_in"), &self.note_inputs[..])?;
+        self.base
+            .write_global_array(&code.path("static_indexes_in"), &self.static_indexes[..])
+    }
+
+    /// Makes the only voice to render the first voice of `note`.
+    fn set_single_voice(&mut self, note: &CompleteNoteData, global_data: &GlobalData) {
+        let voice = &note.voices[0];
+        self.note_inputs.clear();
+        self.note_inputs.push(NoteInput::from(
+            &note.data,
+            &self.parameters,
+            global_data,
+            1.0,
+            voice,
+        ));
+        self.static_indexes.clear();
+        self.static_indexes.push(voice.static_index as i64);
+    }
+
+    fn reset_effects_static_data(&mut self) -> Result<(), String> {
+        if !self.current_code.has_effects {
+            return Ok(());
+        }
         self.base.call_fn(
-            &["Main", "Generated", "static_init"],
+            &self.current_code.path("effects_static_init"),
+            |_, _| Ok(()),
+            |_, _| Ok(()),
+        )
+    }
+
+    fn reset_static_data(&mut self, code: LoadedCode, index: usize) -> Result<(), String> {
+        self.base.call_fn(
+            &code.path("static_init"),
             |frame, inputs| {
                 inputs.push(Value::new(frame, index)?);
                 Ok(())
@@ -320,37 +1347,85 @@ impl AudiobenchExecutor {
 
     // Runs the main function once to make sure everything is compiled.
     pub fn preheat(&mut self, notes: &mut NoteTracker, dyn_data: &[IOData]) -> Result<(), String> {
+        if !self.loaded {
+            return Ok(());
+        }
+        let code = self.current_code;
         let was_dummy_note_active = notes.dummy_note.is_some();
         notes.start_dummy_note();
         for index in std::mem::take(&mut notes.static_indexes_to_reset) {
-            self.reset_static_data(index)?;
+            self.reset_static_data(code, index)?;
         }
-        let note = notes.dummy_note.as_ref().unwrap();
-        let note_input = NoteInput::from(&note.data, &self.parameters, 1.0);
-        let static_index = note.static_index;
         let global_data = GlobalData::new();
-        self.base.call_fn(
-            &["Main", "Generated", "exec"],
-            |frame, inputs| {
-                inputs.append(&mut global_data.as_julia_values(frame)?);
-                inputs.push(Value::new(frame, false)?); // do_feedback
-                inputs.push(Value::new(frame, note_input)?);
-                inputs.push(Value::new(frame, static_index)?);
-                inputs.push(Value::new(frame, 0)?);
-                for item in dyn_data {
-                    inputs.push(item.as_julia_value(frame)?);
-                }
-                Ok(())
-            },
-            |frame, output| Ok(()),
-        )?;
+        self.set_single_voice(notes.dummy_note.as_ref().unwrap(), &global_data);
+        self.write_global_inputs(code, &global_data)?;
+        self.write_voice_inputs(code)?;
+        self.write_dyn_data(code, dyn_data)?;
+        self.write_options(code, false, 0, 1, 0)?;
+        self.base
+            .call_fn(&code.path("exec_voices"), |_, _| Ok(()), |_, _| Ok(()))?;
+        if code.has_effects {
+            self.base
+                .write_global_array(&code.path("voices_in"), &self.silence[..])?;
+            self.execute_effects(code, false, 0, |_| ())?;
+        }
         notes.set_dummy_note_active(was_dummy_note_active);
         Ok(())
     }
 
+    /// Runs the effects section on the mix of every voice, which must already be written to the
+    /// `voices_in` buffer of the code along with the rest of its inputs. `on_output` receives the
+    /// resulting audio, which is in the same format as the mix. If `do_feedback` is true, the
+    /// feedback of the section is written to `effects_feedback`.
+    fn execute_effects(
+        &mut self,
+        code: LoadedCode,
+        do_feedback: bool,
+        view_index: usize,
+        on_output: impl FnOnce(&[f32]),
+    ) -> Result<(), String> {
+        self.write_options(code, do_feedback, 0, 0, view_index)?;
+        let mut feedback = std::mem::take(&mut self.effects_feedback);
+        let res = self.base.call_fn(
+            &code.path("exec_effects"),
+            |_, _| Ok(()),
+            |frame, output| {
+                if do_feedback {
+                    if let Err(err) = read_feedback_data(frame, output, view_index, &mut feedback)?
+                    {
+                        return Ok(Err(err));
+                    }
+                }
+                let audio = match output.get_nth_field(frame, 0) {
+                    Ok(v) => v,
+                    Err(err) => {
+                        return Ok(Err(format!(
+                            "ERROR: Failed to retrieve effects output, caused by:\n{:?}",
+                            err
+                        )))
+                    }
+                };
+                let audio = match audio.cast::<TypedArray<'_, '_, f32>>() {
+                    Ok(v) => v,
+                    Err(err) => {
+                        return Ok(Err(format!(
+                            "ERROR: effects output is not expected type, caused by:\n{:?}",
+                            err
+                        )))
+                    }
+                };
+                on_output(audio.inline_data(frame)?.into_slice());
+                Ok(Ok(()))
+            },
+        );
+        self.effects_feedback = feedback;
+        res.map_err(Self::beautify_stack_trace)?
+    }
+
     /// This handles everything from global setup, note iteration, program execution, note teardown,
-    /// and finally global teardown. Returns true if feedback data was updated. View index is which
-    /// module's outputs should be retrieved.
+    /// and finally global teardown. Returns true if feedback data was updated, in which case it can
+    /// be retrieved with `replace_feedback`. View index is which module's outputs should be
+    /// retrieved.
     pub fn execute(
         &mut self,
         do_feedback: bool,
@@ -359,15 +1434,98 @@ impl AudiobenchExecutor {
         notes: &mut NoteTracker,
         dyn_data: &[IOData],
         audio_output: &mut [f32],
-    ) -> Result<Option<FeedbackData>, String> {
-        for index in std::mem::take(&mut notes.static_indexes_to_reset) {
-            self.reset_static_data(index)?;
-        }
-
+    ) -> Result<bool, String> {
         let channels = self.parameters.channels;
         let buf_len = self.parameters.buffer_length;
         assert!(audio_output.len() == buf_len * channels);
 
+        if !self.loaded {
+            for sample in audio_output.iter_mut() {
+                *sample = 0.0;
+            }
+            notes.advance_all_notes(&self.parameters, global_data);
+            return Ok(false);
+        }
+        // These only belong to notes which just started, so they start from scratch in the code
+        // which is fading out as well.
+        // Indexing instead of taking the vector keeps its capacity for the next notes.
+        for position in 0..notes.static_indexes_to_reset.len() {
+            let index = notes.static_indexes_to_reset[position];
+            self.reset_static_data(self.current_code, index)?;
+            if let Some(fading) = &self.fading_code {
+                self.reset_static_data(fading.code, index)?;
+            }
+        }
+        notes.static_indexes_to_reset.clear();
+
+        let has_feedback = self.execute_code(
+            self.current_code,
+            do_feedback,
+            view_index,
+            global_data,
+            notes,
+            dyn_data,
+            true,
+            audio_output,
+        )?;
+
+        if let Some(mut fading) = self.fading_code.take() {
+            // Taken out of self so that execute_code can borrow the executor mutably.
+            let mut faded_output = std::mem::take(&mut self.faded_output);
+            let result = self.execute_code(
+                fading.code,
+                false,
+                view_index,
+                global_data,
+                notes,
+                &fading.dyn_data[..],
+                false,
+                &mut faded_output[..],
+            );
+            if let Err(err) = result {
+                self.faded_output = faded_output;
+                return Err(err);
+            }
+            let fade_samples =
+                ((CODE_CROSSFADE_TIME * self.parameters.sample_rate as f32) as usize).max(1);
+            for (i, (new, old)) in audio_output.iter_mut().zip(faded_output.iter()).enumerate() {
+                let amount = (fading.progress + i / channels) as f32 / fade_samples as f32;
+                let amount = amount.min(1.0);
+                *new = *new * amount + old * (1.0 - amount);
+            }
+            self.faded_output = faded_output;
+            fading.progress += buf_len;
+            if fading.progress < fade_samples {
+                self.fading_code = Some(fading);
+            }
+        }
+
+        notes.advance_all_notes(&self.parameters, global_data);
+        Ok(has_feedback)
+    }
+
+    /// Swaps the feedback data produced by the last call to `execute` with `spare`, so that the
+    /// vectors of `spare` can be reused the next time feedback data is produced.
+    pub fn replace_feedback(&mut self, spare: FeedbackData) -> FeedbackData {
+        std::mem::replace(&mut self.feedback, spare)
+    }
+
+    /// Renders every active note and the effects section using one particular piece of loaded
+    /// code. If `update_notes` is false, this will not change how loud notes are considered to be.
+    /// Returns true if `feedback` was updated.
+    fn execute_code(
+        &mut self,
+        code: LoadedCode,
+        do_feedback: bool,
+        view_index: usize,
+        global_data: &GlobalData,
+        notes: &mut NoteTracker,
+        dyn_data: &[IOData],
+        update_notes: bool,
+        audio_output: &mut [f32],
+    ) -> Result<bool, String> {
+        let channels = self.parameters.channels;
+        let buf_len = self.parameters.buffer_length;
         for i in 0..buf_len * channels {
             audio_output[i] = 0.0;
         }
@@ -376,70 +1534,52 @@ impl AudiobenchExecutor {
         } else {
             None
         };
-        let mut feedback_data = None;
+        let mut has_feedback = false;
 
         let pitch_mul = (2.0f32).powf(global_data.pitch_wheel * 7.0 / 12.0);
-        let mut is_dummy = notes.dummy_note.is_some();
+        let fade_samples = NoteTracker::steal_fade_samples(&self.parameters);
+        // Every voice of every note is rendered by a single call, so collect all their inputs
+        // first. feedback_voice is the 1-based Julia index of the voice to get feedback from, or
+        // zero for none.
+        self.note_inputs.clear();
+        self.static_indexes.clear();
+        let mut feedback_voice = 0;
         for note in notes.active_notes_mut() {
-            let note_input = NoteInput::from(&note.data, &self.parameters, pitch_mul);
-            let static_index = note.static_index;
-            let do_feedback = feedback_note == Some(static_index);
+            for voice in note.voices.iter() {
+                self.note_inputs.push(NoteInput::from(
+                    &note.data,
+                    &self.parameters,
+                    global_data,
+                    pitch_mul,
+                    voice,
+                ));
+                self.static_indexes.push(voice.static_index as i64);
+                if feedback_note == Some(voice.static_index) {
+                    feedback_voice = self.static_indexes.len();
+                }
+            }
+        }
+        self.write_global_inputs(code, global_data)?;
+        self.write_dyn_data(code, dyn_data)?;
 
+        if self.static_indexes.len() > 0 {
+            let num_voices = self.static_indexes.len();
+            self.write_voice_inputs(code)?;
+            self.write_options(code, false, feedback_voice, num_voices, view_index)?;
+            let skip_dummy = notes.dummy_note.is_some();
+            let mut feedback = std::mem::take(&mut self.feedback);
             let res = self.base.call_fn(
-                &["Main", "Generated", "exec"],
-                |frame, inputs| {
-                    inputs.append(&mut global_data.as_julia_values(frame)?);
-                    inputs.push(Value::new(frame, do_feedback)?);
-                    inputs.push(Value::new(frame, note_input)?);
-                    inputs.push(Value::new(frame, static_index)?);
-                    inputs.push(Value::new(frame, view_index)?);
-                    for item in dyn_data {
-                        inputs.push(item.as_julia_value(frame)?);
-                    }
-                    Ok(())
-                },
+                &code.path("exec_voices"),
+                |_, _| Ok(()),
                 |frame, output| {
-                    if do_feedback {
-                        let julia_feedback = match output.get_nth_field(frame, 1) {
-                            Ok(v) => v,
-                            Err(err) => {
-                                return Ok(Err(format!(
-                                    "ERROR: Failed to retrieve feedback data, caused by:\n{:?}",
-                                    err
-                                )))
-                            }
-                        };
-                        let mut native_feedback = FeedbackData::default();
-                        for index in 0..julia_feedback.n_fields() {
-                            let field = julia_feedback.get_nth_field(frame, index)?;
-                            let field = field.cast::<TypedArray<'_, '_, f32>>()?;
-                            let field = field.inline_data(frame)?.into_slice();
-                            native_feedback.widget_feeback.push(Vec::from(field));
+                    if feedback_voice > 0 {
+                        if let Err(err) =
+                            read_feedback_data(frame, output, view_index, &mut feedback)?
+                        {
+                            return Ok(Err(err));
                         }
-                        let julia_view_data= match output.get_nth_field(frame, 2) {
-                            Ok(v) => v,
-                            Err(err) => {
-                                return Ok(Err(format!(
-                                    "ERROR: Failed to retrieve output view data, caused by:\n{:?}",
-                                    err
-                                )))
-                            }
-                        };
-                        for index in 0..julia_view_data.n_fields() {
-                            let field = julia_view_data.get_nth_field(frame, index)?;
-                            let field = field.cast::<TypedArray<'_, '_, f32>>()?;
-                            let field = field.inline_data(frame)?.into_slice();
-                            native_feedback.output_view.push(Vec::from(field));
-                        }
-                        native_feedback.output_view_module_index = view_index;
-                        feedback_data = Some(native_feedback);
                     }
 
-                    if is_dummy {
-                        // Don't process the audio of the dummy note.
-                        is_dummy = false;
-                        return Ok(Ok(()));
-                    }
                     // 0-based index, not Julia index.
                     let audio = match output.get_nth_field(frame, 0) {
                         Ok(v) => v,
@@ -460,24 +1600,433 @@ impl AudiobenchExecutor {
                         }
                     };
                     let audio = audio.inline_data(frame)?.into_slice();
-                    let mut silent = true;
-                    for i in 0..buf_len * channels {
-                        audio_output[i] += audio[i];
-                        silent &= audio[i].abs() < SILENT_CUTOFF;
-                    }
-                    if silent {
-                        note.silent_samples += buf_len;
-                    } else {
-                        note.silent_samples = 0;
+                    // Each voice's audio follows the previous voice's audio. There may be room
+                    // for more voices after them.
+                    let mut voice_audio = audio.chunks_exact(buf_len * channels);
+                    let mut is_dummy = skip_dummy;
+                    for note in notes.active_notes_mut() {
+                        if is_dummy {
+                            // Don't process the audio of the dummy note.
+                            is_dummy = false;
+                            for _ in note.voices.iter() {
+                                voice_audio.next();
+                            }
+                            continue;
+                        }
+                        let fade_progress = note.fade_progress;
+                        let mut silent = true;
+                        let mut peak = 0.0f32;
+                        for voice in note.voices.iter() {
+                            let audio = voice_audio.next().unwrap();
+                            for i in 0..buf_len * channels {
+                                let sample = audio[i] * voice.channel_gain(i % channels, channels);
+                                let sample = if let Some(progress) = fade_progress {
+                                    let faded =
+                                        (progress + i / channels) as f32 / fade_samples as f32;
+                                    sample * (1.0 - faded).max(0.0)
+                                } else {
+                                    sample
+                                };
+                                audio_output[i] += sample;
+                                silent &= sample.abs() < SILENT_CUTOFF;
+                                peak = peak.max(sample.abs());
+                            }
+                        }
+                        if update_notes {
+                            note.peak = peak;
+                            if silent {
+                                note.silent_samples += buf_len;
+                            } else {
+                                note.silent_samples = 0;
+                            }
+                        }
                     }
-
                     Ok(Ok(()))
                 },
             );
+            self.feedback = feedback;
             res.map_err(Self::beautify_stack_trace)??;
+            has_feedback = feedback_voice > 0;
         }
 
-        notes.advance_all_notes(&self.parameters, global_data);
-        Ok(feedback_data)
+        if code.has_effects {
+            self.base
+                .write_global_array(&code.path("voices_in"), &audio_output[..])?;
+            self.execute_effects(code, do_feedback, view_index, |audio| {
+                audio_output.copy_from_slice(audio)
+            })?;
+            if do_feedback {
+                if has_feedback {
+                    self.feedback.merge(&self.effects_feedback);
+                } else {
+                    std::mem::swap(&mut self.feedback, &mut self.effects_feedback);
+                }
+                has_feedback = true;
+            }
+        }
+
+        Ok(has_feedback)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(value: f32, expected: f32) {
+        assert!(
+            (value - expected).abs() < 1e-4,
+            "{} is not close to {}",
+            value,
+            expected
+        );
+    }
+
+    fn unison_voices(count: usize, detune: f32, spread: f32) -> UnisonVoices {
+        let mut notes = NoteTracker::new();
+        notes.set_voice_settings(VoiceSettings {
+            unison_voices: count,
+            unison_detune: detune,
+            unison_spread: spread,
+            unison_random_phase: false,
+            ..Default::default()
+        });
+        notes.reserve_unison_voices()
+    }
+
+    #[test]
+    fn unison_voices_are_spread_evenly() {
+        let voices = unison_voices(5, 20.0, 0.5);
+        let mut static_indexes: Vec<_> = voices.iter().map(|v| v.static_index).collect();
+        static_indexes.sort();
+        assert_eq!(static_indexes, vec![0, 1, 2, 3, 4]);
+        for (voice, &position) in voices.iter().zip([-1.0, -0.5, 0.0, 0.5, 1.0].iter()) {
+            assert_close(voice.detune.log2() * 1200.0, position * 10.0);
+            assert_close(voice.pan, position * 0.5);
+            assert_close(voice.gain, 1.0 / 5f32.sqrt());
+            assert_eq!(voice.phase, 0.0);
+        }
+    }
+
+    #[test]
+    fn single_unison_voice_is_centered() {
+        let voices = unison_voices(1, 50.0, 1.0);
+        assert_eq!(voices.len(), 1);
+        assert_eq!(voices[0].detune, 1.0);
+        assert_eq!(voices[0].pan, 0.0);
+        assert_eq!(voices[0].gain, 1.0);
+    }
+
+    #[test]
+    fn unison_voices_are_limited_by_max_voices() {
+        let mut notes = NoteTracker::new();
+        notes.set_voice_settings(VoiceSettings {
+            max_voices: 3,
+            unison_voices: 8,
+            ..Default::default()
+        });
+        assert_eq!(notes.reserve_unison_voices().len(), 3);
+    }
+
+    #[test]
+    fn panning_keeps_power_constant() {
+        for &pan in &[-1.0, -0.6, 0.0, 0.25, 1.0] {
+            let voice = UnisonVoice {
+                pan,
+                ..UnisonVoice::centered(0)
+            };
+            let left = voice.channel_gain(0, 2);
+            let right = voice.channel_gain(1, 2);
+            assert_close(left * left + right * right, 2.0);
+            assert_close(voice.channel_gain(0, 1), 1.0);
+            // Only the front left and right speakers are panned between.
+            assert_close(voice.channel_gain(2, 6), 1.0);
+            assert_close(voice.channel_gain(4, 6), 1.0);
+        }
+        let centered = UnisonVoice::centered(0);
+        assert_close(centered.channel_gain(0, 2), 1.0);
+        assert_close(centered.channel_gain(1, 2), 1.0);
+        let left = UnisonVoice {
+            pan: -1.0,
+            ..UnisonVoice::centered(0)
+        };
+        assert_close(left.channel_gain(1, 6), 0.0);
+    }
+
+    #[test]
+    fn feedback_comes_from_the_middle_voice() {
+        let mut notes = NoteTracker::new();
+        notes.start_dummy_note();
+        let data = notes.dummy_note.unwrap().data;
+        let note = CompleteNoteData::new(data, unison_voices(3, 20.0, 1.0));
+        assert_eq!(note.feedback_voice().detune, 1.0);
+        assert_eq!(note.feedback_voice().pan, 0.0);
+    }
+
+    const PARAMS: GlobalParameters = GlobalParameters {
+        channels: 2,
+        buffer_length: 512,
+        sample_rate: 44100,
+    };
+
+    fn tracker(settings: VoiceSettings) -> NoteTracker {
+        let mut notes = NoteTracker::new();
+        notes.set_voice_settings(settings);
+        notes
+    }
+
+    /// Lets a buffer pass as if every note had been rendered.
+    fn advance(notes: &mut NoteTracker) {
+        notes.advance_all_notes(&PARAMS, &GlobalData::new());
+    }
+
+    fn key_with_pitch(notes: &NoteTracker, pitch: f32) -> usize {
+        (0..NUM_MIDI_NOTES)
+            .find(|&key| notes.key_frequencies.get(key) == Some(pitch))
+            .unwrap()
+    }
+
+    fn held_keys(notes: &NoteTracker) -> Vec<usize> {
+        (0..NUM_MIDI_NOTES)
+            .filter(|&key| notes.held_notes[key].is_some())
+            .collect()
+    }
+
+    /// Keys whose notes were stolen (and are fading out) if `stolen` is true, or which were
+    /// released normally if it is false.
+    fn decaying_keys(notes: &NoteTracker, stolen: bool) -> Vec<usize> {
+        notes
+            .decaying_notes
+            .iter()
+            .filter(|note| note.fade_progress.is_some() == stolen)
+            .map(|note| key_with_pitch(notes, note.data.pitch))
+            .collect()
+    }
+
+    /// Plays each key one buffer after the previous one, so that the first key is the oldest.
+    fn play(notes: &mut NoteTracker, keys: &[usize]) {
+        for &key in keys {
+            notes.start_note(key, 1.0, 0);
+            advance(notes);
+        }
+    }
+
+    fn steal_with(policy: StealingPolicy) -> NoteTracker {
+        let mut notes = tracker(VoiceSettings {
+            max_voices: 3,
+            stealing_policy: policy,
+            ..Default::default()
+        });
+        play(&mut notes, &[64, 60, 67]);
+        notes
+    }
+
+    #[test]
+    fn voice_limit_steals_oldest() {
+        let mut notes = steal_with(StealingPolicy::Oldest);
+        notes.start_note(62, 1.0, 0);
+        assert_eq!(held_keys(&notes), vec![60, 62, 67]);
+        assert_eq!(decaying_keys(&notes, true), vec![64]);
+    }
+
+    #[test]
+    fn voice_limit_steals_lowest_and_highest() {
+        let mut notes = steal_with(StealingPolicy::Lowest);
+        notes.start_note(62, 1.0, 0);
+        assert_eq!(decaying_keys(&notes, true), vec![60]);
+
+        let mut notes = steal_with(StealingPolicy::Highest);
+        notes.start_note(62, 1.0, 0);
+        assert_eq!(decaying_keys(&notes, true), vec![67]);
+    }
+
+    #[test]
+    fn voice_limit_steals_quietest() {
+        let mut notes = steal_with(StealingPolicy::Quietest);
+        for &(key, peak) in &[(64, 0.5), (60, 0.1), (67, 0.9)] {
+            notes.held_notes[key].as_mut().unwrap().peak = peak;
+        }
+        notes.start_note(62, 1.0, 0);
+        assert_eq!(decaying_keys(&notes, true), vec![60]);
+    }
+
+    #[test]
+    fn unrendered_notes_are_not_the_quietest() {
+        let mut notes = tracker(VoiceSettings {
+            max_voices: 2,
+            stealing_policy: StealingPolicy::Quietest,
+            ..Default::default()
+        });
+        play(&mut notes, &[60]);
+        notes.held_notes[60].as_mut().unwrap().peak = 0.5;
+        // 64 has not been rendered yet, so nothing is known about how loud it is.
+        notes.start_note(64, 1.0, 0);
+        notes.start_note(67, 1.0, 0);
+        assert_eq!(held_keys(&notes), vec![64, 67]);
+        assert_eq!(decaying_keys(&notes, true), vec![60]);
+    }
+
+    #[test]
+    fn voice_limit_steals_released_notes_first() {
+        let mut notes = steal_with(StealingPolicy::ReleaseFirst);
+        // 67 is the youngest note, but it is the only one which is not held.
+        notes.release_note(67, 0.5, 0);
+        notes.start_note(62, 1.0, 0);
+        assert_eq!(held_keys(&notes), vec![60, 62, 64]);
+        assert_eq!(decaying_keys(&notes, true), vec![67]);
+    }
+
+    #[test]
+    fn stolen_notes_fade_out() {
+        let mut notes = steal_with(StealingPolicy::Oldest);
+        notes.start_note(62, 1.0, 0);
+        // Stealing fades over less than a buffer, the note is gone after it is rendered.
+        advance(&mut notes);
+        advance(&mut notes);
+        assert!(notes.decaying_notes.is_empty());
+        assert_eq!(notes.reserved_static_indexes.len(), 3);
+    }
+
+    fn mono(priority: NotePriority, legato: bool, glide_time: f32) -> NoteTracker {
+        tracker(VoiceSettings {
+            mono: true,
+            note_priority: priority,
+            legato,
+            glide_time,
+            ..Default::default()
+        })
+    }
+
+    fn mono_pitch(notes: &NoteTracker) -> f32 {
+        notes.held_notes[notes.mono_key.unwrap()]
+            .as_ref()
+            .unwrap()
+            .data
+            .pitch
+    }
+
+    #[test]
+    fn mono_release_falls_back_to_held_key() {
+        let mut notes = mono(NotePriority::Last, false, 0.0);
+        play(&mut notes, &[60, 64]);
+        assert_eq!(held_keys(&notes), vec![64]);
+        notes.release_note(64, 0.5, 0);
+        assert_eq!(held_keys(&notes), vec![60]);
+        assert_eq!(mono_pitch(&notes), notes.key_frequencies.get(60).unwrap());
+        assert_eq!(notes.held_notes[60].as_ref().unwrap().data.key, 60);
+        assert!(notes.decaying_notes.is_empty());
+        notes.release_note(60, 0.5, 0);
+        assert_eq!(held_keys(&notes), vec![]);
+        assert_eq!(decaying_keys(&notes, false), vec![60]);
+    }
+
+    #[test]
+    fn mono_note_priority() {
+        let mut notes = mono(NotePriority::Low, false, 0.0);
+        play(&mut notes, &[64, 60, 67]);
+        assert_eq!(held_keys(&notes), vec![60]);
+        notes.release_note(60, 0.5, 0);
+        assert_eq!(held_keys(&notes), vec![64]);
+
+        let mut notes = mono(NotePriority::High, false, 0.0);
+        play(&mut notes, &[64, 67, 60]);
+        assert_eq!(held_keys(&notes), vec![67]);
+        notes.release_note(64, 0.5, 0);
+        assert_eq!(held_keys(&notes), vec![67]);
+    }
+
+    #[test]
+    fn legato_does_not_restart_the_note() {
+        let mut notes = mono(NotePriority::Last, true, 0.0);
+        play(&mut notes, &[60]);
+        notes.start_note(64, 1.0, 0);
+        let data = &notes.held_notes[64].as_ref().unwrap().data;
+        assert!(!data.start_trigger);
+        assert!(data.elapsed_samples > 0);
+
+        let mut notes = mono(NotePriority::Last, false, 0.0);
+        play(&mut notes, &[60]);
+        notes.start_note(64, 1.0, 100);
+        let data = &notes.held_notes[64].as_ref().unwrap().data;
+        assert!(data.start_trigger);
+        assert_eq!(data.elapsed_samples, 0);
+        assert_eq!(data.start_offset, 100);
+    }
+
+    #[test]
+    fn glide_slides_to_the_new_pitch() {
+        let buffer_time = PARAMS.buffer_length as f32 / PARAMS.sample_rate as f32;
+        let mut notes = mono(NotePriority::Last, true, buffer_time * 4.0);
+        play(&mut notes, &[60]);
+        let from = notes.key_frequencies.get(60).unwrap();
+        let to = notes.key_frequencies.get(72).unwrap();
+        notes.start_note(72, 1.0, 0);
+        assert_eq!(mono_pitch(&notes), from);
+        // The glide is linear in octaves, so it is halfway after half of the glide time.
+        advance(&mut notes);
+        advance(&mut notes);
+        assert_close(mono_pitch(&notes).log2(), from.log2() + 0.5);
+        for _ in 0..3 {
+            advance(&mut notes);
+        }
+        assert_close(mono_pitch(&notes).log2(), to.log2());
+        assert!(notes.held_notes[72].as_ref().unwrap().glide.is_none());
+    }
+
+    #[test]
+    fn sustain_up_releases_only_unheld_keys() {
+        let mut notes = tracker(Default::default());
+        play(&mut notes, &[60, 64]);
+        notes.set_pedal(Pedal::Sustain, true, 0);
+        notes.release_note(60, 0.5, 0);
+        assert_eq!(held_keys(&notes), vec![60, 64]);
+        notes.set_pedal(Pedal::Sustain, false, 10);
+        assert_eq!(held_keys(&notes), vec![64]);
+        assert_eq!(decaying_keys(&notes, false), vec![60]);
+        let release = &notes.decaying_notes[0].data;
+        assert_eq!(release.release_velocity, 0.5);
+        assert_eq!(release.release_offset, 10);
+    }
+
+    #[test]
+    fn restriking_a_sustained_key_cuts_the_old_note() {
+        let mut notes = tracker(Default::default());
+        notes.set_pedal(Pedal::Sustain, true, 0);
+        play(&mut notes, &[60]);
+        notes.release_note(60, 0.5, 0);
+        notes.start_note(60, 1.0, 0);
+        assert_eq!(decaying_keys(&notes, false), vec![60]);
+        assert!(notes.decaying_notes[0].data.release_trigger);
+        let note = &notes.held_notes[60].as_ref().unwrap().data;
+        assert!(note.start_trigger);
+        assert_eq!(note.elapsed_samples, 0);
+        // The new note is held by the key, not the pedal.
+        notes.set_pedal(Pedal::Sustain, false, 0);
+        assert_eq!(held_keys(&notes), vec![60]);
+    }
+
+    #[test]
+    fn sostenuto_holds_only_keys_down_when_pressed() {
+        let mut notes = tracker(Default::default());
+        play(&mut notes, &[60]);
+        notes.set_pedal(Pedal::Sostenuto, true, 0);
+        play(&mut notes, &[64]);
+        notes.release_note(60, 0.5, 0);
+        notes.release_note(64, 0.5, 0);
+        assert_eq!(held_keys(&notes), vec![60]);
+        assert_eq!(decaying_keys(&notes, false), vec![64]);
+        notes.set_pedal(Pedal::Sostenuto, false, 0);
+        assert_eq!(held_keys(&notes), vec![]);
+    }
+
+    #[test]
+    fn sostenuto_ignores_keys_only_held_by_sustain() {
+        let mut notes = tracker(Default::default());
+        notes.set_pedal(Pedal::Sustain, true, 0);
+        play(&mut notes, &[60]);
+        notes.release_note(60, 0.5, 0);
+        notes.set_pedal(Pedal::Sostenuto, true, 0);
+        notes.set_pedal(Pedal::Sustain, false, 0);
+        assert_eq!(held_keys(&notes), vec![]);
     }
 }
@@ -115,6 +115,7 @@ pub enum TabArchetype {
     ModuleBrowser(Rc<graph::ModuleGraph>),
     LibraryInfo,
     MessageLog,
+    PatchSettings,
 }
 
 impl TabArchetype {
@@ -158,6 +159,13 @@ impl TabArchetype {
                     false
                 }
             }
+            PatchSettings => {
+                if let PatchSettings = other {
+                    true
+                } else {
+                    false
+                }
+            }
         }
     }
 
@@ -168,6 +176,7 @@ impl TabArchetype {
             Self::ModuleBrowser(add_to) => Rc::new(ModuleBrowser::new(parent, add_to)) as _,
             Self::LibraryInfo => Rc::new(LibraryInfo::new(parent)) as _,
             Self::MessageLog => Rc::new(MessageLog::new(parent)) as _,
+            Self::PatchSettings => Rc::new(PatchSettings::new(parent)) as _,
         }
     }
 }
@@ -377,6 +386,7 @@ impl WidgetImpl<Renderer, DropTarget> for Root {
 
     fn draw_impl(self: &Rc<Self>, renderer: &mut Renderer) {
         self.with_gui_state_mut(|state| {
+            state.engine.borrow().sync_host_parameters();
             let new_errors = state.engine.borrow_mut().take_posted_errors();
             for error in new_errors {
                 state.add_error_message(error);
@@ -393,6 +403,9 @@ impl WidgetImpl<Renderer, DropTarget> for Root {
         let message = if julia_status == Status::Busy {
             renderer.set_color(&COLOR_WARNING);
             "Working..."
+        } else if julia_status == Status::RecoverableError {
+            renderer.set_color(&COLOR_ERROR);
+            "Error, see message log"
         } else if julia_status == Status::Error {
             renderer.set_color(&COLOR_ERROR);
             "Unrecoverable Error :("
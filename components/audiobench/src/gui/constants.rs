@@ -106,6 +106,8 @@ pub const COLOR_WARNING: (u8, u8, u8) = hex_color(0xCC7722);
 /// represented as text since it is white and COLOR_EDITABLE is red, which is less readable.
 pub const COLOR_EDITABLE: (u8, u8, u8) = hex_color(0xFF0033);
 pub const COLOR_AUTOMATION: (u8, u8, u8) = hex_color(0xC7D5E8);
+/// Used for automation lanes that follow one of the macros of a patch.
+pub const COLOR_MACRO: (u8, u8, u8) = hex_color(0x54bdff);
 // pub const COLOR_AUTOMATION_FOCUSED: (u8, u8, u8) = hex_color(0x54bdff);
 /// The darkest foreground color.
 pub const COLOR_FG0: (u8, u8, u8) = (0x77, 0x77, 0x77);
@@ -134,9 +134,9 @@ impl WidgetImpl<Renderer, DropTarget> for DurationBox {
         g.set_color(&COLOR_BG0);
         g.draw_rounded_rect(0, (W, H), CS);
         {
-            let is_beats = state.mode_control.borrow().is_beat_synchronized();
+            let units = state.mode_control.borrow().get_units();
             let val = state.duration_control.borrow().get_formatted_value();
-            let val = format!("{}{}", val, if is_beats { "b" } else { "s" });
+            let val = format!("{}{}", val, units.suffix());
             g.set_color(&COLOR_FG1);
             g.draw_text(
                 BIG_FONT_SIZE,
@@ -2,7 +2,9 @@ use crate::{
     engine::controls::{Control, FloatInRangeControl},
     gui::{
         constants::*,
-        module_widgets::ModuleWidgetImpl,
+        module_widgets::{
+            editor_tooltip, float_control_tooltip, lane_color, lane_name, ModuleWidgetImpl,
+        },
         mouse_behaviors::{ManipulateFIRControl, ManipulateLane},
         top_level::graph::{Module, ModuleGraph},
         InteractionHint, Tooltip,
@@ -87,6 +89,7 @@ impl WidgetImpl<Renderer, DropTarget> for HSlider {
             let graph = Rc::clone(&self.parents.graph);
             let menu = HSliderEditor::new(
                 self,
+                Rc::clone(&graph),
                 Rc::clone(&state.control),
                 Rc::clone(&state.value),
                 pos,
@@ -112,8 +115,9 @@ impl WidgetImpl<Renderer, DropTarget> for HSlider {
     }
 
     fn on_hover_impl(self: &Rc<Self>, _pos: Vec2D) -> Option<()> {
+        let state = self.state.borrow();
         let tooltip = Tooltip {
-            text: self.state.borrow().tooltip.clone(),
+            text: float_control_tooltip(&state.tooltip, &*state.control.borrow()),
             interaction: vec![
                 InteractionHint::LeftClickAndDrag,
                 InteractionHint::RightClick,
@@ -147,7 +151,7 @@ impl WidgetImpl<Renderer, DropTarget> for HSlider {
         let zero_point = value_to_point(control.range, state.size.x, 0.0);
         // If manual, show the manual value. If automated, show the most recent value recorded
         // from when a note was actually playing.
-        let value = if control.automation.len() > 0 {
+        let value = if control.is_automated() {
             *state.value.borrow()
         } else {
             control.value
@@ -166,15 +170,16 @@ impl WidgetImpl<Renderer, DropTarget> for HSlider {
         g.set_alpha(1.0);
         g.set_color(&COLOR_FG1);
 
-        if control.automation.len() > 0 {
-            let num_lanes = control.automation.len() as f32;
+        if control.is_automated() {
+            let num_lanes = control.num_lanes() as f32;
             let lane_size = (grid(1) - CS * 2.0) / num_lanes;
             let lane_size = lane_size.min(KNOB_MAX_LANE_SIZE).max(2.0);
-            for (index, lane) in control.automation.iter().enumerate() {
-                g.set_color(&COLOR_AUTOMATION);
+            for index in 0..control.num_lanes() {
+                g.set_color(lane_color(&*control, index));
+                let lane_range = control.lane_range(index);
                 let index = index as f32;
-                let start_point = value_to_point(control.range, state.size.x, lane.range.0);
-                let end_point = value_to_point(control.range, state.size.x, lane.range.1);
+                let start_point = value_to_point(control.range, state.size.x, lane_range.0);
+                let end_point = value_to_point(control.range, state.size.x, lane_range.1);
                 g.draw_rect(
                     // -0.5 makes it crisper at default zoom.
                     (start_point.min(end_point), CS + (lane_size) * index - 0.5),
@@ -199,6 +204,7 @@ impl ModuleWidgetImpl for HSlider {
 scui::widget! {
     pub HSliderEditor
     State {
+        graph: Rc<ModuleGraph>,
         control: Rcrc<FloatInRangeControl>,
         value: Rcrc<f32>,
         pos: Vec2D,
@@ -211,6 +217,7 @@ scui::widget! {
 impl HSliderEditor {
     fn new(
         parent: &impl HSliderEditorParent,
+        graph: Rc<ModuleGraph>,
         control: Rcrc<FloatInRangeControl>,
         value: Rcrc<f32>,
         center_pos: Vec2D,
@@ -218,7 +225,7 @@ impl HSliderEditor {
         label: String,
         tooltip: String,
     ) -> Rc<Self> {
-        let num_channels = control.borrow().automation.len().max(0) as f32;
+        let num_channels = control.borrow().num_lanes() as f32;
         let required_height = grid(1)
             + KNOB_MENU_LANE_GAP
             + (KNOB_MENU_LANE_SIZE + KNOB_MENU_LANE_GAP) * num_channels;
@@ -227,6 +234,7 @@ impl HSliderEditor {
             required_height + GRID_P * 2.0,
         );
         let state = HSliderEditorState {
+            graph,
             control,
             value,
             pos: center_pos - size / 2.0,
@@ -267,17 +275,28 @@ impl WidgetImpl<Renderer, DropTarget> for HSliderEditor {
         const LS: f32 = KNOB_MENU_LANE_SIZE;
         let row = ((pos.y - GP) / (GAP + LS)).max(0.0) as usize;
         let control_ref = state.control.borrow();
-        if row >= control_ref.automation.len() {
+        if row >= control_ref.num_lanes() {
             // Clicked the actual control...
+            if mods.right_click && mods.snap {
+                let control = Rc::clone(&state.control);
+                let engine = self.with_gui_state(|state| Rc::clone(&state.engine));
+                return OnClickBehavior::wrap(move || {
+                    engine.borrow_mut().toggle_host_parameter(&control)
+                });
+            } else if mods.right_click {
+                let control = Rc::clone(&state.control);
+                let graph = Rc::clone(&state.graph);
+                return OnClickBehavior::wrap(move || graph.toggle_midi_learn(&control));
+            }
             return Some(Box::new(ManipulateFIRControl::new(
                 self,
                 Rc::clone(&state.control),
             )));
         }
         // Lanes are rendered backwards, flip it back around.
-        let lane = control_ref.automation.len() - row - 1;
+        let lane = control_ref.num_lanes() - row - 1;
         let point = pos.x;
-        let lane_range = control_ref.automation[lane].range;
+        let lane_range = control_ref.lane_range(lane);
         let mut min_point = self.value_to_point(lane_range.0);
         let mut max_point = self.value_to_point(lane_range.1);
         let ends_flipped = lane_range.0 > lane_range.1;
@@ -291,7 +310,7 @@ impl WidgetImpl<Renderer, DropTarget> for HSliderEditor {
                 let control = Rc::clone(&state.control);
                 let engine = self.with_gui_state(|state| Rc::clone(&state.engine));
                 return OnClickBehavior::wrap(move || {
-                    control.borrow_mut().automation.remove(lane);
+                    control.borrow_mut().remove_lane(lane);
                     engine.borrow_mut().regenerate_code()
                 });
             } else {
@@ -317,13 +336,14 @@ impl WidgetImpl<Renderer, DropTarget> for HSliderEditor {
         const LS: f32 = KNOB_MENU_LANE_SIZE;
         let row = ((pos.y - GP) / (GAP + LS)).max(0.0) as usize;
         let control_ref = state.control.borrow();
-        if row >= control_ref.automation.len() {
+        if row >= control_ref.num_lanes() {
             // Inside the actual control
             let tooltip = Tooltip {
-                text: state.tooltip.clone(),
+                text: editor_tooltip(&state.tooltip, &*control_ref),
                 interaction: vec![
                     InteractionHint::LeftClickAndDrag,
                     InteractionHint::DoubleClick,
+                    InteractionHint::RightClick,
                 ],
             };
             self.with_gui_state_mut(|state| {
@@ -332,14 +352,14 @@ impl WidgetImpl<Renderer, DropTarget> for HSliderEditor {
             return Some(());
         }
         // Lanes are rendered backwards, flip it back around.
-        let lane = control_ref.automation.len() - row - 1;
+        let lane = control_ref.num_lanes() - row - 1;
         let point = pos.x;
         let tooltip = if point < GP + grid(1) || point > state.size.x - GP - grid(1) {
             // Clicked outside the lane.
             Tooltip {
                 text: format!(
-                    "Automation lane #{}, click + drag to move one of the ends.",
-                    lane + 1,
+                    "{}, click + drag to move one of the ends.",
+                    lane_name(&state.graph, &*control_ref, lane),
                 ),
                 interaction: vec![
                     InteractionHint::LeftClickAndDrag,
@@ -349,8 +369,8 @@ impl WidgetImpl<Renderer, DropTarget> for HSliderEditor {
         } else {
             Tooltip {
                 text: format!(
-                    "Automation lane #{}, click + drag on empty space to move one end at a time.",
-                    lane + 1,
+                    "{}, click + drag on empty space to move one end at a time.",
+                    lane_name(&state.graph, &*control_ref, lane),
                 ),
                 interaction: vec![
                     InteractionHint::LeftClickAndDrag,
@@ -393,14 +413,15 @@ impl WidgetImpl<Renderer, DropTarget> for HSliderEditor {
 
         const GAP: f32 = KNOB_MENU_LANE_GAP;
         const LS: f32 = KNOB_MENU_LANE_SIZE;
-        for (index, lane) in control.automation.iter().rev().enumerate() {
+        for (index, lane) in (0..control.num_lanes()).rev().enumerate() {
             g.set_color(&COLOR_BG0);
             let y = GP + (LS + GAP) * index as f32;
             g.draw_rounded_rect((x, y), (width, LS), CS);
-            g.set_color(&COLOR_AUTOMATION);
-            let min_point = self.value_to_point(lane.range.0);
-            let max_point = self.value_to_point(lane.range.1);
-            let height = if lane.range.0 > lane.range.1 {
+            g.set_color(lane_color(&*control, lane));
+            let lane_range = control.lane_range(lane);
+            let min_point = self.value_to_point(lane_range.0);
+            let max_point = self.value_to_point(lane_range.1);
+            let height = if lane_range.0 > lane_range.1 {
                 LS / 2.0
             } else {
                 LS
@@ -421,7 +442,11 @@ impl WidgetImpl<Renderer, DropTarget> for HSliderEditor {
             1,
             &state.label,
         );
-        let value_text = format!("{}{}", format_decimal(value, 3), control.suffix);
+        let value_text = if state.graph.is_learning_midi(&state.control) {
+            "Learning...".to_owned()
+        } else {
+            format!("{}{}", format_decimal(value, 3), control.suffix)
+        };
         g.draw_text(
             FONT_SIZE,
             (x + GP, boty),
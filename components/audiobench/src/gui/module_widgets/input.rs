@@ -1,11 +1,12 @@
 use super::ModuleWidgetImpl;
 use crate::gui::constants::*;
+use crate::gui::mouse_behaviors::ManipulateIntBox;
 use crate::gui::top_level::graph::{ConnectToControl, Module, ModuleGraph};
 use crate::gui::{InteractionHint, Tooltip};
 use crate::scui_config::{DropTarget, MaybeMouseBehavior, Renderer};
 use crate::{
     engine::{
-        controls::{Control, InputControl},
+        controls::{Control, InputControl, UpdateRequest, NUM_MIDI_CCS},
         UiThreadEngine,
     },
     registry::yaml::YamlNode,
@@ -96,6 +97,15 @@ impl Input {
         };
         Rc::new(Self::create(parent, state))
     }
+
+    fn uses_midi_cc(self: &Rc<Self>) -> bool {
+        let state = self.state.borrow();
+        let control = state.control.borrow();
+        control
+            .get_used_default()
+            .map(|default| default.uses_midi_cc())
+            .unwrap_or(false)
+    }
 }
 
 struct InputBehavior {
@@ -138,10 +148,26 @@ impl WidgetImpl<Renderer, DropTarget> for Input {
     fn get_mouse_behavior_impl(
         self: &Rc<Self>,
         _pos: Vec2D,
-        _mods: &MouseMods,
+        mods: &MouseMods,
     ) -> MaybeMouseBehavior {
         let engine = self.with_gui_state(|state| Rc::clone(&state.engine));
         let control = Rc::clone(&self.state.borrow().control);
+        if mods.right_click && self.uses_midi_cc() {
+            let current = control.borrow().get_midi_cc();
+            let callback = Box::new(move |new_cc: i32| {
+                control.borrow_mut().set_midi_cc(new_cc as u8);
+                UpdateRequest::UpdateCode
+            });
+            let max = NUM_MIDI_CCS as i32 - 1;
+            return Some(Box::new(ManipulateIntBox::new(
+                self,
+                callback,
+                0,
+                max,
+                1,
+                current as i32,
+            )));
+        }
         let pos = self.get_pos() + self.parents.module.get_pos() + self.get_size() / 2.0;
         let g = &self.parents.graph;
         let connector = g.connect_to_control_behavior(Rc::clone(&control) as _, pos);
@@ -154,14 +180,17 @@ impl WidgetImpl<Renderer, DropTarget> for Input {
 
     fn on_hover_impl(self: &Rc<Self>, _pos: Vec2D) -> Option<()> {
         let this_state = self.state.borrow();
+        let mut interaction = vec![
+            InteractionHint::LeftClick,
+            InteractionHint::LeftClickAndDrag,
+        ];
+        let mut text = this_state.tooltip.clone();
+        if self.uses_midi_cc() {
+            interaction.push(InteractionHint::RightClick);
+            text.push_str(" Right-click or right-click and drag to pick which MIDI CC is used.");
+        }
         self.with_gui_state_mut(|state| {
-            state.set_tooltip(Tooltip {
-                text: this_state.tooltip.clone(),
-                interaction: vec![
-                    InteractionHint::LeftClick,
-                    InteractionHint::LeftClickAndDrag,
-                ],
-            });
+            state.set_tooltip(Tooltip { text, interaction });
         });
         Some(())
     }
@@ -203,7 +232,7 @@ impl WidgetImpl<Renderer, DropTarget> for Input {
         g.draw_icon(state.icon, (JS + JIP, JIP), JS - JIP * 2.0);
 
         if hovered {
-            if let Some(default) = control.get_used_default() {
+            if let Some(default_name) = control.get_used_default_name() {
                 g.draw_text(
                     FONT_SIZE,
                     (-100.0 - GRID_P, -JS / 2.0),
@@ -218,7 +247,7 @@ impl WidgetImpl<Renderer, DropTarget> for Input {
                     (100.0, JS),
                     (1, -1),
                     1,
-                    &format!("({})", &default.name),
+                    &format!("({})", &default_name),
                 );
             } else {
                 g.draw_text(
@@ -81,6 +81,7 @@ impl WidgetImpl<Renderer, DropTarget> for Knob {
             let graph = Rc::clone(&self.parents.graph);
             let menu = KnobEditor::new(
                 self,
+                Rc::clone(&graph),
                 Rc::clone(&state.control),
                 Rc::clone(&state.value),
                 pos,
@@ -105,8 +106,9 @@ impl WidgetImpl<Renderer, DropTarget> for Knob {
     }
 
     fn on_hover_impl(self: &Rc<Self>, _pos: Vec2D) -> Option<()> {
+        let state = self.state.borrow();
         let tooltip = Tooltip {
-            text: self.state.borrow().tooltip.clone(),
+            text: float_control_tooltip(&state.tooltip, &*state.control.borrow()),
             interaction: vec![
                 InteractionHint::LeftClickAndDrag,
                 InteractionHint::RightClick,
@@ -144,7 +146,7 @@ impl WidgetImpl<Renderer, DropTarget> for Knob {
         let zero_angle = value_to_angle(control.range, 0.0);
         // If manual, show the manual value. If automated, show the most recent value recorded
         // from when a note was actually playing.
-        let value = if control.automation.len() > 0 {
+        let value = if control.is_automated() {
             *state.value.borrow()
         } else {
             control.value
@@ -167,18 +169,19 @@ impl WidgetImpl<Renderer, DropTarget> for Knob {
         g.set_color(&COLOR_FG1);
         g.draw_text(FONT_SIZE, 0, grid(2), (0, 1), 1, &state.label);
 
-        if control.automation.len() > 0 {
-            let num_lanes = control.automation.len() as f32;
+        if control.is_automated() {
+            let num_lanes = control.num_lanes() as f32;
             let lane_size = KNOB_AUTOMATION_SPACE / num_lanes;
             let lane_size = lane_size.min(KNOB_MAX_LANE_SIZE).max(2.0);
-            for (index, lane) in control.automation.iter().enumerate() {
-                g.set_color(&COLOR_AUTOMATION);
+            for index in 0..control.num_lanes() {
+                g.set_color(lane_color(&*control, index));
+                let lane_range = control.lane_range(index);
                 let index = index as f32;
                 let outer_diameter = grid(2) - (KNOB_OUTSIDE_SPACE * 2.0) - lane_size * index * 2.0;
                 let inner_diameter = outer_diameter - (lane_size - KNOB_LANE_GAP) * 2.0;
                 let inset = (grid(2) - outer_diameter) / 2.0;
-                let min_angle = value_to_angle(control.range, lane.range.0);
-                let max_angle = value_to_angle(control.range, lane.range.1);
+                let min_angle = value_to_angle(control.range, lane_range.0);
+                let max_angle = value_to_angle(control.range, lane_range.1);
                 g.draw_pie(inset, outer_diameter, inner_diameter, min_angle, max_angle);
             }
         }
@@ -199,6 +202,7 @@ impl ModuleWidgetImpl for Knob {
 scui::widget! {
     pub(super) KnobEditor
     State {
+        graph: Rc<ModuleGraph>,
         control: Rcrc<FloatInRangeControl>,
         value: Rcrc<f32>,
         pos: Vec2D,
@@ -211,17 +215,19 @@ scui::widget! {
 impl KnobEditor {
     pub(super) fn new(
         parent: &impl KnobEditorParent,
+        graph: Rc<ModuleGraph>,
         control: Rcrc<FloatInRangeControl>,
         value: Rcrc<f32>,
         center_pos: Vec2D,
         label: String,
         tooltip: String,
     ) -> Rc<Self> {
-        let num_channels = control.borrow().automation.len().max(2) as f32;
+        let num_channels = control.borrow().num_lanes().max(2) as f32;
         let required_radius =
             (KNOB_MENU_LANE_SIZE + KNOB_MENU_LANE_GAP) * num_channels + KNOB_MENU_KNOB_OR + GRID_P;
         let size = (required_radius * 2.0, required_radius + fatgrid(1)).into();
         let state = KnobEditorState {
+            graph,
             control,
             value,
             pos: (center_pos - size / 2.0),
@@ -253,13 +259,24 @@ impl WidgetImpl<Renderer, DropTarget> for KnobEditor {
         let centered = (pos - state.size.x / 2.0) * (1, -1);
         let (angle, radius) = (centered.angle(), centered.length());
         let control = &*state.control.borrow();
-        let auto_lanes = control.automation.len();
+        let auto_lanes = control.num_lanes();
         // Clicked somewhere in the top "half" where the main knob and automation lanes are.
         if angle >= 0.0 && angle <= PI {
             let radius = radius as f32;
             if radius < KNOB_MENU_KNOB_IR {
                 // Nothing interactable inside the knob.
             } else if radius < KNOB_MENU_KNOB_OR {
+                if mods.right_click && mods.snap {
+                    let control = Rc::clone(&state.control);
+                    let engine = self.with_gui_state(|state| Rc::clone(&state.engine));
+                    return OnClickBehavior::wrap(move || {
+                        engine.borrow_mut().toggle_host_parameter(&control)
+                    });
+                } else if mods.right_click {
+                    let control = Rc::clone(&state.control);
+                    let graph = Rc::clone(&state.graph);
+                    return OnClickBehavior::wrap(move || graph.toggle_midi_learn(&control));
+                }
                 return Some(Box::new(ManipulateFIRControl::new(
                     self,
                     Rc::clone(&state.control),
@@ -272,7 +289,7 @@ impl WidgetImpl<Renderer, DropTarget> for KnobEditor {
                     // match up.
                     let lane = auto_lanes - lane - 1;
                     let range = control.range;
-                    let lane_range = control.automation[lane].range;
+                    let lane_range = control.lane_range(lane);
                     let mut min_angle = lane_range.0.from_range_to_range(range.0, range.1, PI, 0.0);
                     let mut max_angle = lane_range.1.from_range_to_range(range.0, range.1, PI, 0.0);
                     let ends_flipped = lane_range.0 > lane_range.1;
@@ -286,7 +303,7 @@ impl WidgetImpl<Renderer, DropTarget> for KnobEditor {
                             let control = Rc::clone(&state.control);
                             let engine = self.with_gui_state(|state| Rc::clone(&state.engine));
                             return OnClickBehavior::wrap(move || {
-                                control.borrow_mut().automation.remove(lane);
+                                control.borrow_mut().remove_lane(lane);
                                 engine.borrow_mut().regenerate_code()
                             });
                         } else {
@@ -316,7 +333,7 @@ impl WidgetImpl<Renderer, DropTarget> for KnobEditor {
                 }
                 // Lanes are shown in reverse order.
                 let lane = auto_lanes - lane - 1;
-                let lane_range = control.automation[lane].range;
+                let lane_range = control.lane_range(lane);
                 let ends_flipped = lane_range.0 > lane_range.1;
                 // xor
                 return Some(Box::new(if (centered.x > 0.0) != ends_flipped {
@@ -336,7 +353,7 @@ impl WidgetImpl<Renderer, DropTarget> for KnobEditor {
         let centered = (pos - state.size.x / 2.0) * (1, -1);
         let (angle, radius) = (centered.angle(), centered.length());
         let control = &*state.control.borrow();
-        let auto_lanes = control.automation.len();
+        let auto_lanes = control.num_lanes();
         // Clicked somewhere in the top "half" where the main knob and automation lanes are.
         if !(angle >= 0.0 && angle <= PI) {
             // If we clicked under one of the automation lanes...
@@ -345,10 +362,11 @@ impl WidgetImpl<Renderer, DropTarget> for KnobEditor {
                     / (KNOB_MENU_LANE_SIZE + KNOB_MENU_LANE_GAP))
                     as usize;
                 if lane < auto_lanes {
+                    let lane = auto_lanes - lane - 1;
                     let tooltip = Tooltip {
                         text: format!(
-                            "Automation lane #{}, click + drag to move one of the ends.",
-                            lane + 1,
+                            "{}, click + drag to move one of the ends.",
+                            lane_name(&state.graph, control, lane),
                         ),
                         interaction: vec![
                             InteractionHint::LeftClickAndDrag,
@@ -370,21 +388,23 @@ impl WidgetImpl<Renderer, DropTarget> for KnobEditor {
         }
         let tooltip = if radius < KNOB_MENU_KNOB_OR {
             Tooltip {
-                text: state.tooltip.clone(),
+                text: editor_tooltip(&state.tooltip, control),
                 interaction: vec![
                     InteractionHint::LeftClickAndDrag,
                     InteractionHint::DoubleClick,
+                    InteractionHint::RightClick,
                 ],
             }
         } else {
             let radius = radius - KNOB_MENU_KNOB_OR;
             let lane = (radius / (KNOB_MENU_LANE_SIZE + KNOB_MENU_LANE_GAP)) as usize;
             if lane < auto_lanes {
+                let lane = auto_lanes - lane - 1;
                 Tooltip {
                     text: format!(
-                    "Automation lane #{}, click + drag on empty space to move one end at a time.",
-                    lane + 1,
-                ),
+                        "{}, click + drag on empty space to move one end at a time.",
+                        lane_name(&state.graph, control, lane),
+                    ),
                     interaction: vec![
                         InteractionHint::LeftClickAndDrag,
                         InteractionHint::DoubleClick,
@@ -434,15 +454,16 @@ impl WidgetImpl<Renderer, DropTarget> for KnobEditor {
 
         const GAP: f32 = KNOB_MENU_LANE_GAP;
         const LS: f32 = KNOB_MENU_LANE_SIZE;
-        for (index, lane) in control.automation.iter().rev().enumerate() {
+        for (index, lane) in (0..control.num_lanes()).rev().enumerate() {
             let ir = KOR + GAP + (GAP + LS) * index as f32;
             let or = ir + LS;
             g.set_color(&COLOR_BG0);
             g.draw_pie(-or, or * 2.0, ir * 2.0, PI, 0.0);
-            g.set_color(&COLOR_AUTOMATION);
-            let min_angle = value_to_angle(control.range, lane.range.0);
-            let max_angle = value_to_angle(control.range, lane.range.1);
-            let ir = if lane.range.0 > lane.range.1 {
+            g.set_color(lane_color(&*control, lane));
+            let lane_range = control.lane_range(lane);
+            let min_angle = value_to_angle(control.range, lane_range.0);
+            let max_angle = value_to_angle(control.range, lane_range.1);
+            let ir = if lane_range.0 > lane_range.1 {
                 ir + LS / 2.0
             } else {
                 ir
@@ -451,8 +472,72 @@ impl WidgetImpl<Renderer, DropTarget> for KnobEditor {
         }
 
         g.set_color(&COLOR_FG1);
-        let value_text = format!("{}{}", format_decimal(value, 3), control.suffix);
+        let value_text = if state.graph.is_learning_midi(&state.control) {
+            "Learning...".to_owned()
+        } else {
+            format!("{}{}", format_decimal(value, 3), control.suffix)
+        };
         g.draw_label((-KIR, -12.0), KIR * 2.0, &value_text);
         g.draw_label((-KOR, GRID_P), KOR * 2.0, &state.label);
     }
 }
+
+/// Adds a description of the MIDI binding and host parameter of a control to its tooltip.
+pub(super) fn float_control_tooltip(tooltip: &str, control: &FloatInRangeControl) -> String {
+    let mut text = tooltip.to_owned();
+    if let Some(binding) = &control.midi_binding {
+        text.push_str(&format!(
+            " (MIDI CC {} sets {}{} to {}{}, {} curve.)",
+            binding.cc,
+            format_decimal(binding.range.0, 4),
+            control.suffix,
+            format_decimal(binding.range.1, 4),
+            control.suffix,
+            binding.curve.name().to_lowercase(),
+        ));
+    }
+    if let Some(index) = control.host_parameter {
+        text.push_str(&format!(" (Host parameter {}.)", index + 1));
+    }
+    text
+}
+
+/// The tooltip for the control itself inside an editor menu.
+pub(super) fn editor_tooltip(tooltip: &str, control: &FloatInRangeControl) -> String {
+    format!(
+        concat!(
+            "{} Right-click to bind it to the next MIDI controller you move, shift + right-click ",
+            "to let the host automate it."
+        ),
+        float_control_tooltip(tooltip, control)
+    )
+}
+
+pub(super) fn lane_name(
+    graph: &Rc<ModuleGraph>,
+    control: &FloatInRangeControl,
+    lane: usize,
+) -> String {
+    if control.is_midi_lane(lane) {
+        let binding = control.midi_binding.as_ref().unwrap();
+        format!(
+            "MIDI CC {} ({} curve, click to change, right-click to remove)",
+            binding.cc,
+            binding.curve.name().to_lowercase()
+        )
+    } else if let Some(index) = control.lane_macro(lane) {
+        format!("{} (right-click to remove)", graph.get_macro_name(index))
+    } else {
+        format!("Automation lane #{}", lane + 1)
+    }
+}
+
+pub(super) fn lane_color(control: &FloatInRangeControl, lane: usize) -> &'static (u8, u8, u8) {
+    if control.is_midi_lane(lane) {
+        &COLOR_EDITABLE
+    } else if control.lane_macro(lane).is_some() {
+        &COLOR_MACRO
+    } else {
+        &COLOR_AUTOMATION
+    }
+}
@@ -2,7 +2,7 @@ use crate::{
     engine::controls::{Control, FloatInRangeControl},
     gui::{
         constants::*,
-        module_widgets::{KnobEditor, ModuleWidgetImpl},
+        module_widgets::{float_control_tooltip, KnobEditor, ModuleWidgetImpl},
         mouse_behaviors::ManipulateFIRControl,
         top_level::graph::{Module, ModuleGraph},
         InteractionHint, Tooltip,
@@ -81,6 +81,7 @@ impl WidgetImpl<Renderer, DropTarget> for MiniKnob {
             let graph = Rc::clone(&self.parents.graph);
             let menu = KnobEditor::new(
                 self,
+                Rc::clone(&graph),
                 Rc::clone(&state.control),
                 Rc::clone(&state.value),
                 pos,
@@ -105,8 +106,9 @@ impl WidgetImpl<Renderer, DropTarget> for MiniKnob {
     }
 
     fn on_hover_impl(self: &Rc<Self>, _pos: Vec2D) -> Option<()> {
+        let state = self.state.borrow();
         let tooltip = Tooltip {
-            text: self.state.borrow().tooltip.clone(),
+            text: float_control_tooltip(&state.tooltip, &*state.control.borrow()),
             interaction: vec![
                 InteractionHint::LeftClickAndDrag,
                 InteractionHint::RightClick,
@@ -143,7 +145,7 @@ impl WidgetImpl<Renderer, DropTarget> for MiniKnob {
         let zero_angle = value_to_angle(control.range, 0.0);
         // If manual, show the manual value. If automated, show the most recent value recorded
         // from when a note was actually playing.
-        let value = if control.automation.len() > 0 {
+        let value = if control.is_automated() {
             *state.value.borrow()
         } else {
             control.value
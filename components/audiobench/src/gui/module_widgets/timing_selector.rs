@@ -1,5 +1,5 @@
 use crate::{
-    engine::controls::TimingModeControl,
+    engine::controls::{TimingModeControl, TimingUnits},
     gui::{
         constants::*, module_widgets::ModuleWidgetImpl, mouse_behaviors::MutateControl,
         InteractionHint, Tooltip,
@@ -27,6 +27,7 @@ scui::widget! {
         song_icon: usize,
         time_icon: usize,
         beats_icon: usize,
+        bars_icon: usize,
     }
 }
 
@@ -46,6 +47,7 @@ impl TimingSelector {
             song_icon: registry.lookup_icon("Factory:treble_clef").unwrap(),
             time_icon: registry.lookup_icon("Factory:time").unwrap(),
             beats_icon: registry.lookup_icon("Factory:metronome").unwrap(),
+            bars_icon: registry.lookup_icon("Factory:bars").unwrap(),
         };
         Rc::new(Self::create(parent, state))
     }
@@ -54,8 +56,8 @@ impl TimingSelector {
         self.state.borrow().control.borrow().uses_elapsed_time()
     }
 
-    fn type_value(&self) -> bool {
-        self.state.borrow().control.borrow().is_beat_synchronized()
+    fn type_value(&self) -> TimingUnits {
+        self.state.borrow().control.borrow().get_units()
     }
 }
 
@@ -77,7 +79,7 @@ impl WidgetImpl<Renderer, DropTarget> for TimingSelector {
         if pos.x < grid(2) / 2.0 {
             MutateControl::wrap(self, move || cref.borrow_mut().toggle_source())
         } else {
-            MutateControl::wrap(self, move || cref.borrow_mut().toggle_units())
+            MutateControl::wrap(self, move || cref.borrow_mut().cycle_units())
         }
     }
 
@@ -91,11 +93,7 @@ impl WidgetImpl<Renderer, DropTarget> for TimingSelector {
             } else {
                 format!(
                     "Change timing type, current value is \"{}\"",
-                    if self.type_value() {
-                        "beats"
-                    } else {
-                        "seconds"
-                    }
+                    self.type_value().name()
                 )
             },
             interaction: vec![InteractionHint::LeftClick],
@@ -123,10 +121,10 @@ impl WidgetImpl<Renderer, DropTarget> for TimingSelector {
             ICON_SIZE,
         );
         g.draw_white_icon(
-            if self.type_value() {
-                state.beats_icon
-            } else {
-                state.time_icon
+            match self.type_value() {
+                TimingUnits::Seconds => state.time_icon,
+                TimingUnits::Beats => state.beats_icon,
+                TimingUnits::Bars => state.bars_icon,
             },
             (CS + ICON_SIZE + CS, CS),
             ICON_SIZE,
@@ -76,6 +76,8 @@ pub struct ManipulateFIRControl {
     control: Rcrc<FloatInRangeControl>,
     #[value(control.borrow().value)]
     current_value: f32,
+    #[value(false)]
+    gesture_started: bool,
 }
 
 impl MouseBehavior<DropTarget> for ManipulateFIRControl {
@@ -86,26 +88,46 @@ impl MouseBehavior<DropTarget> for ManipulateFIRControl {
         let delta = delta * (range.1 - range.0) as f32;
         self.current_value = (self.current_value + delta).clam(range.0, range.1);
         control_ref.value = maybe_snap_value(self.current_value, range, mods);
-        for lane in &mut control_ref.automation {
-            lane.range.0 = (lane.range.0 + delta).clam(range.0, range.1);
-            lane.range.1 = (lane.range.1 + delta).clam(range.0, range.1);
+        for lane in 0..control_ref.num_lanes() {
+            let lane_range = control_ref.lane_range_mut(lane);
+            lane_range.0 = (lane_range.0 + delta).clam(range.0, range.1);
+            lane_range.1 = (lane_range.1 + delta).clam(range.0, range.1);
         }
         let tooltip = range_value_tooltip(control_ref.value, &control_ref.suffix);
         drop(control_ref);
-        self.engine.borrow_mut().reload_dyn_data();
+        let mut engine = self.engine.borrow_mut();
+        let control_ref = self.control.borrow();
+        if !self.gesture_started {
+            engine.begin_host_parameter_gesture(&*control_ref);
+            self.gesture_started = true;
+        }
+        engine.host_parameter_changed(&*control_ref);
+        drop(control_ref);
+        engine.reload_dyn_data();
+        drop(engine);
         self.gui_interface.state.borrow_mut().set_tooltip(tooltip);
     }
 
     fn on_drop(self: Box<Self>, _drop_target: Option<DropTarget>) {
-        self.engine.borrow().set_dummy_note_active(false);
+        let engine = self.engine.borrow();
+        engine.set_dummy_note_active(false);
+        if self.gesture_started {
+            engine.end_host_parameter_gesture(&*self.control.borrow());
+        }
     }
 
     fn on_double_click(self: Box<Self>) {
         let mut cref = self.control.borrow_mut();
         cref.value = cref.default;
         drop(cref);
-        self.engine.borrow_mut().reload_dyn_data();
-        self.engine.borrow().activate_dummy_note_once();
+        let mut engine = self.engine.borrow_mut();
+        let cref = self.control.borrow();
+        engine.begin_host_parameter_gesture(&*cref);
+        engine.host_parameter_changed(&*cref);
+        engine.end_host_parameter_gesture(&*cref);
+        drop(cref);
+        engine.reload_dyn_data();
+        engine.activate_dummy_note_once();
     }
 }
 
@@ -130,7 +152,7 @@ impl ManipulateLane {
         let engine = Rc::clone(&widget.provide_gui_interface().state.borrow().engine);
         let gui_interface = Rc::clone(&widget.provide_gui_interface());
         let control_ref = control.borrow();
-        let real_value = control_ref.automation[lane].range;
+        let real_value = control_ref.lane_range(lane);
         drop(control_ref);
         Self {
             engine,
@@ -174,19 +196,19 @@ impl MouseBehavior<DropTarget> for ManipulateLane {
         let mut control_ref = self.control.borrow_mut();
         let range = control_ref.range;
         let delta = delta * (range.1 - range.0) as f32;
-        let lane = &mut control_ref.automation[self.lane];
+        let lane_range = control_ref.lane_range_mut(self.lane);
         if self.start {
             self.real_value.0 = (self.real_value.0 + delta).clam(range.0, range.1);
-            lane.range.0 = maybe_snap_value(self.real_value.0, range, mods);
+            lane_range.0 = maybe_snap_value(self.real_value.0, range, mods);
         }
         if self.end {
             self.real_value.1 = (self.real_value.1 + delta).clam(range.0, range.1);
-            lane.range.1 = maybe_snap_value(self.real_value.1, range, mods);
+            lane_range.1 = maybe_snap_value(self.real_value.1, range, mods);
         }
         let tttext = format!(
             "{0}{2} to {1}{2}",
-            format_decimal(lane.range.0, 4),
-            format_decimal(lane.range.1, 4),
+            format_decimal(lane_range.0, 4),
+            format_decimal(lane_range.1, 4),
             control_ref.suffix,
         );
         drop(control_ref);
@@ -206,15 +228,28 @@ impl MouseBehavior<DropTarget> for ManipulateLane {
         self.engine.borrow().set_dummy_note_active(false);
     }
 
+    fn on_click(self: Box<Self>) {
+        // Clicking a MIDI binding without dragging it switches to the next curve.
+        let mut cref = self.control.borrow_mut();
+        if !cref.is_midi_lane(self.lane) {
+            return;
+        }
+        let binding = cref.midi_binding.as_mut().unwrap();
+        binding.curve = binding.curve.next();
+        drop(cref);
+        self.engine.borrow_mut().reload_dyn_data();
+        self.engine.borrow().activate_dummy_note_once();
+    }
+
     fn on_double_click(self: Box<Self>) {
         let mut cref = self.control.borrow_mut();
         let range = cref.range;
-        let lane = &mut cref.automation[self.lane];
+        let lane_range = cref.lane_range_mut(self.lane);
         if self.start {
-            lane.range.0 = range.0
+            lane_range.0 = range.0
         };
         if self.end {
-            lane.range.1 = range.1
+            lane_range.1 = range.1
         };
         drop(cref);
         self.engine.borrow_mut().reload_dyn_data();
@@ -1,15 +1,16 @@
 use crate::{
     engine::{
-        controls::{AutomationSource, Control},
+        controls::{AutomationSource, Control, FloatInRangeControl},
         parts as ep,
     },
     gui::{
         constants::*,
         graphics::GrahpicsWrapper,
         top_level::{graph::Module, ModuleBrowser},
+        ui_widgets::TextBox,
         InteractionHint, TabArchetype, Tooltip,
     },
-    registry::module_template::ModuleTemplate,
+    registry::{module_template::ModuleTemplate, save_data::Patch},
     scui_config::{DropTarget, MaybeMouseBehavior, Renderer},
 };
 use scones::make_constructor;
@@ -28,10 +29,19 @@ scui::widget! {
         current_draw_layer: usize,
         wire_preview_endpoint: Option<Vec2D>,
         hovered_module: Option<Rc<Module>>,
+        /// The control to bind to the next MIDI controller that is moved.
+        midi_learn_target: Option<Rcrc<FloatInRangeControl>>,
+        /// Groups which have been opened, each one inside the previous one. Only the modules in
+        /// the last one are shown.
+        open_groups: Vec<Rcrc<ep::Module>>,
+        /// Modules which will be put into a new group when the user asks for one.
+        selected_modules: Vec<Rcrc<ep::Module>>,
     }
     Children {
         modules: Vec<Rc<Module>>,
-        detail_menu: Option<Box<dyn Widget<Renderer, DropTarget>>>
+        detail_menu: Option<Box<dyn Widget<Renderer, DropTarget>>>,
+        /// Shown in screen space while a group is open so that it can be renamed.
+        group_name_box: Option<Rc<TextBox>>,
     }
 }
 
@@ -40,6 +50,8 @@ pub enum GraphHighlightMode {
     None,
     ReceivesType(ep::JackType),
     ProducesType(ep::JackType),
+    /// Highlights controls that one of the patch's macros can be connected to.
+    ReceivesMacro,
 }
 
 impl GraphHighlightMode {
@@ -59,11 +71,12 @@ impl GraphHighlightMode {
                 .into_iter()
                 .any(|i| i == typ),
             Self::ProducesType(_) => false,
+            Self::ReceivesMacro => control.borrow().accepts_macros(),
         }
     }
 
     pub fn should_dim(&self, control: &Rcrc<impl Control + ?Sized>) -> bool {
-        if let Self::ReceivesType(..) = self {
+        if let Self::ReceivesType(..) | Self::ReceivesMacro = self {
             !self.should_highlight(control)
         } else {
             false
@@ -81,15 +94,17 @@ impl ModuleGraph {
             current_draw_layer: 0,
             wire_preview_endpoint: None,
             hovered_module: None,
+            midi_learn_target: None,
+            open_groups: Vec::new(),
+            selected_modules: Vec::new(),
         };
         let this = Rc::new(Self::create(parent, state));
         graph.borrow_mut().current_widget = Some(Rc::clone(&this));
         let mut children = this.children.borrow_mut();
-        children.modules = graph
-            .borrow()
-            .borrow_modules()
-            .iter()
-            .map(|module_rc| Module::new(&this, Rc::clone(module_rc)))
+        children.modules = this
+            .current_modules()
+            .into_iter()
+            .map(|module_rc| Module::new(&this, module_rc))
             .collect();
         drop(children);
         this.recenter();
@@ -97,14 +112,30 @@ impl ModuleGraph {
     }
 
     pub fn rebuild(self: &Rc<Self>) {
+        let mut state = self.state.borrow_mut();
+        state.midi_learn_target = None;
+        state.selected_modules.clear();
+        // The open groups might not exist anymore if a different patch was loaded.
+        let graph = Rc::clone(&state.graph);
+        let graph = graph.borrow();
+        let still_exist = state
+            .open_groups
+            .iter()
+            .take_while(|group| graph.contains_module(group))
+            .count();
+        state.open_groups.truncate(still_exist);
+        drop(graph);
+        drop(state);
+        self.rebuild_group_name_box();
+
         let mut children = self.children.borrow_mut();
         children.modules.clear();
         children.detail_menu = None;
         let state = self.state.borrow();
         let mut top_left = Vec2D::from(std::f32::MAX);
         let mut bottom_right = Vec2D::from(std::f32::MIN);
-        for module_rc in state.graph.borrow().borrow_modules() {
-            let module_widget = Module::new(self, Rc::clone(module_rc));
+        for module_rc in self.current_modules() {
+            let module_widget = Module::new(self, module_rc);
             let pos = module_widget.get_pos();
             let size = module_widget.get_size();
             top_left = top_left.min(pos);
@@ -116,6 +147,145 @@ impl ModuleGraph {
         self.recenter();
     }
 
+    /// Returns the modules this widget shows, which are either the ones at the top level of the
+    /// graph or the ones in the group that is open.
+    fn current_modules(self: &Rc<Self>) -> Vec<Rcrc<ep::Module>> {
+        let state = self.state.borrow();
+        if let Some(group) = state.open_groups.last() {
+            group.borrow().group.as_ref().unwrap().modules.clone()
+        } else {
+            state.graph.borrow().borrow_modules().to_vec()
+        }
+    }
+
+    fn rebuild_group_name_box(self: &Rc<Self>) {
+        let group = self.get_open_group();
+        let name_box = group.map(|group| {
+            let name = group.borrow().group.as_ref().unwrap().name.clone();
+            TextBox::new(
+                self,
+                GRID_P,
+                (fatgrid(6), grid(1)),
+                name,
+                Box::new(move |text| {
+                    let mut group = group.borrow_mut();
+                    group.group.as_mut().unwrap().name = text.to_owned();
+                    group.rebuild_group_face();
+                }),
+            )
+        });
+        self.children.borrow_mut().group_name_box = name_box;
+    }
+
+    pub fn get_open_group(self: &Rc<Self>) -> Option<Rcrc<ep::Module>> {
+        self.state.borrow().open_groups.last().cloned()
+    }
+
+    /// Shows the modules inside the given group, which must be one of the modules currently
+    /// shown.
+    pub fn open_group(self: &Rc<Self>, group: &Rcrc<ep::Module>) {
+        self.state.borrow_mut().open_groups.push(Rc::clone(group));
+        self.rebuild();
+    }
+
+    /// Goes back to showing whatever contains the group that is open.
+    pub fn close_group(self: &Rc<Self>) {
+        self.state.borrow_mut().open_groups.pop();
+        self.rebuild();
+    }
+
+    pub fn toggle_selected(self: &Rc<Self>, module: &Rcrc<ep::Module>) {
+        let mut state = self.state.borrow_mut();
+        let selected = &mut state.selected_modules;
+        if let Some(index) = selected.iter().position(|other| Rc::ptr_eq(other, module)) {
+            selected.remove(index);
+        } else {
+            selected.push(Rc::clone(module));
+        }
+    }
+
+    pub fn is_selected(self: &Rc<Self>, module: &Rcrc<ep::Module>) -> bool {
+        let state = self.state.borrow();
+        state
+            .selected_modules
+            .iter()
+            .any(|other| Rc::ptr_eq(other, module))
+    }
+
+    /// Puts the selected modules into a new group. Nothing happens if no modules are selected.
+    pub fn group_selected(self: &Rc<Self>) {
+        let state = self.state.borrow();
+        if state.selected_modules.len() == 0 {
+            return;
+        }
+        let selected = state.selected_modules.clone();
+        let graph = Rc::clone(&state.graph);
+        drop(state);
+        graph
+            .borrow_mut()
+            .group_modules(&selected, "Group".to_owned());
+        self.rebuild();
+        self.with_gui_state_mut(|state| {
+            state.engine.borrow_mut().regenerate_code();
+        });
+    }
+
+    /// Shows or hides a control of a module in the open group on the face of the group.
+    pub fn toggle_exposed_control(self: &Rc<Self>, module: &Rcrc<ep::Module>, index: usize) {
+        if let Some(group) = self.get_open_group() {
+            let graph = self.get_real_graph();
+            graph
+                .borrow_mut()
+                .toggle_exposed_control(&group, module, index);
+            self.with_gui_state_mut(|state| {
+                state.engine.borrow_mut().regenerate_code();
+            });
+        }
+    }
+
+    /// Shows or hides an output of a module in the open group as an output of the group.
+    pub fn toggle_exposed_output(self: &Rc<Self>, module: &Rcrc<ep::Module>, index: usize) {
+        if let Some(group) = self.get_open_group() {
+            let graph = self.get_real_graph();
+            graph
+                .borrow_mut()
+                .toggle_exposed_output(&group, module, index);
+            self.with_gui_state_mut(|state| {
+                state.engine.borrow_mut().regenerate_code();
+            });
+        }
+    }
+
+    pub fn is_exposed_control(self: &Rc<Self>, module: &Rcrc<ep::Module>, index: usize) -> bool {
+        if let Some(group) = self.get_open_group() {
+            let group = group.borrow();
+            let exposed = &group.group.as_ref().unwrap().exposed_controls;
+            exposed
+                .iter()
+                .any(|(other, other_index)| Rc::ptr_eq(other, module) && *other_index == index)
+        } else {
+            false
+        }
+    }
+
+    pub fn is_exposed_output(self: &Rc<Self>, module: &Rcrc<ep::Module>, index: usize) -> bool {
+        if let Some(group) = self.get_open_group() {
+            let group = group.borrow();
+            let exposed = &group.group.as_ref().unwrap().exposed_outputs;
+            exposed
+                .iter()
+                .any(|(other, other_index)| Rc::ptr_eq(other, module) && *other_index == index)
+        } else {
+            false
+        }
+    }
+
+    pub fn export_group(self: &Rc<Self>, group: &Rcrc<ep::Module>) {
+        self.with_gui_state_mut(|state| {
+            state.engine.borrow_mut().export_module_group(group);
+        });
+    }
+
     fn recenter(self: &Rc<Self>) {
         let mut top_left = Vec2D::from(std::f32::MAX);
         let mut bottom_right = Vec2D::from(std::f32::MIN);
@@ -138,13 +308,33 @@ impl ModuleGraph {
 
     /// This also adds the module to the actual graph this widget represents.
     pub fn add_module(self: &Rc<Self>, template: Rcrc<ModuleTemplate>) {
-        let mut module = ep::Module::create(template);
+        self.place_module(rcrc(ep::Module::create(template)));
+    }
+
+    /// Adds a copy of a group that was exported to the library.
+    pub fn add_exported_group(self: &Rc<Self>, group: &Rcrc<Patch>) {
+        let registry = self.with_gui_state(|state| Rc::clone(&state.registry));
+        let res = group.borrow().restore_module_group(&*registry.borrow());
+        if let Ok(module) = res {
+            self.place_module(module);
+        } else {
+            self.with_gui_state_mut(|state| {
+                let message = format!("ERROR: Group data is corrupt.");
+                state.engine.borrow_mut().post_error(message);
+            });
+        }
+    }
+
+    fn place_module(self: &Rc<Self>, module: Rcrc<ep::Module>) {
         let state = self.state.borrow();
         let mut pos = state.offset * -1.0;
         pos += TAB_BODY_SIZE / 3.0;
-        module.pos = (pos.x, pos.y);
-        let module = rcrc(module);
-        state.graph.borrow_mut().add_module(Rc::clone(&module));
+        module.borrow_mut().pos = (pos.x, pos.y);
+        let group = state.open_groups.last();
+        state
+            .graph
+            .borrow_mut()
+            .add_module_to(group, Rc::clone(&module));
         let mut children = self.children.borrow_mut();
         children.modules.push(Module::new(self, module));
         self.with_gui_state_mut(|state| {
@@ -162,8 +352,12 @@ impl ModuleGraph {
     }
 
     pub fn remove_module(self: &Rc<Self>, module: &Rcrc<ep::Module>) {
-        let state = self.state.borrow();
+        let mut state = self.state.borrow_mut();
+        state
+            .selected_modules
+            .retain(|other| !Rc::ptr_eq(other, module));
         state.graph.borrow_mut().remove_module(module);
+        drop(state);
         let mut children = self.children.borrow_mut();
         let index = children
             .modules
@@ -194,6 +388,41 @@ impl ModuleGraph {
         self.children.borrow_mut().detail_menu = Some(menu);
     }
 
+    /// Binds the next MIDI controller that is moved to the given control. Calling this again with
+    /// the same control cancels learning.
+    pub fn toggle_midi_learn(self: &Rc<Self>, control: &Rcrc<FloatInRangeControl>) {
+        if self.is_learning_midi(control) {
+            self.state.borrow_mut().midi_learn_target = None;
+        } else {
+            // Ignore any controller moved before learning started.
+            self.with_gui_state(|state| state.engine.borrow().take_last_midi_control());
+            self.state.borrow_mut().midi_learn_target = Some(Rc::clone(control));
+        }
+    }
+
+    pub fn is_learning_midi(self: &Rc<Self>, control: &Rcrc<FloatInRangeControl>) -> bool {
+        if let Some(target) = &self.state.borrow().midi_learn_target {
+            Rc::ptr_eq(target, control)
+        } else {
+            false
+        }
+    }
+
+    fn poll_midi_learn(self: &Rc<Self>) {
+        let mut state = self.state.borrow_mut();
+        if state.midi_learn_target.is_none() {
+            return;
+        }
+        self.with_gui_state(|gui_state| {
+            let mut engine = gui_state.engine.borrow_mut();
+            if let Some(cc) = engine.take_last_midi_control() {
+                let target = state.midi_learn_target.take().unwrap();
+                target.borrow_mut().bind_midi_cc(cc);
+                engine.regenerate_code();
+            }
+        });
+    }
+
     pub fn get_current_draw_layer(self: &Rc<Self>) -> usize {
         self.state.borrow().current_draw_layer
     }
@@ -273,6 +502,19 @@ impl ModuleGraph {
         Box::new(ConnectToControl { graph, control })
     }
 
+    /// Connects the macro with the given index to whichever control it is dropped on.
+    pub fn connect_from_macro_behavior(self: &Rc<Self>, index: usize) -> Box<ConnectFromMacro> {
+        self.state.borrow_mut().highlight_mode = GraphHighlightMode::ReceivesMacro;
+        let graph = Rc::clone(self);
+        Box::new(ConnectFromMacro { graph, index })
+    }
+
+    pub fn get_macro_name(self: &Rc<Self>, index: usize) -> String {
+        let state = self.state.borrow();
+        let graph = state.graph.borrow();
+        graph.macros[index].name.clone()
+    }
+
     pub fn get_real_graph(self: &Rc<Self>) -> Rcrc<ep::ModuleGraph> {
         Rc::clone(&self.state.borrow().graph)
     }
@@ -355,6 +597,29 @@ impl MouseBehavior<DropTarget> for ConnectToControl {
     }
 }
 
+pub struct ConnectFromMacro {
+    graph: Rc<ModuleGraph>,
+    index: usize,
+}
+
+impl MouseBehavior<DropTarget> for ConnectFromMacro {
+    fn on_click(self: Box<Self>) {
+        self.on_drop(None);
+    }
+
+    fn on_drop(self: Box<Self>, drop_target: Option<DropTarget>) {
+        if let Some(DropTarget::Control(control)) = drop_target {
+            if control.borrow().accepts_macros() {
+                control.borrow_mut().connect_macro(self.index);
+            }
+            self.graph.with_gui_state_mut(|state| {
+                state.engine.borrow_mut().regenerate_code();
+            })
+        }
+        self.graph.clear_wire_preview();
+    }
+}
+
 impl WidgetImpl<Renderer, DropTarget> for ModuleGraph {
     fn get_pos_impl(self: &Rc<Self>) -> Vec2D {
         0.into()
@@ -369,8 +634,11 @@ impl WidgetImpl<Renderer, DropTarget> for ModuleGraph {
         pos: Vec2D,
         mods: &MouseMods,
     ) -> MaybeMouseBehavior {
-        let pos = self.translate_screen_pos(pos);
         let children = self.children.borrow();
+        if let Some(name_box) = &children.group_name_box {
+            ris!(name_box.get_mouse_behavior(pos, mods));
+        }
+        let pos = self.translate_screen_pos(pos);
         if let Some(widget) = &children.detail_menu {
             let local_pos = pos - widget.get_pos();
             if local_pos.inside(widget.get_size()) {
@@ -385,13 +653,20 @@ impl WidgetImpl<Renderer, DropTarget> for ModuleGraph {
         for module in children.modules.iter().rev() {
             ris!(module.get_mouse_behavior(pos, mods))
         }
+        if mods.right_click && self.get_open_group().is_some() {
+            let this = Rc::clone(self);
+            return OnClickBehavior::wrap(move || this.close_group());
+        }
         Some(Box::new(GraphInteract::new(Rc::clone(self))))
     }
 
     fn on_hover_impl(self: &Rc<Self>, pos: Vec2D) -> Option<()> {
         self.clear_hovered_module();
-        let pos = self.translate_screen_pos(pos);
         let children = self.children.borrow();
+        if let Some(name_box) = &children.group_name_box {
+            ris!(name_box.on_hover(pos));
+        }
+        let pos = self.translate_screen_pos(pos);
         if let Some(widget) = &children.detail_menu {
             let local_pos = pos - widget.get_pos();
             if local_pos.inside(widget.get_size()) {
@@ -401,16 +676,31 @@ impl WidgetImpl<Renderer, DropTarget> for ModuleGraph {
         for module in children.modules.iter().rev() {
             ris!(module.on_hover(pos))
         }
-        self.with_gui_state_mut(|state| {
-            state.set_tooltip(Tooltip {
+        let tooltip = if self.get_open_group().is_some() {
+            Tooltip {
+                text: concat!(
+                    "Double-click to add a new module to this group, right-click to go back ",
+                    "to what contains it"
+                )
+                .to_owned(),
+                interaction: vec![
+                    InteractionHint::Scroll,
+                    InteractionHint::DoubleClick,
+                    InteractionHint::LeftClickAndDrag,
+                    InteractionHint::RightClick,
+                ],
+            }
+        } else {
+            Tooltip {
                 text: "Double-click to add a new module.".to_owned(),
                 interaction: vec![
                     InteractionHint::Scroll,
                     InteractionHint::DoubleClick,
                     InteractionHint::LeftClickAndDrag,
                 ],
-            })
-        });
+            }
+        };
+        self.with_gui_state_mut(|state| state.set_tooltip(tooltip));
         Some(())
     }
 
@@ -431,10 +721,12 @@ impl WidgetImpl<Renderer, DropTarget> for ModuleGraph {
     }
 
     fn draw_impl(self: &Rc<Self>, g: &mut GrahpicsWrapper) {
+        self.poll_midi_learn();
         let mouse_pos = self.parents.gui.get_mouse_pos() - Vec2D::new(0.0, HEADER_HEIGHT);
         let mouse_pos = self.translate_screen_pos(mouse_pos);
         let state = self.state.borrow();
         let children = self.children.borrow();
+        g.push_state();
         g.scale(state.zoom);
         g.translate(state.offset);
         drop(state);
@@ -454,5 +746,9 @@ impl WidgetImpl<Renderer, DropTarget> for ModuleGraph {
             g.set_color(&COLOR_FG1);
             g.draw_line(*end, mouse_pos, 2.0);
         }
+        g.pop_state();
+        if let Some(name_box) = &children.group_name_box {
+            name_box.draw(g);
+        }
     }
 }
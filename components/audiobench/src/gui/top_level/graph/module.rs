@@ -1,5 +1,5 @@
 use crate::{
-    engine::parts as ep,
+    engine::{controls::Control, parts as ep},
     gui::{
         constants::*,
         module_widgets::ModuleWidget,
@@ -191,12 +191,17 @@ impl Module {
 
     fn draw_wires(self: &Rc<Self>, g: &mut Renderer, pos: Vec2D) {
         let mut wire_tracker = WireTracker::new(self.get_size());
+        let graph = &self.parents.graph;
         let state = self.state.borrow();
         for widget in &state.widgets {
             let center = widget.get_pos() + widget.get_size() / 2.0;
             let input_style = widget.use_input_style_wires();
             if let Some(control) = widget.represented_control() {
                 for source in control.borrow().get_connected_automation() {
+                    // Wires coming from outside of the open group are not shown.
+                    if graph.get_widget_for_module(&source.module).is_none() {
+                        continue;
+                    }
                     let source_coord =
                         Module::output_position(&*source.module.borrow(), source.output_index);
                     wire_tracker.add_wire(source_coord, center, input_style);
@@ -217,6 +222,39 @@ impl Module {
     pub fn is_hovered(self: &Rc<Self>) -> bool {
         self.parents.graph.is_hovered_module(self)
     }
+
+    /// Returns the index the given control has in the module this widget represents.
+    fn index_of_control(&self, control: &Rcrc<dyn Control>) -> Option<usize> {
+        let as_address = |control: &Rcrc<dyn Control>| Rc::as_ptr(control) as *const ();
+        let state = self.state.borrow();
+        let module = state.module.borrow();
+        module
+            .controls
+            .iter()
+            .position(|other| as_address(&other.as_dyn_ptr()) == as_address(control))
+    }
+
+    /// Returns a behavior which shows or hides whatever is under the mouse on the face of the
+    /// open group.
+    fn toggle_exposed_behavior(self: &Rc<Self>, mouse_pos: Vec2D) -> MaybeMouseBehavior {
+        let state = self.state.borrow();
+        let graph = Rc::clone(&self.parents.graph);
+        let module = Rc::clone(&state.module);
+        for widget in &state.widgets {
+            if !(mouse_pos - widget.get_pos()).inside(widget.get_size()) {
+                continue;
+            }
+            let control = widget.represented_control()?;
+            let index = self.index_of_control(&control)?;
+            return OnClickBehavior::wrap(move || graph.toggle_exposed_control(&module, index));
+        }
+        for (index, output) in state.outputs.iter().enumerate() {
+            if output.mouse_in_bounds(mouse_pos) {
+                return OnClickBehavior::wrap(move || graph.toggle_exposed_output(&module, index));
+            }
+        }
+        None
+    }
 }
 
 pub struct DragModule {
@@ -236,6 +274,13 @@ impl DragModule {
 }
 
 impl MouseBehavior<DropTarget> for DragModule {
+    fn on_double_click(self: Box<Self>) {
+        let real_module = self.module.get_real_module();
+        if real_module.borrow().group.is_some() {
+            self.module.parents.graph.open_group(&real_module);
+        }
+    }
+
     fn on_drag(&mut self, delta: Vec2D, mods: &MouseMods) {
         let zoom = self.module.parents.graph.get_zoom();
         self.real_pos += delta / zoom;
@@ -276,6 +321,10 @@ impl WidgetImpl<Renderer, DropTarget> for Module {
         mouse_pos: Vec2D,
         mods: &MouseMods,
     ) -> MaybeMouseBehavior {
+        let graph = &self.parents.graph;
+        if mods.precise && mods.right_click && graph.get_open_group().is_some() {
+            ris!(self.toggle_exposed_behavior(mouse_pos));
+        }
         let state = self.state.borrow();
         for widget in &state.widgets {
             ris!(widget.get_mouse_behavior(mouse_pos, mods));
@@ -286,9 +335,20 @@ impl WidgetImpl<Renderer, DropTarget> for Module {
                 return Some(g.connect_from_source_behavior(Rc::clone(&state.module), index));
             }
         }
-        if mods.right_click {
-            let graph = Rc::clone(&self.parents.graph);
-            let module = Rc::clone(&state.module);
+        let graph = Rc::clone(&self.parents.graph);
+        let module = Rc::clone(&state.module);
+        if mods.precise && mods.right_click {
+            OnClickBehavior::wrap(move || {
+                if !graph.is_selected(&module) {
+                    graph.toggle_selected(&module);
+                }
+                graph.group_selected();
+            })
+        } else if mods.precise {
+            OnClickBehavior::wrap(move || graph.toggle_selected(&module))
+        } else if mods.snap && mods.right_click && module.borrow().group.is_some() {
+            OnClickBehavior::wrap(move || graph.export_group(&module))
+        } else if mods.right_click {
             OnClickBehavior::wrap(move || graph.remove_module(&module))
         } else {
             Some(Box::new(DragModule::new(Rc::clone(self))))
@@ -330,11 +390,21 @@ impl WidgetImpl<Renderer, DropTarget> for Module {
             }
         }
 
+        let mut text = "Alt + click to select, alt + right-click to group the selection".to_owned();
+        if state.module.borrow().group.is_some() {
+            text.push_str(
+                ", double-click to open, shift + right-click to export to the User library",
+            );
+        }
+        if self.parents.graph.get_open_group().is_some() {
+            text.push_str(", alt + right-click a control or output to show it on the group");
+        }
         let mut tooltip = Tooltip {
-            text: "".to_owned(),
+            text,
             interaction: vec![
                 InteractionHint::LeftClickAndDrag,
                 InteractionHint::RightClick,
+                InteractionHint::PrecisionModifier,
             ],
         };
         for output in state.outputs.iter() {
@@ -371,12 +441,34 @@ impl WidgetImpl<Renderer, DropTarget> for Module {
             const CS: f32 = CORNER_SIZE;
             const JS: f32 = JACK_SIZE;
             const MIW: f32 = MODULE_IO_WIDTH;
+            let graph = &self.parents.graph;
 
+            if graph.is_selected(&state.module) {
+                g.set_color(&COLOR_EDITABLE);
+                g.draw_rounded_rect((JS - 2.0, -2.0), size - (JS, 0.0) + 4.0, CS);
+            }
             g.set_color(&COLOR_BG1);
             g.draw_rounded_rect((JS, 0.0), size - (JS, 0.0), CS);
             g.set_color(&COLOR_BG2);
             g.draw_rect((JS + MIW, 0.0), size - (MIW * 2.0 + JS, 0.0));
 
+            // Show what is on the face of the open group.
+            g.set_color(&COLOR_EDITABLE);
+            for widget in &state.widgets {
+                let control = widget.represented_control();
+                let index = control.and_then(|control| self.index_of_control(&control));
+                if let Some(index) = index {
+                    if graph.is_exposed_control(&state.module, index) {
+                        g.draw_rounded_rect(widget.get_pos() - 2.0, widget.get_size() + 4.0, CS);
+                    }
+                }
+            }
+            for (index, output) in state.outputs.iter().enumerate() {
+                if graph.is_exposed_output(&state.module, index) {
+                    g.draw_rounded_rect(output.pos - 2.0, JS + 4.0, CS);
+                }
+            }
+
             g.set_color(&COLOR_FG1);
             g.draw_text(
                 FONT_SIZE,
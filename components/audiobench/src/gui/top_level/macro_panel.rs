@@ -0,0 +1,240 @@
+use crate::{
+    engine::parts as ep,
+    gui::{
+        constants::*, mouse_behaviors::ManipulateFIRControl, top_level::graph::ModuleGraph,
+        ui_widgets::TextBox, InteractionHint, Tooltip,
+    },
+    scui_config::{DropTarget, MaybeMouseBehavior, Renderer},
+};
+use scui::{MouseMods, OnClickBehavior, Vec2D, WidgetImpl};
+use shared_util::prelude::*;
+use std::f32::consts::PI;
+
+scui::widget! {
+    pub MacroPanel
+    State {
+        graph: Rc<ModuleGraph>,
+        real_graph: Rcrc<ep::ModuleGraph>,
+        jack_icon: usize,
+    }
+    Children {
+        name_boxes: Vec<Rc<TextBox>>,
+    }
+}
+
+/// Which part of a macro the mouse is over.
+#[derive(Clone, Copy)]
+enum Part {
+    Knob,
+    Jack,
+}
+
+const SLOT_WIDTH: f32 = TAB_BODY_WIDTH / ep::Macro::NUM_MACROS as f32;
+const JACK_Y: f32 = GRID_P + (grid(2) - JACK_SIZE) / 2.0;
+const MIN_ANGLE: f32 = PI * 1.10;
+const MAX_ANGLE: f32 = -PI * 0.10;
+
+fn slot_x(index: usize) -> f32 {
+    SLOT_WIDTH * index as f32
+}
+
+impl MacroPanel {
+    pub const HEIGHT: f32 = GRID_P * 3.0 + grid(2) + grid(1);
+
+    pub fn new(parent: &impl MacroPanelParent, graph: Rc<ModuleGraph>) -> Rc<Self> {
+        let real_graph = graph.get_real_graph();
+        let inter = parent.provide_gui_interface();
+        let gui_state = inter.state.borrow();
+        let engine = gui_state.engine.borrow();
+        let jack_icon = engine
+            .borrow_registry()
+            .borrow()
+            .lookup_icon("Factory:audio")
+            .unwrap();
+        drop(engine);
+        drop(gui_state);
+        let state = MacroPanelState {
+            graph,
+            real_graph: Rc::clone(&real_graph),
+            jack_icon,
+        };
+        let this = Rc::new(Self::create(parent, state));
+        let mut children = this.children.borrow_mut();
+        for index in 0..ep::Macro::NUM_MACROS {
+            let name = real_graph.borrow().macros[index].name.clone();
+            let real_graph = Rc::clone(&real_graph);
+            children.name_boxes.push(TextBox::new(
+                &this,
+                (slot_x(index) + GRID_P, GRID_P * 2.0 + grid(2)),
+                (SLOT_WIDTH - GRID_P * 2.0, grid(1)),
+                name,
+                Box::new(move |text| real_graph.borrow_mut().macros[index].name = text.to_owned()),
+            ));
+        }
+        drop(children);
+        this
+    }
+
+    fn part_at(pos: Vec2D) -> Option<(usize, Part)> {
+        let index = (pos.x / SLOT_WIDTH) as usize;
+        if index >= ep::Macro::NUM_MACROS {
+            return None;
+        }
+        let local = pos - (slot_x(index), 0.0);
+        if (local - GRID_P).inside(grid(2).into()) {
+            Some((index, Part::Knob))
+        } else if (local - (GRID_P * 2.0 + grid(2), JACK_Y)).inside(JACK_SIZE.into()) {
+            Some((index, Part::Jack))
+        } else {
+            None
+        }
+    }
+}
+
+impl WidgetImpl<Renderer, DropTarget> for MacroPanel {
+    fn get_pos_impl(self: &Rc<Self>) -> Vec2D {
+        (0.0, TAB_BODY_HEIGHT - Self::HEIGHT).into()
+    }
+
+    fn get_size_impl(self: &Rc<Self>) -> Vec2D {
+        (TAB_BODY_WIDTH, Self::HEIGHT).into()
+    }
+
+    fn get_mouse_behavior_impl(
+        self: &Rc<Self>,
+        pos: Vec2D,
+        mods: &MouseMods,
+    ) -> MaybeMouseBehavior {
+        ris!(self.get_mouse_behavior_children(pos, mods));
+        let (index, part) = Self::part_at(pos)?;
+        let state = self.state.borrow();
+        let graph = Rc::clone(&state.graph);
+        let control = Rc::clone(&state.real_graph.borrow().macros[index].control);
+        if let Part::Jack = part {
+            return Some(graph.connect_from_macro_behavior(index));
+        }
+        let engine = self.with_gui_state(|state| Rc::clone(&state.engine));
+        if mods.right_click && mods.snap {
+            OnClickBehavior::wrap(move || engine.borrow_mut().toggle_host_parameter(&control))
+        } else if mods.right_click {
+            OnClickBehavior::wrap(move || {
+                // Right-clicking a bound macro unbinds it instead of learning a new controller.
+                if control.borrow().midi_binding.is_some() && !graph.is_learning_midi(&control) {
+                    control.borrow_mut().midi_binding = None;
+                    engine.borrow_mut().regenerate_code();
+                } else {
+                    graph.toggle_midi_learn(&control);
+                }
+            })
+        } else {
+            Some(Box::new(ManipulateFIRControl::new(self, control)))
+        }
+    }
+
+    fn on_hover_impl(self: &Rc<Self>, pos: Vec2D) -> Option<()> {
+        ris!(self.on_hover_children(pos));
+        let (index, part) = if let Some(found) = Self::part_at(pos) {
+            found
+        } else {
+            // Don't show the tooltip of the graph underneath the panel.
+            return Some(());
+        };
+        let state = self.state.borrow();
+        let real_graph = state.real_graph.borrow();
+        let patch_macro = &real_graph.macros[index];
+        let tooltip = match part {
+            Part::Jack => Tooltip {
+                text: format!(
+                    "Drag onto a knob or slider to let {} control it",
+                    patch_macro.name
+                ),
+                interaction: vec![InteractionHint::LeftClickAndDrag],
+            },
+            Part::Knob => {
+                let control = patch_macro.control.borrow();
+                let mut text = format!("{}, right-click to ", patch_macro.name);
+                if let Some(binding) = &control.midi_binding {
+                    text.push_str(&format!("unbind MIDI CC {}", binding.cc));
+                } else {
+                    text.push_str("bind it to the next MIDI controller you move");
+                }
+                text.push_str(", shift + right-click to let the host automate it");
+                if let Some(index) = control.host_parameter {
+                    text.push_str(&format!(" (host parameter {})", index + 1));
+                }
+                Tooltip {
+                    text,
+                    interaction: vec![
+                        InteractionHint::LeftClickAndDrag,
+                        InteractionHint::DoubleClick,
+                        InteractionHint::RightClick,
+                    ],
+                }
+            }
+        };
+        self.with_gui_state_mut(|state| state.set_tooltip(tooltip));
+        Some(())
+    }
+
+    fn draw_impl(self: &Rc<Self>, g: &mut Renderer) {
+        g.set_color(&COLOR_BG2);
+        g.draw_rect(0, (TAB_BODY_WIDTH, Self::HEIGHT));
+
+        let state = self.state.borrow();
+        let real_graph = state.real_graph.borrow();
+        let children = self.children.borrow();
+        for (index, patch_macro) in real_graph.macros.iter().enumerate() {
+            let x = slot_x(index);
+            let control = patch_macro.control.borrow();
+            let knob_pos = (x + GRID_P, GRID_P);
+            g.set_color(&COLOR_BG0);
+            g.draw_pie(
+                knob_pos,
+                grid(2),
+                KNOB_INSIDE_SPACE * 2.0,
+                MIN_ANGLE,
+                MAX_ANGLE,
+            );
+            if state.graph.is_learning_midi(&patch_macro.control) {
+                g.set_color(&COLOR_FG1);
+            } else {
+                g.set_color(&COLOR_MACRO);
+            }
+            let angle = control.value.from_range_to_range(
+                control.range.0,
+                control.range.1,
+                MIN_ANGLE,
+                MAX_ANGLE,
+            );
+            g.draw_pie(knob_pos, grid(2), KNOB_INSIDE_SPACE * 2.0, MIN_ANGLE, angle);
+            if control.midi_binding.is_some() {
+                // A thin ring shows that the knob is being moved by a MIDI controller.
+                g.set_color(&COLOR_EDITABLE);
+                g.draw_pie(
+                    knob_pos,
+                    grid(2),
+                    grid(2) - KNOB_MAX_LANE_SIZE,
+                    MIN_ANGLE,
+                    MAX_ANGLE,
+                );
+            }
+
+            let jack_pos = (x + GRID_P * 2.0 + grid(2), JACK_Y);
+            g.set_color(&COLOR_FG1);
+            g.draw_rounded_rect(jack_pos, JACK_SIZE, CORNER_SIZE);
+            g.draw_icon(
+                state.jack_icon,
+                Vec2D::from(jack_pos) + JACK_ICON_PADDING,
+                JACK_SIZE - JACK_ICON_PADDING * 2.0,
+            );
+
+            // The name may have been changed by loading a different patch.
+            let name_box = &children.name_boxes[index];
+            if !name_box.is_focused() && name_box.get_text() != patch_macro.name {
+                name_box.set_text(patch_macro.name.clone());
+            }
+        }
+        drop(children);
+        self.draw_children(g);
+    }
+}
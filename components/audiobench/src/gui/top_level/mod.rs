@@ -3,14 +3,18 @@
 pub mod graph;
 mod header;
 mod library_info;
+mod macro_panel;
 mod message_log;
 mod module_browser;
 mod note_graph;
 mod patch_browser;
+mod patch_settings;
 
 pub use header::Header;
 pub use library_info::*;
+pub use macro_panel::*;
 pub use message_log::*;
 pub use module_browser::*;
 pub use note_graph::*;
 pub use patch_browser::*;
+pub use patch_settings::*;
@@ -4,7 +4,7 @@ use crate::{
         constants::*, graphics::GrahpicsWrapper, top_level::graph::ModuleGraph, GuiTab,
         InteractionHint, TabArchetype, Tooltip,
     },
-    registry::{module_template::ModuleTemplate, Registry},
+    registry::{module_template::ModuleTemplate, save_data::Patch, Registry},
     scui_config::{DropTarget, MaybeMouseBehavior, Renderer},
 };
 use owning_ref::OwningRef;
@@ -19,6 +19,9 @@ struct ModuleBrowserEntry {
     input_icons: Vec<usize>,
     output_icons: Vec<usize>,
     template: Rcrc<ModuleTemplate>,
+    /// Present if this entry is a group exported to the library, in which case a new copy of it
+    /// is restored from this whenever the entry is clicked.
+    exported_group: Option<Rcrc<Patch>>,
 }
 
 impl ModuleBrowserEntry {
@@ -44,9 +47,20 @@ impl ModuleBrowserEntry {
             input_icons,
             output_icons,
             template: Rc::clone(template),
+            exported_group: None,
         }
     }
 
+    fn from_exported_group(registry: &Registry, group: &Rcrc<Patch>) -> Option<Self> {
+        let module = group.borrow().restore_module_group(registry).ok()?;
+        let template = Rc::clone(&module.borrow().template);
+        // The copy is only needed to find out what the group looks like.
+        module.borrow_mut().sever();
+        let mut entry = Self::from(registry, &template);
+        entry.exported_group = Some(Rc::clone(group));
+        Some(entry)
+    }
+
     fn draw(&self, g: &mut GrahpicsWrapper) {
         const CS: f32 = CORNER_SIZE;
         const BAND_SIZE: f32 = GRID_P;
@@ -110,6 +124,8 @@ scui::widget! {
     State {
         add_to_graph: Rc<ModuleGraph>,
         vertical_stacking: usize,
+        /// How many exported groups the registry had when the entries were built.
+        num_exported_groups: usize,
         entries: Vec<ModuleBrowserEntry>,
         alphabetical_list: Vec<VisualEntry>,
         categorical_list: Vec<VisualEntry>,
@@ -120,14 +136,31 @@ scui::widget! {
 impl ModuleBrowser {
     pub fn new(parent: &impl ModuleBrowserParent, add_to_graph: Rc<ModuleGraph>) -> Rc<Self> {
         let inter = parent.provide_gui_interface();
-        let state = inter.state.borrow();
-        let registry = state.registry.borrow();
-
-        let entries: Vec<_> = registry
-            .borrow_templates()
-            .imc(|module| ModuleBrowserEntry::from(&*registry, module));
+        let gui_state = inter.state.borrow();
+        let registry = gui_state.registry.borrow();
         let vertical_stacking =
             (TAB_BODY_HEIGHT / (ModuleBrowserEntry::HEIGHT + GRID_P)).floor() as usize;
+        let mut state = ModuleBrowserState {
+            add_to_graph,
+            vertical_stacking,
+            num_exported_groups: 0,
+            entries: Vec::new(),
+            alphabetical_list: Vec::new(),
+            categorical_list: Vec::new(),
+            current_sort: SortMethod::Categorical,
+        };
+        Self::build_entries(&mut state, &*registry);
+
+        Rc::new(Self::create(parent, state))
+    }
+
+    fn build_entries(state: &mut ModuleBrowserState, registry: &Registry) {
+        let mut entries: Vec<_> = registry
+            .borrow_templates()
+            .imc(|module| ModuleBrowserEntry::from(registry, module));
+        for group in registry.borrow_module_groups() {
+            entries.extend(ModuleBrowserEntry::from_exported_group(registry, group));
+        }
 
         let mut alphabetical_order: Vec<_> = (0..entries.len()).collect();
         alphabetical_order.sort_by(|a, b| entries[*a].name.cmp(&entries[*b].name));
@@ -156,16 +189,21 @@ impl ModuleBrowser {
             }
         }
 
-        let state = ModuleBrowserState {
-            add_to_graph,
-            vertical_stacking,
-            entries,
-            alphabetical_list,
-            categorical_list,
-            current_sort: SortMethod::Categorical,
-        };
+        state.num_exported_groups = registry.borrow_module_groups().len();
+        state.entries = entries;
+        state.alphabetical_list = alphabetical_list;
+        state.categorical_list = categorical_list;
+    }
 
-        Rc::new(Self::create(parent, state))
+    /// Groups can be exported while the browser is open, in which case the entries are rebuilt
+    /// so that the new groups show up.
+    fn refresh_exported_groups(self: &Rc<Self>) {
+        let registry = self.with_gui_state(|state| Rc::clone(&state.registry));
+        let registry = registry.borrow();
+        let mut state = self.state.borrow_mut();
+        if state.num_exported_groups != registry.borrow_module_groups().len() {
+            Self::build_entries(&mut *state, &*registry);
+        }
     }
 
     fn get_current_list(&self) -> OwningRef<Ref<ModuleBrowserState>, Vec<VisualEntry>> {
@@ -221,9 +259,14 @@ impl WidgetImpl<Renderer, DropTarget> for ModuleBrowser {
         if let Some(entry) = self.get_entry_at(pos) {
             let add_to_graph = Rc::clone(&self.state.borrow().add_to_graph);
             let template = Rc::clone(&entry.template);
+            let exported_group = entry.exported_group.clone();
             let this = Rc::clone(self);
             OnClickBehavior::wrap(move || {
-                add_to_graph.add_module(template);
+                if let Some(group) = &exported_group {
+                    add_to_graph.add_exported_group(group);
+                } else {
+                    add_to_graph.add_module(template);
+                }
                 let this = this;
                 // ew, ew, ew.
                 let tab = Rc::new(Rc::clone(&this));
@@ -250,6 +293,7 @@ impl WidgetImpl<Renderer, DropTarget> for ModuleBrowser {
     }
 
     fn draw_impl(self: &Rc<Self>, g: &mut GrahpicsWrapper) {
+        self.refresh_exported_groups();
         let state = self.state.borrow();
         let list = self.get_current_list();
         for (index, entry) in list.iter().enumerate() {
@@ -1,14 +1,20 @@
 use crate::{
-    gui::{constants::*, top_level::graph::ModuleGraph, GuiTab, TabArchetype},
+    gui::{
+        constants::*,
+        top_level::{graph::ModuleGraph, MacroPanel},
+        GuiTab, TabArchetype,
+    },
     scui_config::{DropTarget, MaybeMouseBehavior, Renderer},
 };
-use scui::{ChildHolder, MouseMods, Vec2D, WidgetImpl};
+use scui::{ChildHolder, MouseMods, Vec2D, Widget, WidgetImpl};
 use shared_util::prelude::*;
 
 scui::widget! {
     pub NoteGraph
     State { }
     Children {
+        // The macro panel sits on top of the graph, so it is asked about the mouse first.
+        macro_panel: ChildHolder<Rc<MacroPanel>>,
         graph: ChildHolder<Rc<ModuleGraph>>,
     }
 }
@@ -22,7 +28,9 @@ impl NoteGraph {
 
         let this = Rc::new(Self::create(parent, NoteGraphState {}));
         let mut children = this.children.borrow_mut();
-        children.graph = ModuleGraph::new(parent, graph).into();
+        let graph = ModuleGraph::new(parent, graph);
+        children.macro_panel = MacroPanel::new(&this, Rc::clone(&graph)).into();
+        children.graph = graph.into();
         drop(children);
 
         this
@@ -54,8 +62,18 @@ impl WidgetImpl<Renderer, DropTarget> for NoteGraph {
         self.on_hover_children(mouse_pos)
     }
 
+    fn get_drop_target_impl(self: &Rc<Self>, pos: Vec2D) -> Option<DropTarget> {
+        // Controls hidden behind the macro panel should not receive anything dropped on it.
+        if pos.y >= TAB_BODY_HEIGHT - MacroPanel::HEIGHT {
+            return None;
+        }
+        self.get_drop_target_children(pos)
+    }
+
     fn draw_impl(self: &Rc<Self>, g: &mut Renderer) {
-        self.draw_children(g);
+        let children = self.children.borrow();
+        children.graph.draw(g);
+        children.macro_panel.draw(g);
     }
 }
 
@@ -95,10 +95,20 @@ impl PatchBrowser {
             "Message Log".into(),
             "View a log of all info/warning/error messages from this session".into(),
         ));
-        let x = x + TabButton::SIZE + GRID_P;
+        let link_x = x + TabButton::SIZE + GRID_P;
+        let x = GRID_P + HW + GRID_P;
+        let y = TabButton::SIZE + GRID_P;
+        tab_buttons.push(TabButton::new(
+            &this,
+            (x, y),
+            registry.lookup_icon("Factory:note").unwrap(),
+            TabArchetype::PatchSettings,
+            "Settings".into(),
+            "Edit settings which apply to the whole patch, like the number of voices".into(),
+        ));
         let link_button = LinkButton::new(
             &this,
-            (x, 0.0),
+            (link_x, 0.0),
             registry.lookup_icon("Factory:github").unwrap(),
             format!("https://github.com/joshua-maros/audiobench/issues/new"),
             "Report A Bug".into(),
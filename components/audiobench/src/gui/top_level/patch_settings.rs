@@ -0,0 +1,333 @@
+use crate::{
+    engine::{
+        parts::{NotePriority, StealingPolicy, VoiceSettings},
+        UiThreadEngine,
+    },
+    gui::{constants::*, GuiTab, InteractionHint, TabArchetype, Tooltip},
+    scui_config::{DropTarget, MaybeMouseBehavior, Renderer},
+};
+use scui::{MouseMods, OnClickBehavior, Vec2D, WidgetImpl};
+use shared_util::prelude::*;
+
+scui::widget! {
+    pub PatchSettings
+    State {}
+}
+
+/// A single adjustable setting that applies to the whole patch instead of a specific module.
+#[derive(Clone, Copy)]
+enum Setting {
+    MaxVoices,
+    StealingPolicy,
+    Mono,
+    NotePriority,
+    Legato,
+    GlideTime,
+    UnisonVoices,
+    UnisonDetune,
+    UnisonSpread,
+    UnisonRandomPhase,
+    Scale,
+    KeyboardMapping,
+}
+
+impl Setting {
+    const ALL: [Setting; 12] = [
+        Self::MaxVoices,
+        Self::StealingPolicy,
+        Self::Mono,
+        Self::NotePriority,
+        Self::Legato,
+        Self::GlideTime,
+        Self::UnisonVoices,
+        Self::UnisonDetune,
+        Self::UnisonSpread,
+        Self::UnisonRandomPhase,
+        Self::Scale,
+        Self::KeyboardMapping,
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::MaxVoices => "Max Voices",
+            Self::StealingPolicy => "Voice Stealing",
+            Self::Mono => "Mono",
+            Self::NotePriority => "Note Priority",
+            Self::Legato => "Legato",
+            Self::GlideTime => "Glide Time",
+            Self::UnisonVoices => "Unison Voices",
+            Self::UnisonDetune => "Unison Detune",
+            Self::UnisonSpread => "Unison Spread",
+            Self::UnisonRandomPhase => "Random Phase",
+            Self::Scale => "Scale",
+            Self::KeyboardMapping => "Keyboard Mapping",
+        }
+    }
+
+    fn tooltip(&self) -> &'static str {
+        match self {
+            Self::MaxVoices => {
+                "How many notes can play at once, including notes that are still decaying"
+            }
+            Self::StealingPolicy => {
+                "Which note gets cut off when a new note is played while all voices are in use"
+            }
+            Self::Mono => "If enabled, only one note plays at a time",
+            Self::NotePriority => "Which held key a mono patch plays",
+            Self::Legato => {
+                "If enabled, switching notes in mono mode changes the pitch without restarting the note"
+            }
+            Self::GlideTime => "How long it takes to slide to a new pitch in mono mode",
+            Self::UnisonVoices => {
+                "How many slightly different copies of the patch each key plays, the module graph shows the middle one"
+            }
+            Self::UnisonDetune => "How far apart the highest and lowest unison voices are tuned",
+            Self::UnisonSpread => "How widely the unison voices are spread across the stereo field",
+            Self::UnisonRandomPhase => {
+                "If enabled, each unison voice starts at a random point in its waveform"
+            }
+            Self::Scale => {
+                "Which Scala (.scl) file to tune notes with, add more by putting them in a library"
+            }
+            Self::KeyboardMapping => {
+                "Which Scala keyboard mapping (.kbm) file decides what note of the scale each key plays"
+            }
+        }
+    }
+
+    fn display(&self, engine: &UiThreadEngine) -> String {
+        let settings = engine.get_voice_settings();
+        let tuning = engine.get_tuning_settings();
+        match self {
+            Self::MaxVoices => format!("{}", settings.max_voices),
+            Self::StealingPolicy => settings.stealing_policy.name().to_owned(),
+            Self::Mono => on_off(settings.mono),
+            Self::NotePriority => settings.note_priority.name().to_owned(),
+            Self::Legato => on_off(settings.legato),
+            Self::GlideTime => format!("{:.3}s", settings.glide_time),
+            Self::UnisonVoices => format!("{}", settings.unison_voices),
+            Self::UnisonDetune => format!("{:.0} cents", settings.unison_detune),
+            Self::UnisonSpread => format!("{:.0}%", settings.unison_spread * 100.0),
+            Self::UnisonRandomPhase => on_off(settings.unison_random_phase),
+            Self::Scale => short_file_name(&tuning.scale, "12-TET"),
+            Self::KeyboardMapping => short_file_name(&tuning.keyboard_mapping, "Default"),
+        }
+    }
+
+    fn adjust(&self, engine: &mut UiThreadEngine, delta: i32) {
+        match self {
+            Self::Scale | Self::KeyboardMapping => self.adjust_tuning(engine, delta),
+            _ => self.adjust_voice_settings(engine, delta),
+        }
+    }
+
+    fn adjust_tuning(&self, engine: &mut UiThreadEngine, delta: i32) {
+        let mut tuning = engine.get_tuning_settings();
+        let registry = engine.borrow_registry().borrow();
+        let (names, current) = if let Self::Scale = self {
+            (registry.get_scale_names(), &mut tuning.scale)
+        } else {
+            (
+                registry.get_keyboard_mapping_names(),
+                &mut tuning.keyboard_mapping,
+            )
+        };
+        drop(registry);
+        let options: Vec<_> = std::iter::once(None)
+            .chain(names.into_iter().map(Some))
+            .collect();
+        // The current file might not exist if the patch came from somewhere else.
+        *current = if options.contains(current) {
+            cycle(&options, current.clone(), delta)
+        } else {
+            None
+        };
+        engine.set_tuning_settings(tuning);
+    }
+
+    fn adjust_voice_settings(&self, engine: &mut UiThreadEngine, delta: i32) {
+        let mut settings = engine.get_voice_settings();
+        match self {
+            Self::MaxVoices => {
+                let max = VoiceSettings::MAX_VOICES as i32;
+                settings.max_voices = (settings.max_voices as i32 + delta).max(1).min(max) as _;
+            }
+            Self::StealingPolicy => {
+                settings.stealing_policy =
+                    cycle(&StealingPolicy::ALL, settings.stealing_policy, delta)
+            }
+            Self::Mono => settings.mono = !settings.mono,
+            Self::NotePriority => {
+                settings.note_priority = cycle(&NotePriority::ALL, settings.note_priority, delta)
+            }
+            Self::Legato => settings.legato = !settings.legato,
+            Self::GlideTime => {
+                // Glide times are adjusted logarithmically, bottoming out at zero.
+                let time = settings.glide_time;
+                let time = if delta > 0 {
+                    (time * GLIDE_STEP).max(MIN_GLIDE_TIME)
+                } else if time / GLIDE_STEP < MIN_GLIDE_TIME {
+                    0.0
+                } else {
+                    time / GLIDE_STEP
+                };
+                settings.glide_time = time.min(VoiceSettings::MAX_GLIDE_TIME);
+            }
+            Self::UnisonVoices => {
+                let max = VoiceSettings::MAX_UNISON_VOICES as i32;
+                settings.unison_voices =
+                    (settings.unison_voices as i32 + delta).max(1).min(max) as _;
+            }
+            Self::UnisonDetune => {
+                let detune = settings.unison_detune + delta as f32 * DETUNE_STEP;
+                settings.unison_detune = detune.max(0.0).min(VoiceSettings::MAX_UNISON_DETUNE);
+            }
+            Self::UnisonSpread => {
+                let spread = settings.unison_spread + delta as f32 * SPREAD_STEP;
+                settings.unison_spread = spread.max(0.0).min(1.0);
+            }
+            Self::UnisonRandomPhase => settings.unison_random_phase = !settings.unison_random_phase,
+            Self::Scale | Self::KeyboardMapping => unreachable!(),
+        }
+        engine.set_voice_settings(settings);
+    }
+}
+
+const MIN_GLIDE_TIME: f32 = 0.005;
+const GLIDE_STEP: f32 = 1.25;
+/// In cents.
+const DETUNE_STEP: f32 = 5.0;
+const SPREAD_STEP: f32 = 0.1;
+
+fn on_off(value: bool) -> String {
+    if value { "On" } else { "Off" }.to_owned()
+}
+
+/// Returns the option `delta` places after `current`, wrapping around at either end.
+fn cycle<T: Clone + PartialEq>(options: &[T], current: T, delta: i32) -> T {
+    let index = options.iter().position(|o| *o == current).unwrap() as i32;
+    options[(index + delta).rem_euclid(options.len() as i32) as usize].clone()
+}
+
+/// Turns something like `Factory:tunings/19-TET.scl` into `19-TET`.
+fn short_file_name(name: &Option<String>, default: &str) -> String {
+    if let Some(name) = name {
+        let start = name
+            .rfind(|c| c == '/' || c == ':')
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let end = name.rfind('.').filter(|i| *i > start).unwrap_or(name.len());
+        name[start..end].to_owned()
+    } else {
+        default.to_owned()
+    }
+}
+
+const ROW_HEIGHT: f32 = grid(1) + GRID_P;
+const LABEL_WIDTH: f32 = grid(8);
+const VALUE_WIDTH: f32 = grid(6);
+
+impl PatchSettings {
+    pub fn new(parent: &impl PatchSettingsParent) -> Rc<Self> {
+        let state = PatchSettingsState {};
+        Rc::new(Self::create(parent, state))
+    }
+
+    fn setting_at(pos: Vec2D) -> Option<Setting> {
+        if pos.x < GRID_P || pos.x > GRID_P + LABEL_WIDTH + VALUE_WIDTH || pos.y < GRID_P {
+            return None;
+        }
+        let index = ((pos.y - GRID_P) / ROW_HEIGHT) as usize;
+        Setting::ALL.get(index).cloned()
+    }
+
+    fn adjust_setting(self: &Rc<Self>, setting: Setting, delta: i32) {
+        let engine = self.with_gui_state(|state| Rc::clone(&state.engine));
+        setting.adjust(&mut *engine.borrow_mut(), delta);
+    }
+}
+
+impl WidgetImpl<Renderer, DropTarget> for PatchSettings {
+    fn get_pos_impl(self: &Rc<Self>) -> Vec2D {
+        (0.0, HEADER_HEIGHT).into()
+    }
+
+    fn get_size_impl(self: &Rc<Self>) -> Vec2D {
+        TAB_BODY_SIZE
+    }
+
+    fn get_mouse_behavior_impl(
+        self: &Rc<Self>,
+        pos: Vec2D,
+        mods: &MouseMods,
+    ) -> MaybeMouseBehavior {
+        let setting = Self::setting_at(pos)?;
+        let delta = if mods.right_click { -1 } else { 1 };
+        let this = Rc::clone(self);
+        OnClickBehavior::wrap(move || this.adjust_setting(setting, delta))
+    }
+
+    fn on_scroll_impl(self: &Rc<Self>, pos: Vec2D, delta: f32) -> Option<()> {
+        let setting = Self::setting_at(pos)?;
+        self.adjust_setting(setting, if delta > 0.0 { 1 } else { -1 });
+        Some(())
+    }
+
+    fn on_hover_impl(self: &Rc<Self>, pos: Vec2D) -> Option<()> {
+        let setting = Self::setting_at(pos)?;
+        let tooltip = Tooltip {
+            text: setting.tooltip().to_owned(),
+            interaction: vec![
+                InteractionHint::LeftClick,
+                InteractionHint::RightClick,
+                InteractionHint::Scroll,
+            ],
+        };
+        self.with_gui_state_mut(|state| state.set_tooltip(tooltip));
+        Some(())
+    }
+
+    fn draw_impl(self: &Rc<Self>, g: &mut Renderer) {
+        const GP: f32 = GRID_P;
+        g.set_color(&COLOR_BG2);
+        g.draw_rect(0, TAB_BODY_SIZE);
+
+        let engine = self.with_gui_state(|state| Rc::clone(&state.engine));
+        let engine = engine.borrow();
+        for (index, setting) in Setting::ALL.iter().enumerate() {
+            let y = GP + ROW_HEIGHT * index as f32;
+            g.set_color(&COLOR_FG1);
+            g.draw_text(
+                FONT_SIZE,
+                (GP, y),
+                (LABEL_WIDTH, ROW_HEIGHT),
+                (-1, 0),
+                1,
+                setting.label(),
+            );
+            let value_pos = (GP + LABEL_WIDTH, y + GP / 2.0);
+            let value_size = (VALUE_WIDTH, ROW_HEIGHT - GP);
+            g.set_color(&COLOR_BG0);
+            g.draw_rounded_rect(value_pos, value_size, CORNER_SIZE);
+            g.set_color(&COLOR_FG1);
+            g.draw_text(
+                FONT_SIZE,
+                value_pos,
+                value_size,
+                (0, 0),
+                1,
+                &setting.display(&*engine),
+            );
+        }
+    }
+}
+
+impl GuiTab for Rc<PatchSettings> {
+    fn get_name(self: &Self) -> String {
+        format!("Patch Settings")
+    }
+
+    fn get_archetype(&self) -> TabArchetype {
+        TabArchetype::PatchSettings
+    }
+}
@@ -45,6 +45,10 @@ impl TextBox {
         self.state.borrow().field.borrow().text.clone()
     }
 
+    pub fn is_focused(&self) -> bool {
+        self.state.borrow().field.borrow().is_focused()
+    }
+
     pub fn set_enabled(&self, enabled: bool) {
         self.state.borrow_mut().enabled = enabled;
     }
@@ -5,6 +5,11 @@ mod registry;
 mod scui_config;
 
 use crossbeam_channel::{Receiver, Sender, TryRecvError};
+pub use engine::data_transfer::NUM_HOST_PARAMETERS;
+pub use engine::{
+    write_wav, HostParameterCallbacks, HostParameterInfo, NoteEvent, NoteExpression,
+    OfflineRenderer, Pedal, TimedNoteEvent, WavFormat,
+};
 use engine::{AudioThreadEngine, UiThreadEngine};
 use gui::graphics::GrahpicsWrapper;
 pub use gui::graphics::GraphicsFunctions;
@@ -47,6 +52,8 @@ pub struct Instance {
     pub registry: Rcrc<Registry>,
     pub ui_engine: Rcrc<UiThreadEngine>,
     pub audio_engine: Rcrc<AudioThreadEngine>,
+    /// Can be read without borrowing ui_engine, which hosts may ask for from any thread.
+    pub host_parameter_info: Arc<HostParameterInfo>,
     pub graphics_fns: Rc<GraphicsFunctions>,
     pub gui: Option<Gui>,
     audio: Vec<f32>,
@@ -78,6 +85,7 @@ impl Instance {
         observatory::init();
         let registry = rcrc(Registry::new()?);
         let (ui_engine, audio_engine) = engine::new_engine(Rc::clone(&registry))?;
+        let host_parameter_info = ui_engine.borrow().host_parameter_info();
         let graphics_fns = Rc::new(GraphicsFunctions::placeholders());
         let (audio_request_pipe, ui_request_pipe) = crossbeam_channel::bounded(1);
         let (ui_response_pipe, audio_response_pipe) = crossbeam_channel::bounded(1);
@@ -85,6 +93,7 @@ impl Instance {
             registry,
             ui_engine,
             audio_engine,
+            host_parameter_info,
             graphics_fns,
             gui: None,
             audio: Vec::new(),
@@ -201,8 +210,11 @@ impl Instance {
         }
     }
 
-    pub fn audio_render_audio(&mut self) -> &[f32] {
-        self.audio = self.audio_engine.borrow_mut().render_audio();
+    /// `host_input` should be in the same format as the returned audio, or empty if there is none.
+    pub fn audio_render_audio(&mut self, host_input: &[f32]) -> &[f32] {
+        self.audio_engine
+            .borrow_mut()
+            .render_audio(host_input, &mut self.audio);
         &self.audio[..]
     }
 
@@ -4,6 +4,7 @@ use crate::{
         library_preload::{self, PreloadedLibrary, ZippedLibraryContentProvider},
         module_template::ModuleTemplate,
         save_data::Patch,
+        tuning::{KeyboardMapping, Scale},
         update_check::{self, UpdateInfo},
         yaml,
     },
@@ -34,6 +35,12 @@ pub struct Registry {
     unloaded_patches: Vec<(String, Option<PathBuf>, Vec<u8>)>,
     patches: Vec<Rcrc<Patch>>,
     patch_paths: HashMap<String, usize>,
+    /// Groups of modules exported to be reused in other patches, stored in the same format as
+    /// patches.
+    module_groups: Vec<Rcrc<Patch>>,
+
+    scales: HashMap<String, Scale>,
+    keyboard_mappings: HashMap<String, KeyboardMapping>,
 
     library_path: PathBuf,
     library_info: HashMap<String, LibraryInfo>,
@@ -151,8 +158,27 @@ impl Registry {
                 name, err
             )
         })?;
-        self.patch_paths.insert(name.to_owned(), self.patches.len());
-        self.patches.push(rcrc(patch));
+        if name.ends_with(".abgroup") {
+            self.module_groups.push(rcrc(patch));
+        } else {
+            self.patch_paths.insert(name.to_owned(), self.patches.len());
+            self.patches.push(rcrc(patch));
+        }
+        Ok(())
+    }
+
+    fn load_scale(&mut self, name: &str, buffer: Vec<u8>) -> Result<(), String> {
+        // Scala files are frequently not UTF-8, but everything important in them is ASCII.
+        let text = String::from_utf8_lossy(&buffer[..]);
+        let scale = Scale::parse(&text, name)?;
+        self.scales.insert(name.to_owned(), scale);
+        Ok(())
+    }
+
+    fn load_keyboard_mapping(&mut self, name: &str, buffer: Vec<u8>) -> Result<(), String> {
+        let text = String::from_utf8_lossy(&buffer[..]);
+        let mapping = KeyboardMapping::parse(&text, name)?;
+        self.keyboard_mappings.insert(name.to_owned(), mapping);
         Ok(())
     }
 
@@ -187,8 +213,12 @@ impl Registry {
             self.load_general_script_resource(lib_name, &full_name, buffer)?;
         } else if file_name.ends_with(".module.jl") {
             self.load_module_script_resource(lib_name, &full_name, buffer)?;
-        } else if file_name.ends_with(".abpatch") {
+        } else if file_name.ends_with(".abpatch") || file_name.ends_with(".abgroup") {
             self.unloaded_patches.push((full_name, full_path, buffer));
+        } else if file_name.ends_with(".scl") {
+            self.load_scale(&full_name, buffer)?;
+        } else if file_name.ends_with(".kbm") {
+            self.load_keyboard_mapping(&full_name, buffer)?;
         } else if file_name.ends_with(".md") {
             // Ignore, probably just readme / license type stuff.
         } else if file_name == "library_info.yaml" {
@@ -386,6 +416,10 @@ impl Registry {
             unloaded_patches: Vec::new(),
             patches: Vec::new(),
             patch_paths: HashMap::new(),
+            module_groups: Vec::new(),
+
+            scales: HashMap::new(),
+            keyboard_mappings: HashMap::new(),
 
             library_path,
             library_info: HashMap::new(),
@@ -396,6 +430,61 @@ impl Registry {
         Ok(registry)
     }
 
+    /// Creates a registry containing only a Factory library with the given modules, so that tests
+    /// do not depend on the libraries installed on the computer running them. Each module is
+    /// given as a file name and the contents of its yaml file.
+    #[cfg(test)]
+    pub(crate) fn for_tests(modules: &[(&str, &str)]) -> Result<Self, String> {
+        let (_, receiver) = mpsc::channel();
+        let mut registry = Self {
+            module_templates: Vec::new(),
+            modules_by_resource_id: HashMap::new(),
+            modules_by_serialized_id: HashMap::new(),
+
+            general_scripts_by_library: HashMap::new(),
+            module_scripts_by_library: HashMap::new(),
+
+            icon_indexes: HashMap::new(),
+            icons: Vec::new(),
+
+            unloaded_patches: Vec::new(),
+            patches: Vec::new(),
+            patch_paths: HashMap::new(),
+            module_groups: Vec::new(),
+
+            scales: HashMap::new(),
+            keyboard_mappings: HashMap::new(),
+
+            library_path: std::env::temp_dir(),
+            library_info: HashMap::new(),
+            checked_updates: HashMap::new(),
+            update_check_stream: receiver,
+        };
+        for name in &["Factory", "User"] {
+            let info = LibraryInfo {
+                internal_name: (*name).to_owned(),
+                pretty_name: (*name).to_owned(),
+                description: String::new(),
+                version: ENGINE_VERSION,
+                dependencies: Vec::new(),
+            };
+            registry.library_info.insert((*name).to_owned(), info);
+        }
+        // Module templates look up the icons of their outputs.
+        for icon in &["pitch", "waveform", "audio", "trigger"] {
+            let icon_id = format!("Factory:{}", icon);
+            registry.icon_indexes.insert(icon_id, registry.icons.len());
+            registry.icons.push(Vec::new());
+        }
+        for (name, yaml) in modules {
+            let module_id = name.replace(".module.yaml", "");
+            let file_name = format!("Factory:{}", name);
+            let buffer = yaml.as_bytes().to_owned();
+            registry.load_module_resource(&file_name, "Factory".to_owned(), module_id, buffer)?;
+        }
+        Ok(registry)
+    }
+
     pub fn borrow_templates(&self) -> &[Rcrc<ModuleTemplate>] {
         &self.module_templates
     }
@@ -439,6 +528,17 @@ impl Registry {
         self.patches.last().unwrap()
     }
 
+    pub fn create_new_user_group(&mut self) -> &Rcrc<Patch> {
+        let filename = format!("{:016X}.abgroup", rand::thread_rng().next_u64());
+        let group = Patch::new(self.library_path.join("User").join(filename));
+        self.module_groups.push(rcrc(group));
+        self.module_groups.last().unwrap()
+    }
+
+    pub fn borrow_module_groups(&self) -> &Vec<Rcrc<Patch>> {
+        &self.module_groups
+    }
+
     pub fn get_patch_by_name(&self, name: &str) -> Option<&Rcrc<Patch>> {
         self.patch_paths.get(name).map(|i| &self.patches[*i])
     }
@@ -447,6 +547,28 @@ impl Registry {
         &self.patches
     }
 
+    pub fn borrow_scale(&self, name: &str) -> Option<&Scale> {
+        self.scales.get(name)
+    }
+
+    /// Returns the names of all loaded scales in alphabetical order.
+    pub fn get_scale_names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.scales.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    pub fn borrow_keyboard_mapping(&self, name: &str) -> Option<&KeyboardMapping> {
+        self.keyboard_mappings.get(name)
+    }
+
+    /// Returns the names of all loaded keyboard mappings in alphabetical order.
+    pub fn get_keyboard_mapping_names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.keyboard_mappings.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
     pub fn borrow_library_info(&self, name: &str) -> Option<&LibraryInfo> {
         self.library_info.get(name)
     }
@@ -2,6 +2,7 @@ mod base;
 pub mod library_preload;
 pub mod module_template;
 pub mod save_data;
+pub mod tuning;
 pub mod update_check;
 pub mod yaml;
 
@@ -24,6 +24,11 @@ pub(super) fn create_module_template_from_yaml(
     let save_id = yaml
         .map_entry("save_id")?
         .parse_ranged(Some(0), Some(0xFFFF))?;
+    let effects_only = if let Ok(node) = yaml.map_entry("effects_only") {
+        node.parse()?
+    } else {
+        false
+    };
 
     let mut gui_description = yaml.map_entry("gui")?;
     let mut widgets_description = gui_description.map_entry("widgets")?;
@@ -88,6 +93,7 @@ pub(super) fn create_module_template_from_yaml(
         lib_name,
         module_name: name,
         save_id,
+        effects_only,
 
         label,
         category,
@@ -105,6 +111,9 @@ pub struct ModuleTemplate {
     pub lib_name: String,
     pub module_name: String,
     pub save_id: usize,
+    /// If true, this module and everything connected after it runs once on the mix of every
+    /// voice instead of once per voice.
+    pub effects_only: bool,
 
     pub label: String,
     pub category: String,
@@ -1,5 +1,9 @@
 use crate::{
-    engine::{controls::AutomationSource, parts as ep},
+    engine::{
+        controls::{AnyControl, AutomationSource, MacroLane, MidiBinding},
+        data_transfer::NUM_HOST_PARAMETERS,
+        parts as ep,
+    },
     registry::Registry,
 };
 use shared_util::{
@@ -23,6 +27,35 @@ pub struct Patch {
     data: Vec<u8>,
 }
 
+/// Identifies optional sections stored after the module controls. Patches saved before a section
+/// existed simply end before it, and the padding at the end of the data reads as "no more
+/// sections".
+const VOICE_SETTINGS_SECTION: u8 = 0;
+const MONO_SETTINGS_SECTION: u8 = 1;
+const TUNING_SECTION: u8 = 2;
+const MIDI_BINDINGS_SECTION: u8 = 3;
+const HOST_PARAMETERS_SECTION: u8 = 4;
+const MACROS_SECTION: u8 = 5;
+const UNISON_SETTINGS_SECTION: u8 = 6;
+const GROUPS_SECTION: u8 = 7;
+
+/// Refers to either a module which is not a group or a group, by its index in the list of modules
+/// or groups in the patch.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MemberRef {
+    Module(usize),
+    Group(usize),
+}
+
+/// A group as it is stored in a patch, see `Patch::save_groups`.
+struct GroupEntry {
+    name: String,
+    pos: (f32, f32),
+    members: Vec<MemberRef>,
+    exposed_controls: Vec<(MemberRef, usize)>,
+    exposed_outputs: Vec<(MemberRef, usize)>,
+}
+
 impl Patch {
     const FORMAT_VERSION: u8 = 2;
 
@@ -107,7 +140,8 @@ impl Patch {
                 .unwrap() as u8
         };
 
-        let ordered_modules = Vec::from(graph.borrow_modules());
+        // Groups are saved separately, only the modules inside them are saved here.
+        let ordered_modules = graph.flattened_modules();
         assert!(ordered_modules.len() < 0x100);
         ser.note("Num modules: ");
         ser.u8(ordered_modules.len() as _);
@@ -115,10 +149,9 @@ impl Patch {
             ordered_modules
                 .iter()
                 .position(|other| Rc::ptr_eq(rc, other))
-                .unwrap() as u8
         };
         ser.note("Modules: ");
-        for module in graph.borrow_modules() {
+        for module in &ordered_modules {
             let module = module.borrow();
             let template = module.template.borrow();
             ser.note("<lib ");
@@ -132,25 +165,182 @@ impl Patch {
             ser.note("> ");
         }
         ser.note("Module controls: ");
-        for module in graph.borrow_modules() {
+        for module in &ordered_modules {
             let module = module.borrow();
             for (index, control) in module.controls.iter().enumerate() {
                 ser.note(&format!("c{}: <", index));
                 let control_ptr = control.as_dyn_ptr();
                 let control = control_ptr.borrow();
                 for source in control.get_connected_automation() {
-                    ser.bool(true);
-                    ser.u8(mod_index(&source.module));
-                    ser.u4(source.output_index as _);
+                    // Wires always point at the module that actually produces the output, which
+                    // groups are rebuilt around when loading. When only part of a graph is being
+                    // saved, wires coming from outside of it are left out.
+                    let (module, output_index) =
+                        ep::Module::resolve_output(&source.module, source.output_index);
+                    if let Some(module_index) = mod_index(&module) {
+                        ser.bool(true);
+                        ser.u8(module_index as _);
+                        ser.u4(output_index as _);
+                    }
                 }
                 ser.bool(false);
                 control.serialize(&mut ser);
                 ser.note("> ");
             }
         }
+        ser.note("Voice settings: ");
+        ser.bool(true);
+        ser.u8(VOICE_SETTINGS_SECTION);
+        graph.voice_settings.serialize_polyphony(&mut ser);
+        ser.note("Mono settings: ");
+        ser.bool(true);
+        ser.u8(MONO_SETTINGS_SECTION);
+        graph.voice_settings.serialize_mono(&mut ser);
+        ser.note("Unison settings: ");
+        ser.bool(true);
+        ser.u8(UNISON_SETTINGS_SECTION);
+        graph.voice_settings.serialize_unison(&mut ser);
+        ser.note("Tuning: ");
+        ser.bool(true);
+        ser.u8(TUNING_SECTION);
+        graph.tuning.serialize(&mut ser);
+        ser.note("MIDI bindings: ");
+        ser.bool(true);
+        ser.u8(MIDI_BINDINGS_SECTION);
+        for (module_index, module) in ordered_modules.iter().enumerate() {
+            for (control_index, control) in module.borrow().controls.iter().enumerate() {
+                if let AnyControl::FloatInRange(control) = control {
+                    let control = control.borrow();
+                    if let Some(binding) = &control.midi_binding {
+                        ser.bool(true);
+                        ser.u8(module_index as _);
+                        ser.u8(control_index as _);
+                        binding.serialize(&mut ser, control.range);
+                    }
+                }
+            }
+        }
+        ser.bool(false);
+        ser.note("Host parameters: ");
+        ser.bool(true);
+        ser.u8(HOST_PARAMETERS_SECTION);
+        for (module_index, module) in ordered_modules.iter().enumerate() {
+            for (control_index, control) in module.borrow().controls.iter().enumerate() {
+                if let AnyControl::FloatInRange(control) = control {
+                    if let Some(parameter) = control.borrow().host_parameter {
+                        ser.bool(true);
+                        ser.u8(module_index as _);
+                        ser.u8(control_index as _);
+                        ser.u8(parameter as _);
+                    }
+                }
+            }
+        }
+        ser.bool(false);
+        ser.note("Macros: ");
+        ser.bool(true);
+        ser.u8(MACROS_SECTION);
+        Self::save_macros(&mut ser, graph, &ordered_modules);
+        ser.note("Groups: ");
+        ser.bool(true);
+        ser.u8(GROUPS_SECTION);
+        Self::save_groups(&mut ser, graph, &ordered_modules);
+        ser.bool(false);
         self.data = ser.finish();
     }
 
+    fn save_macros(ser: &mut MiniSer, graph: &ep::ModuleGraph, modules: &[Rcrc<ep::Module>]) {
+        for patch_macro in &graph.macros {
+            let control = patch_macro.control.borrow();
+            ser.str(&patch_macro.name);
+            ser.f32_in_range(control.value, control.range.0, control.range.1);
+            if let Some(binding) = &control.midi_binding {
+                ser.bool(true);
+                binding.serialize(ser, control.range);
+            } else {
+                ser.bool(false);
+            }
+            if let Some(parameter) = control.host_parameter {
+                ser.bool(true);
+                ser.u8(parameter as _);
+            } else {
+                ser.bool(false);
+            }
+        }
+        for (module_index, module) in modules.iter().enumerate() {
+            for (control_index, control) in module.borrow().controls.iter().enumerate() {
+                if let AnyControl::FloatInRange(control) = control {
+                    let control = control.borrow();
+                    for lane in &control.macro_lanes {
+                        ser.bool(true);
+                        ser.u8(module_index as _);
+                        ser.u8(control_index as _);
+                        ser.u3(lane.index as _);
+                        ser.f32_in_range(lane.range.0, control.range.0, control.range.1);
+                        ser.f32_in_range(lane.range.1, control.range.0, control.range.1);
+                    }
+                }
+            }
+        }
+        ser.bool(false);
+    }
+
+    /// Groups are stored with every group coming before the groups inside it. Each one lists what
+    /// is directly inside it followed by what it exposes on its face.
+    fn save_groups(ser: &mut MiniSer, graph: &ep::ModuleGraph, modules: &[Rcrc<ep::Module>]) {
+        let groups: Vec<_> = graph
+            .all_modules()
+            .into_iter()
+            .filter(|module| module.borrow().group.is_some())
+            .collect();
+        assert!(groups.len() < 0x100);
+        let member_ref = |module: &Rcrc<ep::Module>| {
+            let position =
+                |list: &[Rcrc<ep::Module>]| list.iter().position(|other| Rc::ptr_eq(other, module));
+            if let Some(index) = position(&groups) {
+                MemberRef::Group(index)
+            } else {
+                MemberRef::Module(position(modules).unwrap())
+            }
+        };
+        let write_ref = |ser: &mut MiniSer, member: MemberRef| match member {
+            MemberRef::Module(index) => {
+                ser.bool(false);
+                ser.u8(index as _);
+            }
+            MemberRef::Group(index) => {
+                ser.bool(true);
+                ser.u8(index as _);
+            }
+        };
+        for group in &groups {
+            let group = group.borrow();
+            let data = group.group.as_ref().unwrap();
+            ser.bool(true);
+            ser.str(&data.name);
+            ser.i32(group.pos.0 as _);
+            ser.i32(group.pos.1 as _);
+            for member in &data.modules {
+                ser.bool(true);
+                write_ref(ser, member_ref(member));
+            }
+            ser.bool(false);
+            for (member, index) in &data.exposed_controls {
+                ser.bool(true);
+                write_ref(ser, member_ref(member));
+                ser.u8(*index as _);
+            }
+            ser.bool(false);
+            for (member, index) in &data.exposed_outputs {
+                ser.bool(true);
+                write_ref(ser, member_ref(member));
+                ser.u8(*index as _);
+            }
+            ser.bool(false);
+        }
+        ser.bool(false);
+    }
+
     pub fn restore_note_graph(
         &self,
         graph: &mut ep::ModuleGraph,
@@ -210,10 +400,276 @@ impl Patch {
                 control.deserialize(&mut des)?;
             }
         }
+        let mut voice_settings = ep::VoiceSettings::default();
+        let mut tuning = ep::TuningSettings::default();
+        let mut macros = ep::Macro::default_macros();
+        let mut group_entries = Vec::new();
+        while des.bool().unwrap_or(false) {
+            match des.u8()? {
+                VOICE_SETTINGS_SECTION => voice_settings.deserialize_polyphony(&mut des)?,
+                MONO_SETTINGS_SECTION => voice_settings.deserialize_mono(&mut des)?,
+                TUNING_SECTION => tuning.deserialize(&mut des)?,
+                MIDI_BINDINGS_SECTION => Self::restore_midi_bindings(&mut des, &modules)?,
+                HOST_PARAMETERS_SECTION => Self::restore_host_parameters(&mut des, &modules)?,
+                MACROS_SECTION => Self::restore_macros(&mut des, &modules, &mut macros)?,
+                UNISON_SETTINGS_SECTION => voice_settings.deserialize_unison(&mut des)?,
+                GROUPS_SECTION => Self::restore_groups(&mut des, &mut group_entries)?,
+                _ => return Err(()),
+            }
+        }
+        let modules = Self::restore_group_structure(modules, group_entries)?;
         graph.set_modules(modules);
+        graph.voice_settings = voice_settings;
+        graph.tuning = tuning;
+        graph.macros = macros;
         Ok(())
     }
 
+    /// Restores a group that was exported on its own, see `UiThreadEngine::export_module_group`.
+    pub fn restore_module_group(&self, registry: &Registry) -> Result<Rcrc<ep::Module>, ()> {
+        let mut graph = ep::ModuleGraph::new();
+        self.restore_note_graph(&mut graph, registry)?;
+        let modules = graph.borrow_modules();
+        if modules.len() != 1 || modules[0].borrow().group.is_none() {
+            return Err(());
+        }
+        // The host parameters the group used belong to the patch it was exported from, and may
+        // already be assigned to something else in the patch it is being added to.
+        for module in graph.flattened_modules() {
+            for control in &module.borrow().controls {
+                if let AnyControl::FloatInRange(control) = control {
+                    control.borrow_mut().host_parameter = None;
+                }
+            }
+        }
+        Ok(Rc::clone(&modules[0]))
+    }
+
+    fn restore_midi_bindings(des: &mut MiniDes, modules: &[Rcrc<ep::Module>]) -> Result<(), ()> {
+        while des.bool()? {
+            let module = modules.get(des.u8()? as usize).ok_or(())?.borrow();
+            let control = module.controls.get(des.u8()? as usize).ok_or(())?;
+            if let AnyControl::FloatInRange(control) = control {
+                let mut control = control.borrow_mut();
+                control.midi_binding = Some(MidiBinding::deserialize(des, control.range)?);
+            } else {
+                return Err(());
+            }
+        }
+        Ok(())
+    }
+
+    fn restore_host_parameters(des: &mut MiniDes, modules: &[Rcrc<ep::Module>]) -> Result<(), ()> {
+        while des.bool()? {
+            let module = modules.get(des.u8()? as usize).ok_or(())?.borrow();
+            let control = module.controls.get(des.u8()? as usize).ok_or(())?;
+            let parameter = des.u8()? as usize;
+            if parameter >= NUM_HOST_PARAMETERS {
+                return Err(());
+            }
+            if let AnyControl::FloatInRange(control) = control {
+                control.borrow_mut().host_parameter = Some(parameter);
+            } else {
+                return Err(());
+            }
+        }
+        Ok(())
+    }
+
+    fn restore_macros(
+        des: &mut MiniDes,
+        modules: &[Rcrc<ep::Module>],
+        macros: &mut [ep::Macro],
+    ) -> Result<(), ()> {
+        for patch_macro in macros {
+            patch_macro.name = des.str()?;
+            let mut control = patch_macro.control.borrow_mut();
+            control.value = des.f32_in_range(control.range.0, control.range.1)?;
+            if des.bool()? {
+                control.midi_binding = Some(MidiBinding::deserialize(des, control.range)?);
+            }
+            if des.bool()? {
+                let parameter = des.u8()? as usize;
+                if parameter >= NUM_HOST_PARAMETERS {
+                    return Err(());
+                }
+                control.host_parameter = Some(parameter);
+            }
+        }
+        while des.bool()? {
+            let module = modules.get(des.u8()? as usize).ok_or(())?.borrow();
+            let control = module.controls.get(des.u8()? as usize).ok_or(())?;
+            let index = des.u3()? as usize;
+            if index >= ep::Macro::NUM_MACROS {
+                return Err(());
+            }
+            if let AnyControl::FloatInRange(control) = control {
+                let mut control = control.borrow_mut();
+                let (min, max) = control.range;
+                let range = (des.f32_in_range(min, max)?, des.f32_in_range(min, max)?);
+                control.macro_lanes.push(MacroLane { index, range });
+            } else {
+                return Err(());
+            }
+        }
+        Ok(())
+    }
+
+    fn restore_groups(des: &mut MiniDes, groups: &mut Vec<GroupEntry>) -> Result<(), ()> {
+        fn read_ref(des: &mut MiniDes) -> Result<MemberRef, ()> {
+            Ok(if des.bool()? {
+                MemberRef::Group(des.u8()? as usize)
+            } else {
+                MemberRef::Module(des.u8()? as usize)
+            })
+        }
+        while des.bool()? {
+            let name = des.str()?;
+            let pos = (des.i32()? as _, des.i32()? as _);
+            let mut members = Vec::new();
+            while des.bool()? {
+                members.push(read_ref(des)?);
+            }
+            let mut exposed_controls = Vec::new();
+            while des.bool()? {
+                exposed_controls.push((read_ref(des)?, des.u8()? as usize));
+            }
+            let mut exposed_outputs = Vec::new();
+            while des.bool()? {
+                exposed_outputs.push((read_ref(des)?, des.u8()? as usize));
+            }
+            groups.push(GroupEntry {
+                name,
+                pos,
+                members,
+                exposed_controls,
+                exposed_outputs,
+            });
+        }
+        Ok(())
+    }
+
+    /// Rebuilds groups around the modules inside them and returns what is left at the top level
+    /// of the graph. Wires are saved as coming directly from the module that produces them, so
+    /// they are changed to come from whichever module can be seen from the place they were made
+    /// in, which is either the module itself or a group it is inside of.
+    fn restore_group_structure(
+        modules: Vec<Rcrc<ep::Module>>,
+        entries: Vec<GroupEntry>,
+    ) -> Result<Vec<Rcrc<ep::Module>>, ()> {
+        let mut module_parents = vec![None; modules.len()];
+        let mut group_parents = vec![None; entries.len()];
+        for (index, entry) in entries.iter().enumerate() {
+            for member in &entry.members {
+                let parent = match *member {
+                    MemberRef::Module(member) => module_parents.get_mut(member),
+                    // Groups always come after the group they are inside of, so there cannot be
+                    // any cycles.
+                    MemberRef::Group(member) if member > index => group_parents.get_mut(member),
+                    MemberRef::Group(..) => None,
+                }
+                .ok_or(())?;
+                if parent.is_some() {
+                    return Err(());
+                }
+                *parent = Some(index);
+            }
+        }
+
+        let mut groups: Vec<Option<Rcrc<ep::Module>>> = vec![None; entries.len()];
+        for (index, entry) in entries.into_iter().enumerate().rev() {
+            let member = |member: MemberRef| {
+                if !entry.members.contains(&member) {
+                    return Err(());
+                }
+                Ok(match member {
+                    MemberRef::Module(index) => Rc::clone(&modules[index]),
+                    MemberRef::Group(index) => Rc::clone(groups[index].as_ref().unwrap()),
+                })
+            };
+            let mut group = ep::ModuleGroup {
+                name: entry.name.clone(),
+                ..Default::default()
+            };
+            for &module in &entry.members {
+                group.modules.push(member(module)?);
+            }
+            for &(module, control_index) in &entry.exposed_controls {
+                let module = member(module)?;
+                if control_index >= module.borrow().controls.len() {
+                    return Err(());
+                }
+                group.exposed_controls.push((module, control_index));
+            }
+            for &(module, output_index) in &entry.exposed_outputs {
+                let module = member(module)?;
+                if output_index >= module.borrow().template.borrow().outputs.len() {
+                    return Err(());
+                }
+                group.exposed_outputs.push((module, output_index));
+            }
+            groups[index] = Some(rcrc(ep::Module::create_group(group, entry.pos)));
+        }
+        let groups: Vec<_> = groups.into_iter().map(Option::unwrap).collect();
+
+        // Every group something is inside of, innermost first and ending with the top level.
+        let containers = |mut parent: Option<usize>| {
+            let mut result = vec![parent];
+            while let Some(index) = parent {
+                parent = group_parents[index];
+                result.push(parent);
+            }
+            result
+        };
+        for (consumer_index, consumer) in modules.iter().enumerate() {
+            let consumer_containers = containers(module_parents[consumer_index]);
+            for control in &consumer.borrow().controls {
+                let control_ptr = control.as_dyn_ptr();
+                let mut control = control_ptr.borrow_mut();
+                for source in control.get_connected_automation_mut() {
+                    let mut parent = modules
+                        .iter()
+                        .position(|other| Rc::ptr_eq(other, &source.module))
+                        .map(|index| module_parents[index])
+                        .ok_or(())?;
+                    // The wire was made in the innermost place both ends can be seen from.
+                    let mut visible = Rc::clone(&source.module);
+                    while !consumer_containers.contains(&parent) {
+                        // Both lists of containers end with the top level, so this is a group.
+                        let index = parent.unwrap();
+                        visible = Rc::clone(&groups[index]);
+                        parent = group_parents[index];
+                    }
+                    if Rc::ptr_eq(&visible, &source.module) {
+                        continue;
+                    }
+                    let num_outputs = visible.borrow().template.borrow().outputs.len();
+                    let output_index = (0..num_outputs)
+                        .find(|index| {
+                            let (module, index) = ep::Module::resolve_output(&visible, *index);
+                            Rc::ptr_eq(&module, &source.module) && index == source.output_index
+                        })
+                        .ok_or(())?;
+                    source.module = visible;
+                    source.output_index = output_index;
+                }
+            }
+        }
+
+        let mut top_level = Vec::new();
+        for (module, parent) in modules.iter().zip(module_parents.iter()) {
+            if parent.is_none() {
+                top_level.push(Rc::clone(module));
+            }
+        }
+        for (group, parent) in groups.iter().zip(group_parents.iter()) {
+            if parent.is_none() {
+                top_level.push(Rc::clone(group));
+            }
+        }
+        Ok(top_level)
+    }
+
     pub fn write(&mut self) -> Result<(), Box<dyn Error>> {
         let path = if let PatchSource::Writable(path) = &self.source {
             path
@@ -273,3 +729,245 @@ impl Patch {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ENGINE_VERSION;
+
+    const SOURCE: &str = "
+save_id: 0
+outputs:
+  audio:
+    datatype: audio
+    label: Audio
+    tooltip: The produced audio
+controls:
+  level:
+    type: FloatInRange
+    min: 0
+    max: 1
+    default: 0.5
+gui:
+  label: Source
+  category: Test
+  tooltip: Produces audio
+  width: 1
+  height: 1
+  widgets:
+";
+
+    const PASS: &str = "
+save_id: 1
+outputs:
+  audio:
+    datatype: audio
+    label: Audio
+    tooltip: The same audio
+controls:
+  audio:
+    type: Input
+    datatype: audio
+gui:
+  label: Pass
+  category: Test
+  tooltip: Passes audio through
+  width: 1
+  height: 1
+  widgets:
+";
+
+    fn registry() -> Registry {
+        Registry::for_tests(&[("Source.module.yaml", SOURCE), ("Pass.module.yaml", PASS)]).unwrap()
+    }
+
+    fn create(registry: &Registry, save_id: usize) -> Rcrc<ep::Module> {
+        let id = ("Factory".to_owned(), save_id);
+        let template = registry.borrow_template_by_serialized_id(&id).unwrap();
+        rcrc(ep::Module::create(Rc::clone(template)))
+    }
+
+    /// Connects the first output of `from` to the input of a Pass module.
+    fn connect(from: &Rcrc<ep::Module>, to: &Rcrc<ep::Module>) {
+        let output_type = from.borrow().template.borrow().outputs[0].get_type();
+        let source = AutomationSource {
+            module: Rc::clone(from),
+            output_index: 0,
+            output_type,
+        };
+        let control = to.borrow().controls[0].as_dyn_ptr();
+        control.borrow_mut().connect_automation(source);
+    }
+
+    fn source_of(module: &Rcrc<ep::Module>) -> Rcrc<ep::Module> {
+        let control = module.borrow().controls[0].as_dyn_ptr();
+        let control = control.borrow();
+        let source = Rc::clone(&control.get_connected_automation()[0].module);
+        source
+    }
+
+    fn group(
+        name: &str,
+        modules: Vec<Rcrc<ep::Module>>,
+        output: &Rcrc<ep::Module>,
+    ) -> Rcrc<ep::Module> {
+        let group = ep::ModuleGroup {
+            name: name.to_owned(),
+            modules,
+            exposed_controls: Vec::new(),
+            exposed_outputs: vec![(Rc::clone(output), 0)],
+        };
+        rcrc(ep::Module::create_group(group, (0.0, 0.0)))
+    }
+
+    fn save(graph: &ep::ModuleGraph, registry: &Registry) -> Patch {
+        let mut patch = Patch::new_dummy("Test".to_owned());
+        patch.save_note_graph(graph, registry);
+        patch
+    }
+
+    fn entry(members: Vec<MemberRef>) -> GroupEntry {
+        GroupEntry {
+            name: "Group".to_owned(),
+            pos: (0.0, 0.0),
+            members,
+            exposed_controls: Vec::new(),
+            exposed_outputs: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn nested_groups_round_trip() {
+        let registry = registry();
+        // source -> first -> second -> last, where second is inside an inner group which is
+        // inside an outer group along with first.
+        let source = create(&registry, 0);
+        let first = create(&registry, 1);
+        let second = create(&registry, 1);
+        let last = create(&registry, 1);
+        connect(&source, &first);
+        connect(&first, &second);
+        let inner = group("Inner", vec![Rc::clone(&second)], &second);
+        let outer = group("Outer", vec![Rc::clone(&first), Rc::clone(&inner)], &inner);
+        connect(&outer, &last);
+        let mut graph = ep::ModuleGraph::new();
+        graph.set_modules(vec![source, outer, last]);
+        let patch = save(&graph, &registry);
+
+        let mut restored = ep::ModuleGraph::new();
+        patch.restore_note_graph(&mut restored, &registry).unwrap();
+        assert_eq!(save(&restored, &registry).data, patch.data);
+        let all = restored.all_modules();
+        let names: Vec<_> = all
+            .iter()
+            .map(|module| module.borrow().template.borrow().label.clone())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["Source", "Outer", "Pass", "Inner", "Pass", "Pass"]
+        );
+        // Wires come from whatever can be seen from where they were made.
+        assert!(Rc::ptr_eq(&source_of(&all[2]), &all[0]));
+        assert!(Rc::ptr_eq(&source_of(&all[4]), &all[2]));
+        assert!(Rc::ptr_eq(&source_of(&all[5]), &all[1]));
+        let outer = all[1].borrow();
+        let outer_members = &outer.group.as_ref().unwrap().modules;
+        assert!(Rc::ptr_eq(&outer_members[1], &all[3]));
+    }
+
+    #[test]
+    fn rejects_bad_member_order() {
+        let registry = registry();
+        let modules = || vec![create(&registry, 1), create(&registry, 1)];
+        // Groups must come before the groups inside them.
+        let entries = vec![
+            entry(vec![MemberRef::Module(0)]),
+            entry(vec![MemberRef::Group(0), MemberRef::Module(1)]),
+        ];
+        assert!(Patch::restore_group_structure(modules(), entries).is_err());
+        // A group cannot contain itself.
+        let entries = vec![entry(vec![MemberRef::Group(0)])];
+        assert!(Patch::restore_group_structure(modules(), entries).is_err());
+        // Nothing can be inside two groups at once.
+        let entries = vec![
+            entry(vec![MemberRef::Module(0)]),
+            entry(vec![MemberRef::Module(0), MemberRef::Module(1)]),
+        ];
+        assert!(Patch::restore_group_structure(modules(), entries).is_err());
+        // The correct order works.
+        let entries = vec![
+            entry(vec![MemberRef::Group(1), MemberRef::Module(1)]),
+            entry(vec![MemberRef::Module(0)]),
+        ];
+        let top_level = Patch::restore_group_structure(modules(), entries).unwrap();
+        assert_eq!(top_level.len(), 1);
+    }
+
+    #[test]
+    fn loads_patches_without_groups_section() {
+        let registry = registry();
+        let source = create(&registry, 0);
+        let pass = create(&registry, 1);
+        // Written the way patches were before groups existed, with no sections after the module
+        // controls except for the voice settings.
+        let mut ser = MiniSer::new();
+        ser.u8(1);
+        ser.str("Factory");
+        ser.version(ENGINE_VERSION);
+        ser.u8(2);
+        for save_id in 0..2 {
+            ser.u8(0);
+            ser.u8(save_id);
+            ser.i32(0);
+            ser.i32(0);
+        }
+        ser.bool(false);
+        source.borrow().controls[0]
+            .as_dyn_ptr()
+            .borrow()
+            .serialize(&mut ser);
+        ser.bool(true);
+        ser.u8(0);
+        ser.u4(0);
+        ser.bool(false);
+        pass.borrow().controls[0]
+            .as_dyn_ptr()
+            .borrow()
+            .serialize(&mut ser);
+        ser.bool(true);
+        ser.u8(VOICE_SETTINGS_SECTION);
+        ep::VoiceSettings::default().serialize_polyphony(&mut ser);
+        ser.bool(false);
+        let mut patch = Patch::new_dummy("Old".to_owned());
+        patch.data = ser.finish();
+
+        let mut graph = ep::ModuleGraph::new();
+        patch.restore_note_graph(&mut graph, &registry).unwrap();
+        let modules = graph.borrow_modules();
+        assert_eq!(modules.len(), 2);
+        assert!(modules.iter().all(|module| module.borrow().group.is_none()));
+        assert!(Rc::ptr_eq(&source_of(&modules[1]), &modules[0]));
+    }
+
+    #[test]
+    fn imported_groups_have_no_host_parameters() {
+        let registry = registry();
+        let source = create(&registry, 0);
+        if let AnyControl::FloatInRange(control) = &source.borrow().controls[0] {
+            control.borrow_mut().host_parameter = Some(3);
+        }
+        let exported = group("Exported", vec![Rc::clone(&source)], &source);
+        let mut graph = ep::ModuleGraph::new();
+        graph.add_module(exported);
+        let patch = save(&graph, &registry);
+
+        let imported = patch.restore_module_group(&registry).unwrap();
+        let imported = imported.borrow();
+        let inside = imported.group.as_ref().unwrap().modules[0].borrow();
+        if let AnyControl::FloatInRange(control) = &inside.controls[0] {
+            assert_eq!(control.borrow().host_parameter, None);
+        } else {
+            panic!("The control of the source module should be a FloatInRange.");
+        }
+    }
+}
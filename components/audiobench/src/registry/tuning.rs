@@ -0,0 +1,401 @@
+//! Parsing for Scala scale (.scl) and keyboard mapping (.kbm) files, and conversion of the two into
+//! the frequency each MIDI key should play. See http://www.huygens-fokker.org/scala/scl_format.html
+//! and http://www.huygens-fokker.org/scala/help.htm#mappings for the formats.
+
+const NUM_MIDI_NOTES: usize = 128;
+/// Mappings larger than this are almost certainly a mistake.
+const MAX_MAP_SIZE: usize = 1024;
+
+/// Returns all lines which are not comments, with surrounding whitespace removed.
+fn content_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.starts_with('!'))
+}
+
+/// Returns the first whitespace-separated word of a line. Anything after it is treated as a
+/// comment by both formats.
+fn first_word(line: &str) -> &str {
+    line.split_whitespace().next().unwrap_or("")
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scale {
+    pub description: String,
+    /// The frequency ratio of each degree of the scale relative to the first, not including the
+    /// first degree itself (which is always 1.) The last entry is the period of the scale, usually
+    /// 2.0 for an octave.
+    ratios: Vec<f32>,
+}
+
+impl Scale {
+    pub fn parse(text: &str, name: &str) -> Result<Self, String> {
+        let mut lines = content_lines(text);
+        let description = lines
+            .next()
+            .ok_or_else(|| format!("ERROR: {} is empty.", name))?
+            .to_owned();
+        let count = lines
+            .next()
+            .map(|line| first_word(line).parse::<usize>())
+            .ok_or_else(|| format!("ERROR: {} does not specify how many notes it has.", name))?
+            .map_err(|_| format!("ERROR: {} has an invalid note count.", name))?;
+        if count == 0 {
+            return Err(format!("ERROR: {} must contain at least one note.", name));
+        }
+        let mut ratios = Vec::with_capacity(count);
+        for line in lines.filter(|line| line.len() > 0).take(count) {
+            let word = first_word(line);
+            let ratio = Self::parse_pitch(word)
+                .ok_or_else(|| format!("ERROR: {} contains an invalid pitch: {}", name, word))?;
+            ratios.push(ratio);
+        }
+        if ratios.len() != count {
+            return Err(format!(
+                "ERROR: {} says it has {} notes but only contains {}.",
+                name,
+                count,
+                ratios.len()
+            ));
+        }
+        Ok(Self {
+            description,
+            ratios,
+        })
+    }
+
+    /// Pitches containing a period are in cents, anything else is a ratio like 3/2 or 2.
+    fn parse_pitch(word: &str) -> Option<f32> {
+        let ratio = if word.contains('.') {
+            let cents: f32 = word.parse().ok()?;
+            (2.0f32).powf(cents / 1200.0)
+        } else if let Some(slash) = word.find('/') {
+            let num: u64 = word[..slash].parse().ok()?;
+            let den: u64 = word[slash + 1..].parse().ok()?;
+            num as f32 / den as f32
+        } else {
+            word.parse::<u64>().ok()? as f32
+        };
+        if ratio.is_finite() && ratio > 0.0 {
+            Some(ratio)
+        } else {
+            None
+        }
+    }
+
+    pub fn num_notes(&self) -> usize {
+        self.ratios.len()
+    }
+
+    /// Returns the frequency ratio of the given degree relative to degree 0. Degrees outside the
+    /// scale repeat the scale shifted by its period.
+    fn ratio_of_degree(&self, degree: i32) -> f32 {
+        let len = self.ratios.len() as i32;
+        let periods = degree.div_euclid(len);
+        let step = degree.rem_euclid(len);
+        let period = *self.ratios.last().unwrap();
+        let step_ratio = if step == 0 {
+            1.0
+        } else {
+            self.ratios[step as usize - 1]
+        };
+        period.powi(periods) * step_ratio
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyboardMapping {
+    first_key: usize,
+    last_key: usize,
+    /// The key that the first entry of the mapping applies to.
+    middle_key: usize,
+    reference_key: usize,
+    reference_frequency: f32,
+    /// Which scale degree the mapping repeats at. Ignored when the mapping is empty.
+    octave_degree: i32,
+    /// Which scale degree each key in the repeating pattern plays, None for keys that should be
+    /// silent. An empty mapping means every key plays the next degree of the scale.
+    mapping: Vec<Option<i32>>,
+}
+
+impl Default for KeyboardMapping {
+    /// Maps middle C to the first degree of the scale and tunes A4 to 440Hz, which is what Scala
+    /// itself does when no mapping is specified.
+    fn default() -> Self {
+        Self {
+            first_key: 0,
+            last_key: NUM_MIDI_NOTES - 1,
+            middle_key: 60,
+            reference_key: 69,
+            reference_frequency: 440.0,
+            octave_degree: 0,
+            mapping: Vec::new(),
+        }
+    }
+}
+
+impl KeyboardMapping {
+    pub fn parse(text: &str, name: &str) -> Result<Self, String> {
+        let mut words = content_lines(text)
+            .filter(|line| line.len() > 0)
+            .map(first_word);
+        let mut next_number = |description: &str| -> Result<f32, String> {
+            let word = words
+                .next()
+                .ok_or_else(|| format!("ERROR: {} is missing the {}.", name, description))?;
+            word.parse()
+                .map_err(|_| format!("ERROR: {} has an invalid {}: {}", name, description, word))
+        };
+        let key = |value: f32, description: &str| -> Result<usize, String> {
+            if value >= 0.0 && value < NUM_MIDI_NOTES as f32 && value.fract() == 0.0 {
+                Ok(value as usize)
+            } else {
+                Err(format!("ERROR: {} has an invalid {}.", name, description))
+            }
+        };
+        let size = next_number("map size")?;
+        if size < 0.0 || size > MAX_MAP_SIZE as f32 || size.fract() != 0.0 {
+            return Err(format!("ERROR: {} has an invalid map size.", name));
+        }
+        let size = size as usize;
+        let first_key = key(next_number("first note")?, "first note")?;
+        let last_key = key(next_number("last note")?, "last note")?;
+        let middle_key = key(next_number("middle note")?, "middle note")?;
+        let reference_key = key(next_number("reference note")?, "reference note")?;
+        let reference_frequency = next_number("reference frequency")?;
+        if !(reference_frequency > 0.0) {
+            return Err(format!(
+                "ERROR: {} has an invalid reference frequency.",
+                name
+            ));
+        }
+        let octave_degree = next_number("octave degree")? as i32;
+        let mut mapping = Vec::with_capacity(size);
+        for word in words.take(size) {
+            if word == "x" {
+                mapping.push(None);
+            } else {
+                let degree = word.parse().map_err(|_| {
+                    format!(
+                        "ERROR: {} contains an invalid mapping entry: {}",
+                        name, word
+                    )
+                })?;
+                mapping.push(Some(degree));
+            }
+        }
+        // Keys missing from the end of the mapping are left unmapped.
+        mapping.resize(size, None);
+        Ok(Self {
+            first_key,
+            last_key,
+            middle_key,
+            reference_key,
+            reference_frequency,
+            octave_degree,
+            mapping,
+        })
+    }
+
+    /// Returns which degree of the scale the key plays, or None if the key is unmapped.
+    fn degree_of_key(&self, key: usize, scale: &Scale) -> Option<i32> {
+        let offset = key as i32 - self.middle_key as i32;
+        if self.mapping.len() == 0 {
+            return Some(offset);
+        }
+        let len = self.mapping.len() as i32;
+        let repetitions = offset.div_euclid(len);
+        let index = offset.rem_euclid(len) as usize;
+        let octave_degree = if self.octave_degree > 0 {
+            self.octave_degree
+        } else {
+            scale.num_notes() as i32
+        };
+        self.mapping[index].map(|degree| repetitions * octave_degree + degree)
+    }
+}
+
+/// The frequency each MIDI key should play, or None if pressing that key should do nothing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyFrequencies {
+    frequencies: Vec<Option<f32>>,
+}
+
+impl Default for KeyFrequencies {
+    /// Twelve tone equal temperament with A4 tuned to 440Hz.
+    fn default() -> Self {
+        // MIDI note 69 is 440Hz. 12 notes is an octave (double / half frequency).
+        let frequencies = (0..NUM_MIDI_NOTES)
+            .map(|key| Some(440.0 * (2.0f32).powf((key as i32 - 69) as f32 / 12.0)))
+            .collect();
+        Self { frequencies }
+    }
+}
+
+impl KeyFrequencies {
+    pub fn new(scale: &Scale, mapping: &KeyboardMapping) -> Result<Self, String> {
+        let reference_degree = mapping
+            .degree_of_key(mapping.reference_key, scale)
+            .ok_or_else(|| {
+                format!(
+                    "ERROR: The reference key ({}) is not mapped to a scale degree.",
+                    mapping.reference_key
+                )
+            })?;
+        let reference_ratio = scale.ratio_of_degree(reference_degree);
+        let frequencies = (0..NUM_MIDI_NOTES)
+            .map(|key| {
+                if key < mapping.first_key || key > mapping.last_key {
+                    return None;
+                }
+                let ratio = scale.ratio_of_degree(mapping.degree_of_key(key, scale)?);
+                Some(mapping.reference_frequency * ratio / reference_ratio)
+            })
+            .collect();
+        Ok(Self { frequencies })
+    }
+
+    pub fn get(&self, key: usize) -> Option<f32> {
+        self.frequencies.get(key).cloned().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TWELVE_TET: &str = "! 12tet.scl
+!
+12 tone equal temperament
+ 12
+!
+ 100.0
+ 200.0
+ 300.0
+ 400.0
+ 500.0
+ 600.0
+ 700.0
+ 800.0
+ 900.0
+ 1000.0
+ 1100.0
+ 2/1
+";
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < b * 1e-4, "{} is not close to {}", a, b);
+    }
+
+    #[test]
+    fn cents_and_ratios() {
+        let text = "Mixed\n4\n! Comments can go anywhere.\n3/2 the fifth\n700.0\n3\n1200.\n";
+        let scale = Scale::parse(text, "mixed.scl").unwrap();
+        assert_eq!(scale.description, "Mixed");
+        assert_eq!(scale.num_notes(), 4);
+        assert_close(scale.ratio_of_degree(1), 1.5);
+        assert_close(scale.ratio_of_degree(2), 1.4983071);
+        assert_close(scale.ratio_of_degree(3), 3.0);
+        // The last pitch is the period of the scale.
+        assert_close(scale.ratio_of_degree(4), 2.0);
+        assert_close(scale.ratio_of_degree(5), 3.0);
+        assert_close(scale.ratio_of_degree(-3), 0.75);
+        assert!(Scale::parse("Bad\n1\n0/2\n", "bad.scl").is_err());
+        assert!(Scale::parse("Bad\n1\n-5/2\n", "bad.scl").is_err());
+    }
+
+    #[test]
+    fn count_mismatch() {
+        let err = Scale::parse("Short\n3\n100.0\n2/1\n", "short.scl").unwrap_err();
+        assert!(err.contains("says it has 3 notes but only contains 2"));
+        assert!(Scale::parse("Empty\n0\n", "empty.scl").is_err());
+        assert!(Scale::parse("No count\n", "none.scl").is_err());
+    }
+
+    #[test]
+    fn matches_twelve_tet_without_mapping() {
+        let scale = Scale::parse(TWELVE_TET, "12tet.scl").unwrap();
+        let frequencies = KeyFrequencies::new(&scale, &KeyboardMapping::default()).unwrap();
+        let expected = KeyFrequencies::default();
+        for key in 0..NUM_MIDI_NOTES {
+            assert_close(frequencies.get(key).unwrap(), expected.get(key).unwrap());
+        }
+        assert_close(frequencies.get(69).unwrap(), 440.0);
+        assert_close(frequencies.get(60).unwrap(), 261.62558);
+    }
+
+    #[test]
+    fn empty_mapping() {
+        // A size of 0 maps each key to the next degree of the scale, here with middle C as the
+        // reference at 256Hz.
+        let text = "0\n0\n127\n60\n60\n256.0\n0\n";
+        let mapping = KeyboardMapping::parse(text, "empty.kbm").unwrap();
+        let scale = Scale::parse("Fifths\n2\n3/2\n2/1\n", "fifths.scl").unwrap();
+        let frequencies = KeyFrequencies::new(&scale, &mapping).unwrap();
+        assert_close(frequencies.get(60).unwrap(), 256.0);
+        assert_close(frequencies.get(61).unwrap(), 384.0);
+        assert_close(frequencies.get(62).unwrap(), 512.0);
+        assert_close(frequencies.get(59).unwrap(), 192.0);
+    }
+
+    #[test]
+    fn unmapped_keys() {
+        // Only the white keys of each octave play, mapped onto a seven note scale.
+        let text = "! white.kbm
+12
+0
+127
+60
+69
+440.0
+7
+0
+x
+1
+x
+2
+3
+x
+4
+x
+5
+x
+6
+";
+        let mapping = KeyboardMapping::parse(text, "white.kbm").unwrap();
+        let scale = Scale::parse(
+            "Major\n7\n9/8\n5/4\n4/3\n3/2\n5/3\n15/8\n2/1\n",
+            "major.scl",
+        )
+        .unwrap();
+        let frequencies = KeyFrequencies::new(&scale, &mapping).unwrap();
+        assert_close(frequencies.get(69).unwrap(), 440.0);
+        assert_close(frequencies.get(60).unwrap(), 264.0);
+        assert_close(frequencies.get(72).unwrap(), 528.0);
+        assert_close(frequencies.get(67).unwrap(), 396.0);
+        assert_eq!(frequencies.get(61), None);
+        assert_eq!(frequencies.get(70), None);
+        assert_eq!(frequencies.get(49), None);
+    }
+
+    #[test]
+    fn unmapped_reference_key() {
+        // The reference key is 61, which the mapping leaves silent.
+        let text = "2\n0\n127\n60\n61\n440.0\n2\n0\nx\n";
+        let mapping = KeyboardMapping::parse(text, "bad.kbm").unwrap();
+        let scale = Scale::parse(TWELVE_TET, "12tet.scl").unwrap();
+        let err = KeyFrequencies::new(&scale, &mapping).unwrap_err();
+        assert!(err.contains("reference key (61) is not mapped"));
+    }
+
+    #[test]
+    fn keys_outside_the_range_are_silent() {
+        let text = "0\n60\n72\n60\n69\n440.0\n0\n";
+        let mapping = KeyboardMapping::parse(text, "range.kbm").unwrap();
+        let scale = Scale::parse(TWELVE_TET, "12tet.scl").unwrap();
+        let frequencies = KeyFrequencies::new(&scale, &mapping).unwrap();
+        assert_eq!(frequencies.get(59), None);
+        assert_eq!(frequencies.get(73), None);
+        assert_close(frequencies.get(72).unwrap(), 523.2511);
+    }
+}
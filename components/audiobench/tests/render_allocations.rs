@@ -0,0 +1,113 @@
+//! Checks that rendering audio does not allocate once a patch is loaded, even while notes start
+//! and pedals are used. This is a separate test binary because it replaces the global allocator, which
+//! would otherwise count the allocations of every other test as well.
+
+use audiobench::{NoteEvent, OfflineRenderer, Pedal, TimedNoteEvent};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = Cell::new(0);
+}
+
+/// Counts the allocations made by each thread, so that the test harness is not counted.
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const PATCH: &[u8] = include_bytes!("../../factory_library/patches/Default.abpatch");
+const SAMPLE_RATE: usize = 44100;
+
+fn pedal(time: f32, pedal: Pedal, down: bool) -> TimedNoteEvent {
+    let event = NoteEvent::Pedal {
+        pedal,
+        down,
+        offset: 0,
+    };
+    TimedNoteEvent { time, event }
+}
+
+/// A second of chords played with both pedals, starting `start` seconds into the render. The
+/// first note is struck again while only the sustain pedal holds it.
+fn events(start: f32) -> Vec<TimedNoteEvent> {
+    let mut events = Vec::new();
+    for (chord, &time) in [0.0, 0.2, 0.4].iter().enumerate() {
+        for index in 0..3 {
+            let index = 60 + chord * 2 + index * 4;
+            let press = NoteEvent::StartNote {
+                index,
+                velocity: 1.0,
+                offset: 0,
+            };
+            let release = NoteEvent::ReleaseNote {
+                index,
+                velocity: 0.5,
+                offset: 0,
+            };
+            events.push(TimedNoteEvent {
+                time: start + time,
+                event: press,
+            });
+            events.push(TimedNoteEvent {
+                time: start + time + 0.1,
+                event: release,
+            });
+        }
+    }
+    let restrike = NoteEvent::StartNote {
+        index: 60,
+        velocity: 1.0,
+        offset: 0,
+    };
+    events.push(TimedNoteEvent {
+        time: start + 0.5,
+        event: restrike,
+    });
+    events.push(pedal(start + 0.05, Pedal::Sostenuto, true));
+    events.push(pedal(start + 0.15, Pedal::Sustain, true));
+    events.push(pedal(start + 0.6, Pedal::Sustain, false));
+    events.push(pedal(start + 0.7, Pedal::Sostenuto, false));
+    events.sort_by(|a, b| a.time.total_cmp(&b.time));
+    events
+}
+
+/// Loading the patch allocates, so this only counts the allocations made while rendering audio
+/// after the patch was loaded.
+#[test]
+fn rendering_buffers_does_not_allocate() {
+    let mut renderer = OfflineRenderer::new(2, 512, SAMPLE_RATE).unwrap();
+    renderer.load_patch(PATCH).unwrap();
+    let mut audio = vec![0.0; SAMPLE_RATE * renderer.get_channels()];
+    // The first second compiles everything the events use. Notes from the first second are still
+    // fading out in the second one, so the buffers only reach their final size there.
+    for &start in &[0.0, 1.0] {
+        let warm_up = events(start);
+        renderer.render_into(&warm_up[..], &mut audio[..]).unwrap();
+    }
+
+    let measured = events(2.0);
+    let before = ALLOCATIONS.with(|count| count.get());
+    renderer.render_into(&measured[..], &mut audio[..]).unwrap();
+    let after = ALLOCATIONS.with(|count| count.get());
+    assert!(audio.iter().any(|&sample| sample != 0.0));
+    assert_eq!(after - before, 0);
+}
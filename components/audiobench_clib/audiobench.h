@@ -20,12 +20,23 @@ struct ABGraphicsFunctions {
     void (*drawDropShadow)(void*, float, float, float, float, float);
 };
 
+// Lets the host record changes the GUI makes to host parameters. Each function receives data and
+// the index of the parameter.
+struct ABParameterCallbacks {
+    void *data;
+    void (*beginGesture)(void*, int);
+    void (*setValue)(void*, int, float);
+    void (*endGesture)(void*, int);
+};
+
 extern "C" {
     ABInstanceRef ABCreateInstance();
     void ABDestroyInstance(ABInstanceRef);
     int ABUiGetNumIcons(ABInstanceRef);
     void ABUiGetIconData(ABInstanceRef, int iconIndex, void **dataBufferPtr, int *sizePtr);
-    float *ABAudioSetGlobalParameters(ABInstanceRef, int, int);
+    float *ABAudioSetGlobalParameters(ABInstanceRef, int, int, int);
+    void ABAudioSetRenderLatency(ABInstanceRef, int);
+    int ABAudioGetLatencySamples(ABInstanceRef);
 
     void ABAudioSerializePatch(ABInstanceRef, char**, uint32_t*);
     void ABUiSerializePatch(ABInstanceRef, char**, uint32_t*);
@@ -34,14 +45,26 @@ extern "C" {
     void ABUiDeserializePatch(ABInstanceRef, char*, uint32_t);
     void ABUiHandleCrossThreadHelp(ABInstanceRef);
 
-    void ABAudioStartNote(ABInstanceRef, int, float);
-    void ABAudioReleaseNote(ABInstanceRef, int);
+    void ABAudioStartNote(ABInstanceRef, int, float, int);
+    void ABAudioReleaseNote(ABInstanceRef, int, float, int);
+    void ABAudioNotePitchBend(ABInstanceRef, int, float);
+    void ABAudioNotePressure(ABInstanceRef, int, float);
+    void ABAudioNoteTimbre(ABInstanceRef, int, float);
     void ABAudioPitchWheel(ABInstanceRef, float);
     void ABAudioBpm(ABInstanceRef, float);
-    void ABAudioSongTime(ABInstanceRef, float);
-    void ABAudioSongBeats(ABInstanceRef, float);
+    void ABAudioElapsedTime(ABInstanceRef, float);
+    void ABAudioElapsedBeats(ABInstanceRef, float);
+    void ABAudioTransportState(ABInstanceRef, bool playing, bool recording);
+    void ABAudioLoopPoints(ABInstanceRef, bool looping, float start, float end);
+    void ABAudioBarStart(ABInstanceRef, float);
+    void ABAudioTimeSignature(ABInstanceRef, int numerator, int denominator);
     void ABAudioControl(ABInstanceRef, int, float);
-    float *ABAudioRenderAudio(ABInstanceRef);
+    int ABAudioGetNumParameters(ABInstanceRef);
+    void ABAudioSetParameter(ABInstanceRef, int, float);
+    void ABUiGetParameterName(ABInstanceRef, int, char**, uint32_t*);
+    void ABUiGetParameterRange(ABInstanceRef, int, float*, float*);
+    void ABUiSetParameterCallbacks(ABInstanceRef, ABParameterCallbacks);
+    float *ABAudioRenderAudio(ABInstanceRef, const float*);
 
     void ABUiSetGraphicsFunctions(ABInstanceRef, ABGraphicsFunctions);
     void ABUiCreateUI(ABInstanceRef);
@@ -42,17 +42,37 @@ pub unsafe extern "C" fn ABUiGetIconData(
 #[no_mangle]
 pub unsafe extern "C" fn ABAudioSetGlobalParameters(
     cr: *mut CreateResult,
+    channels: i32,
     buffer_length: i32,
     sample_rate: i32,
 ) {
+    with_ok(cr, |instance| {
+        instance.audio_engine.borrow_mut().set_global_params(
+            channels.max(1) as usize,
+            buffer_length as usize,
+            sample_rate as usize,
+        )
+    });
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ABAudioSetRenderLatency(cr: *mut CreateResult, buffers: i32) {
     with_ok(cr, |instance| {
         instance
             .audio_engine
             .borrow_mut()
-            .set_global_params(buffer_length as usize, sample_rate as usize)
+            .set_render_latency(buffers.max(0) as usize)
     });
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn ABAudioGetLatencySamples(cr: *mut CreateResult) -> i32 {
+    with_ok(cr, |instance| {
+        instance.audio_engine.borrow().get_latency_samples()
+    })
+    .unwrap_or_default() as i32
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn ABAudioSerializePatch(
     cr: *mut CreateResult,
@@ -124,39 +144,149 @@ pub unsafe extern "C" fn ABUiHandleCrossThreadHelp(cr: *mut CreateResult) {
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn ABAudioStartNote(cr: *mut CreateResult, index: i32, velocity: f32) {
+pub unsafe extern "C" fn ABAudioStartNote(
+    cr: *mut CreateResult,
+    index: i32,
+    velocity: f32,
+    offset: i32,
+) {
+    with_ok(cr, |instance| {
+        instance.audio_engine.borrow_mut().start_note(
+            index as usize,
+            velocity,
+            offset.max(0) as usize,
+        )
+    });
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ABAudioReleaseNote(
+    cr: *mut CreateResult,
+    index: i32,
+    velocity: f32,
+    offset: i32,
+) {
+    with_ok(cr, |instance| {
+        instance.audio_engine.borrow_mut().release_note(
+            index as usize,
+            velocity,
+            offset.max(0) as usize,
+        )
+    });
+}
+
+/// `semitones` is how far the note should be bent from its original pitch.
+#[no_mangle]
+pub unsafe extern "C" fn ABAudioNotePitchBend(cr: *mut CreateResult, index: i32, semitones: f32) {
+    with_ok(cr, |instance| {
+        instance.audio_engine.borrow_mut().set_note_expression(
+            index as usize,
+            NoteExpression::PitchBend,
+            semitones,
+        )
+    });
+}
+
+/// `value` ranges from 0 to 1.
+#[no_mangle]
+pub unsafe extern "C" fn ABAudioNotePressure(cr: *mut CreateResult, index: i32, value: f32) {
+    with_ok(cr, |instance| {
+        instance.audio_engine.borrow_mut().set_note_expression(
+            index as usize,
+            NoteExpression::Pressure,
+            value,
+        )
+    });
+}
+
+/// `value` ranges from 0 to 1.
+#[no_mangle]
+pub unsafe extern "C" fn ABAudioNoteTimbre(cr: *mut CreateResult, index: i32, value: f32) {
+    with_ok(cr, |instance| {
+        instance.audio_engine.borrow_mut().set_note_expression(
+            index as usize,
+            NoteExpression::Timbre,
+            value,
+        )
+    });
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ABAudioPitchWheel(cr: *mut CreateResult, value: f32) {
+    with_ok(cr, |instance| {
+        instance.audio_engine.borrow_mut().set_pitch_wheel(value)
+    });
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ABAudioControl(cr: *mut CreateResult, index: i32, value: f32) {
     with_ok(cr, |instance| {
         instance
             .audio_engine
             .borrow_mut()
-            .start_note(index as usize, velocity)
+            .set_control(index as usize, value)
     });
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn ABAudioReleaseNote(cr: *mut CreateResult, index: i32) {
+pub unsafe extern "C" fn ABAudioGetNumParameters(_cr: *mut CreateResult) -> i32 {
+    NUM_HOST_PARAMETERS as i32
+}
+
+/// `value` should be between 0 and 1.
+#[no_mangle]
+pub unsafe extern "C" fn ABAudioSetParameter(cr: *mut CreateResult, index: i32, value: f32) {
     with_ok(cr, |instance| {
         instance
             .audio_engine
             .borrow_mut()
-            .release_note(index as usize)
+            .set_host_parameter(index as usize, value)
     });
 }
 
+/// The returned string must be freed with ABCleanupSerializedData.
 #[no_mangle]
-pub unsafe extern "C" fn ABAudioPitchWheel(cr: *mut CreateResult, value: f32) {
+pub unsafe extern "C" fn ABUiGetParameterName(
+    cr: *mut CreateResult,
+    index: i32,
+    data_out: *mut *mut u8,
+    size_out: *mut u32,
+) {
     with_ok(cr, |instance| {
-        instance.audio_engine.borrow_mut().set_pitch_wheel(value)
+        let data = instance
+            .host_parameter_info
+            .get_name(index as usize)
+            .into_bytes()
+            .into_boxed_slice();
+        *size_out = data.len() as u32;
+        *data_out = Box::leak(data).as_mut_ptr();
     });
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn ABAudioControl(cr: *mut CreateResult, index: i32, value: f32) {
+pub unsafe extern "C" fn ABUiGetParameterRange(
+    cr: *mut CreateResult,
+    index: i32,
+    min_out: *mut f32,
+    max_out: *mut f32,
+) {
+    with_ok(cr, |instance| {
+        let range = instance.host_parameter_info.get_range(index as usize);
+        *min_out = range.0;
+        *max_out = range.1;
+    });
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ABUiSetParameterCallbacks(
+    cr: *mut CreateResult,
+    callbacks: HostParameterCallbacks,
+) {
     with_ok(cr, |instance| {
         instance
-            .audio_engine
+            .ui_engine
             .borrow_mut()
-            .set_control(index as usize, value)
+            .set_host_parameter_callbacks(callbacks)
     });
 }
 
@@ -182,8 +312,73 @@ pub unsafe extern "C" fn ABAudioElapsedBeats(cr: *mut CreateResult, beats: f32)
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn ABAudioRenderAudio(cr: *mut CreateResult) -> *const f32 {
-    with_ok(cr, |instance| instance.audio_render_audio().as_ptr()).unwrap_or(std::ptr::null())
+pub unsafe extern "C" fn ABAudioTransportState(
+    cr: *mut CreateResult,
+    playing: bool,
+    recording: bool,
+) {
+    with_ok(cr, |instance| {
+        instance
+            .audio_engine
+            .borrow_mut()
+            .set_transport_state(playing, recording)
+    });
+}
+
+/// `start` and `end` are measured in beats and ignored if `looping` is false.
+#[no_mangle]
+pub unsafe extern "C" fn ABAudioLoopPoints(
+    cr: *mut CreateResult,
+    looping: bool,
+    start: f32,
+    end: f32,
+) {
+    with_ok(cr, |instance| {
+        let points = if looping { Some((start, end)) } else { None };
+        instance.audio_engine.borrow_mut().set_loop_points(points)
+    });
+}
+
+/// `beats` is the position of the start of the bar the song is currently in.
+#[no_mangle]
+pub unsafe extern "C" fn ABAudioBarStart(cr: *mut CreateResult, beats: f32) {
+    with_ok(cr, |instance| {
+        instance.audio_engine.borrow_mut().set_bar_start(beats)
+    });
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ABAudioTimeSignature(
+    cr: *mut CreateResult,
+    numerator: i32,
+    denominator: i32,
+) {
+    with_ok(cr, |instance| {
+        instance
+            .audio_engine
+            .borrow_mut()
+            .set_time_signature(numerator.max(0) as u32, denominator.max(0) as u32)
+    });
+}
+
+/// `host_input` is either null or interleaved audio with as many channels and samples as were
+/// specified in ABAudioSetGlobalParameters.
+#[no_mangle]
+pub unsafe extern "C" fn ABAudioRenderAudio(
+    cr: *mut CreateResult,
+    host_input: *const f32,
+) -> *const f32 {
+    with_ok(cr, |instance| {
+        let host_input = if host_input.is_null() {
+            &[]
+        } else {
+            let params = instance.audio_engine.borrow().get_global_params();
+            let len = params.channels * params.buffer_length;
+            std::slice::from_raw_parts(host_input, len)
+        };
+        instance.audio_render_audio(host_input).as_ptr()
+    })
+    .unwrap_or(std::ptr::null())
 }
 
 #[no_mangle]
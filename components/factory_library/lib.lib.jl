@@ -171,7 +171,7 @@ const num_midi_controls = 128
 const default_graph_resolution = 42
 
 const MonoSample = fixed_array_type(Float32, Tuple{1})
-const StereoSample = fixed_array_type(Float32, Tuple{2})
+const StereoSample = fixed_array_type(Float32, Tuple{channels})
 
 const StaticMonoAudio = fixed_array_type(Float32, Tuple{1, 1})
 const StaticStereoAudio = fixed_array_type(Float32, Tuple{channels, 1})
@@ -179,6 +179,9 @@ const MonoAudio = fixed_array_type(Float32, Tuple{1, buffer_length})
 const StereoAudio = fixed_array_type(Float32, Tuple{channels, buffer_length})
 const StaticControlSignal = StaticMonoAudio
 const ControlSignal = MonoAudio
+# With a single channel the stereo types are the mono types, so methods specific to them are only
+# defined when there is more than one channel. Otherwise they would overwrite the mono methods.
+const has_stereo_types = channels > 1
 
 const StaticTrigger = fixed_array_type(Bool, Tuple{1, 1})
 const Trigger = fixed_array_type(Bool, Tuple{1, buffer_length})
@@ -189,23 +192,96 @@ const ramp_up_waveform = (phase::Float32, _buffer_pos::Integer) -> phase * 2 - 1
 const ramp_down_waveform = (phase::Float32, _buffer_pos::Integer) -> 1 - phase * 2
 const sine_waveform = (phase::Float32, _buffer_pos::Integer) -> sin(phase * pi * 2f0)
 
+# What the host's transport is doing. Loop points are measured in beats.
+struct TransportInput
+    playing::Bool
+    recording::Bool
+    looping::Bool
+    loop_start::Float32
+    loop_end::Float32
+    # Counts bars from the start of the song, using the host's time signature.
+    elapsed_bars::Float32
+    time_signature_numerator::Int32
+    time_signature_denominator::Int32
+end
+
+# How many beats (quarter notes) there are in each bar.
+function beats_per_bar(transport::TransportInput)::Float32
+    transport.time_signature_numerator * 4f0 / transport.time_signature_denominator
+end
+
 struct GlobalInput
     midi_controls::Vector{Float32}
     pitch_wheel::Float32
     bpm::Float32
     elapsed_time::Float32
     elapsed_beats::Float32
+    # Audio the host sent to be processed, silent if the host did not send any.
+    host_input::StereoAudio
+    # Each channel of host_input on its own.
+    host_input_channels::Vector{MonoAudio}
+    # The value of each host automation parameter, from 0 to 1.
+    host_parameters::Vector{Float32}
+    transport::TransportInput
+end
+
+# A single channel of the host's input audio. The last channel is used if there are not enough.
+function host_input_channel(global_in::GlobalInput, channel::Integer)::MonoAudio
+    global_in.host_input_channels[min(channel, channels)]
 end
 
 struct NoteInput
     pitch::Float32
     velocity::Float32
+    # How fast the key was released, zero until the note is released.
+    release_velocity::Float32
     elapsed_time::Float32
     elapsed_beats::Float32
+    pitch_bend::Float32
+    pressure::Float32
+    timbre::Float32
+    # Where in their cycle oscillators start, from 0 to 1. Differs between unison voices.
+    phase::Float32
+    # The MIDI key the note is playing, from 0 to 127. Unlike the pitch, tunings do not change it.
+    key::Int32
+    start_offset::Int32
+    release_offset::Int32
     start_trigger::Bool
     release_trigger::Bool
 end
 
+# Modules in the effects section process every voice at once, so there is no real note for them to
+# read. They get a note which started when the song did and never ends.
+function effects_note_input(global_in::GlobalInput)::NoteInput
+    NoteInput(
+        440f0, 1f0, 0f0, global_in.elapsed_time, global_in.elapsed_beats,
+        0f0, 0f0, 0.5f0, 0f0, Int32(69), Int32(0), Int32(0), false, false
+    )
+end
+
+# How far a note is from middle C, scaled so that -1 and 1 are the lowest and highest MIDI notes.
+function key_tracking(note_in::NoteInput)::Float32
+    semitones = 12f0 * log2(note_in.pitch / 440f0) + 9f0
+    clamp(semitones / 64f0, -1f0, 1f0)
+end
+
+# The MIDI key of a note, scaled like a MIDI controller value so that key 0 is -1 and key 127 is 1.
+function note_number(note_in::NoteInput)::Float32
+    note_in.key / 63.5f0 - 1f0
+end
+
+# Maps a MIDI controller value from -1 to 1 onto 0 to 1 using the curve of a MIDI binding. 0 is
+# linear, 1 is exponential and 2 is logarithmic.
+function midi_binding_curve(value::Float32, curve::Float32)::StaticControlSignal
+    t = (value + 1f0) * 0.5f0
+    if curve == 1f0
+        t = t * t
+    elseif curve == 2f0
+        t = sqrt(t)
+    end
+    StaticControlSignal(t)
+end
+
 mutable struct NoteOutput
     audio::StereoAudio
 end
@@ -218,6 +294,8 @@ struct NoteContext
     global_in::GlobalInput
     note_in::NoteInput
     note_out::NoteOutput
+    # The mix of every voice, only meaningful in the effects section.
+    voices_in::StereoAudio
 end
 
 function timing_mode_source_is_global(mode::Integer)::Bool
@@ -229,29 +307,51 @@ function timing_mode_source_is_note(mode::Integer)::Bool
 end
 
 function timing_mode_unit_is_beats(mode::Integer)::Bool
-    mode & 0b10 == 0b10
+    mode & 0b110 == 0b010
 end
 
 function timing_mode_unit_is_seconds(mode::Integer)::Bool
-    mode & 0b10 == 0b00
+    mode & 0b110 == 0b000
+end
+
+function timing_mode_unit_is_bars(mode::Integer)::Bool
+    mode & 0b110 == 0b100
+end
+
+# How many seconds long one unit of the timing mode currently is.
+function seconds_per_timing_unit(context::NoteContext, mode::Integer)::Float32
+    if timing_mode_unit_is_beats(mode)
+        60f0 / context.global_in.bpm
+    elseif timing_mode_unit_is_bars(mode)
+        60f0 / context.global_in.bpm * beats_per_bar(context.global_in.transport)
+    else
+        1f0
+    end
 end
 
 # Timing modes:
 # Bit 1 controls note (false) vs song (true)
-# Bit 2 controls seconds (false) vs beats (true)
+# Bits 2 and 3 control seconds (0), beats (1) or bars (2)
 function get_timing(context::NoteContext, mode::Integer)::ControlSignal
     result = similar(ControlSignal)
     global_source::Bool = timing_mode_source_is_global(mode)
-    beat_units::Bool = timing_mode_unit_is_beats(mode)
-    value::Float32 = if global_source 
-        if beat_units context.global_in.elapsed_beats else context.global_in.elapsed_time end
+    transport = context.global_in.transport
+    value::Float32 = if timing_mode_unit_is_bars(mode)
+        if global_source
+            transport.elapsed_bars
+        else
+            context.note_in.elapsed_beats / beats_per_bar(transport)
+        end
+    elseif timing_mode_unit_is_beats(mode)
+        if global_source context.global_in.elapsed_beats else context.note_in.elapsed_beats end
     else 
-        if beat_units context.note_in.elapsed_beats else context.note_in.elapsed_time end
+        if global_source context.global_in.elapsed_time else context.note_in.elapsed_time end
     end
-    per_sample::Float32 = if beat_units
-        context.global_in.bpm / 60f0 / sample_rate
+    per_sample::Float32 = if global_source && !transport.playing
+        # The song position does not move while the host is stopped.
+        0f0
     else
-        1f0 / sample_rate
+        1f0 / sample_rate / seconds_per_timing_unit(context, mode)
     end
     for i in 1:buffer_length
         result[1, i] = value
@@ -270,26 +370,36 @@ end
 
 # Audio type to sample type
 at2st(_audio_type::Type{StaticMonoAudio})::Type{MonoSample} = MonoSample
-at2st(_audio_type::Type{StaticStereoAudio})::Type{StereoSample} = StereoSample
 at2st(_audio_type::Type{MonoAudio})::Type{MonoSample} = MonoSample
-at2st(_audio_type::Type{StereoAudio})::Type{StereoSample} = StereoSample
+if has_stereo_types
+    at2st(_audio_type::Type{StaticStereoAudio})::Type{StereoSample} = StereoSample
+    at2st(_audio_type::Type{StereoAudio})::Type{StereoSample} = StereoSample
+end
 
 # Sample type to audio type
 st2at(_sample_type::Type{MonoSample})::Type{MonoAudio} = MonoAudio
-st2at(_sample_type::Type{StereoSample})::Type{StereoAudio} = StereoAudio
+if has_stereo_types
+    st2at(_sample_type::Type{StereoSample})::Type{StereoAudio} = StereoAudio
+end
 
 # Sample type to static audio type
 st2sat(_sample_type::Type{MonoSample})::Type{StaticMonoAudio} = StaticMonoAudio
-st2sat(_sample_type::Type{StereoSample})::Type{StaticStereoAudio} = StaticStereoAudio
+if has_stereo_types
+    st2sat(_sample_type::Type{StereoSample})::Type{StaticStereoAudio} = StaticStereoAudio
+end
 
 # Audio to control signal
-a2cs(audio::StereoAudio)::ControlSignal = ControlSignal(sum(audio; dims=1) ./ 2)
 a2cs(audio::MonoAudio)::ControlSignal = ControlSignal(audio)
-a2cs(audio::StaticStereoAudio)::StaticControlSignal = StaticControlSignal((audio[1] + audio[2]) / 2)
 a2cs(audio::StaticMonoAudio)::StaticControlSignal = StaticControlSignal(audio)
+if has_stereo_types
+    a2cs(audio::StereoAudio)::ControlSignal = ControlSignal(sum(audio; dims=1) ./ channels)
+    a2cs(audio::StaticStereoAudio)::StaticControlSignal = StaticControlSignal(sum(audio.data) / channels)
+end
 
 function assert_is_sample_type(_type::Type{MonoSample}) end
-function assert_is_sample_type(_type::Type{StereoSample}) end
+if has_stereo_types
+    function assert_is_sample_type(_type::Type{StereoSample}) end
+end
 function assert_is_sample_type(type) 
     throw(AssertionError("$type is not a valid sample type."))
 end
@@ -301,9 +411,11 @@ function assert_is_control_signal_type(type)
 end
 
 function assert_is_audio_type(_type::Type{StaticMonoAudio}) end
-function assert_is_audio_type(_type::Type{StaticStereoAudio}) end
 function assert_is_audio_type(_type::Type{MonoAudio}) end
-function assert_is_audio_type(_type::Type{StereoAudio}) end
+if has_stereo_types
+    function assert_is_audio_type(_type::Type{StaticStereoAudio}) end
+    function assert_is_audio_type(_type::Type{StereoAudio}) end
+end
 function assert_is_audio_type(type) 
     throw(AssertionError("$type is not a valid audio type."))
 end
@@ -326,15 +438,19 @@ end
 
 # Allows accessing static data as a smaller data type. Cannot view small data as a bigger type.
 viewas(data::Union{(MonoSample), (StereoSample)}, type::(MonoSample)) = @view data[1:1]
-viewas(data::(StereoSample), type::(StereoSample)) = @view data[:]
+if has_stereo_types
+    viewas(data::(StereoSample), type::(StereoSample)) = @view data[:]
+end
 
 viewas(data::Union{(StaticTrigger), (Trigger)}, type::(StaticTrigger)) = @view data[1:1]
 viewas(data::(Trigger), type::(Trigger)) = @view data[:]
 
 viewas(data::Union{(StaticMonoAudio), (StaticStereoAudio), (MonoAudio), (StereoAudio)}, type::Type{StaticMonoAudio}) = @view data[1:1, 1:1]
-viewas(data::Union{(StaticStereoAudio), (StereoAudio)}, type::Type{StaticStereoAudio}) = @view data[:, 1:1]
 viewas(data::Union{(MonoAudio), (StereoAudio)}, type::Type{MonoAudio}) = @view data[1:1, :]
-viewas(data::(StereoAudio), type::Type{StereoAudio}) = @view data[:, :]
+if has_stereo_types
+    viewas(data::Union{(StaticStereoAudio), (StereoAudio)}, type::Type{StaticStereoAudio}) = @view data[:, 1:1]
+    viewas(data::(StereoAudio), type::Type{StereoAudio}) = @view data[:, :]
+end
 
 # Allows manually iterating over audio.
 sample_indices(_buf::FixedArray{Float32, Tuple{C, S}, 2, N}) where {C, S, N} = Base.OneTo(S)
@@ -387,6 +503,60 @@ function make_trigger_view_data(trigger)::Vector{Float32}
     result
 end
 
+# Panning
+# The direction of the speaker each channel is played from, in degrees clockwise from straight
+# ahead. The order matches the order the host expects channels in. LFE channels are `nothing`
+# because panned audio is never sent to them.
+const speaker_angles = if channels == 1
+    [0f0] # Mono
+elseif channels == 2
+    [-30f0, 30f0] # L R
+elseif channels == 3
+    [-30f0, 30f0, 0f0] # L R C
+elseif channels == 4
+    [-45f0, 45f0, -135f0, 135f0] # L R Ls Rs
+elseif channels == 5
+    [-30f0, 30f0, 0f0, -110f0, 110f0] # L R C Ls Rs
+elseif channels == 6
+    [-30f0, 30f0, 0f0, nothing, -110f0, 110f0] # L R C LFE Ls Rs
+elseif channels == 7
+    [-30f0, 30f0, 0f0, -90f0, 90f0, -150f0, 150f0] # L R C Ls Rs Lrs Rrs
+else
+    [-30f0, 30f0, 0f0, nothing, -90f0, 90f0, -150f0, 150f0] # L R C LFE Ls Rs Lrs Rrs
+end
+# (angle, channel) pairs for every speaker audio can be panned to, sorted by angle.
+const panning_speakers = sort([
+    (angle, channel) for (channel, angle) in enumerate(speaker_angles) if angle !== nothing
+])
+# The angle of the outermost speakers in front of the listener, which is as far as a pan of -1 or 1
+# goes.
+const front_speaker_angle = maximum(abs(angle) for (angle, _) in panning_speakers if abs(angle) <= 90)
+
+# Writes how loud a sound coming from the specified angle should be in each channel. The sound is
+# split between the two speakers on either side of it while keeping its total power the same.
+function pan_gains!(gains::AbstractVector{Float32}, angle::Float32)
+    gains .= 0f0
+    num_speakers = length(panning_speakers)
+    if num_speakers == 1
+        gains[panning_speakers[1][2]] = 1f0
+        return gains
+    end
+    for index in 1:num_speakers
+        (start_angle, start_channel) = panning_speakers[index]
+        (end_angle, end_channel) = panning_speakers[mod1(index + 1, num_speakers)]
+        # The last pair wraps around behind the listener.
+        span = mod(end_angle - start_angle, 360f0)
+        offset = mod(angle - start_angle, 360f0)
+        if offset <= span
+            amount = offset / span * Float32(pi) / 2
+            gains[start_channel] = cos(amount)
+            gains[end_channel] = sin(amount)
+            return gains
+        end
+    end
+    gains
+end
+
 # Other stuff
 lerp(from, to, amount) = to * amount + from * (1 - amount)
 
@@ -58,10 +58,7 @@ function exec()
         elseif now_time > attack_time[1, 1] + decay_time[1, 1]
             now_time = attack_time[1, 1] + decay_time[1, 1]
         end
-        multiplier = 1f0
-        if timing_mode_unit_is_beats(timing_mode)
-            multiplier = 60.0 / context.global_in.bpm
-        end
+        multiplier = seconds_per_timing_unit(context, timing_mode)
         push!(graph_feedback, first(attack_time) * multiplier)
         push!(graph_feedback, first(decay_time) * multiplier)
         push!(graph_feedback, first(sustain))
@@ -0,0 +1,26 @@
+const max_delay_time = 2f0
+
+mutable struct StaticData
+    buffer::Matrix{Float32}
+    write_pos::Int
+end
+
+function static_init()
+    StaticData(zeros(Float32, channels, Int(ceil(max_delay_time * sample_rate))), 1)
+end
+
+function exec()
+    output = similar(StereoAudio)
+    len = size(static.buffer, 2)
+    for s in sample_indices(StereoAudio)
+        delay_samples = clamp(Int(round(time[1, s] * sample_rate)), 1, len - 1)
+        read_pos = mod1(static.write_pos - delay_samples, len)
+        for c in channel_indices(StereoAudio)
+            dry = input[c, s]
+            wet = static.buffer[c, read_pos]
+            static.buffer[c, static.write_pos] = dry + wet * feedback[1, s]
+            output[c, s] = lerp(dry, wet, mix[1, s])
+        end
+        static.write_pos = mod1(static.write_pos + 1, len)
+    end
+end
@@ -0,0 +1,3 @@
+function exec()
+    audio = context.voices_in .* gain
+end
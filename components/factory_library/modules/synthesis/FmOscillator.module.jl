@@ -22,7 +22,7 @@ function exec()
         @. pitch_here = pitch[1, s] * (fm_signal[:, s] * fm_strength[1, s] + 1f0)
         @. phase_delta = pitch_here / sample_rate / Float32(oversampling) + 1f0
         for subsample in 1:oversampling
-            @. sample += waveform(phase, (s,))
+            @. sample += waveform((phase + context.note_in.phase) % 1f0, (s,))
             @. phase = (phase + phase_delta) % 1f0
         end
         @. audio[:, s] = sample * amplitude[1, s] / Float32(oversampling)
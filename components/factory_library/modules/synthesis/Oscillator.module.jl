@@ -14,7 +14,7 @@ function exec()
         sample = 0f0
         phase_delta = pitch[1, s] / sample_rate / Float32(oversampling)
         for subsample in 1:oversampling
-            sample += waveform(static.phase, s)
+            sample += waveform((static.phase + context.note_in.phase) % 1f0, s)
             static.phase = (static.phase + phase_delta) % 1f0
         end
         audio[1, s] = sample * amplitude[1, s] / Float32(oversampling)
@@ -0,0 +1,19 @@
+mutable struct StaticData
+    gains::Vector{Float32}
+end
+
+function static_init()
+    StaticData(zeros(Float32, channels))
+end
+
+function exec()
+    output = similar(StereoAudio)
+    mono_input = a2cs(input)
+    for s in sample_indices(StereoAudio)
+        angle = pan[1, s] * front_speaker_angle + rotate[1, s]
+        pan_gains!(static.gains, angle)
+        for c in channel_indices(StereoAudio)
+            output[c, s] = mono_input[1, s] * static.gains[c]
+        end
+    end
+end
@@ -15,6 +15,59 @@
 #include "PluginEditor.h"
 #include "audiobench.h"
 
+//==============================================================================
+AudiobenchParameter::AudiobenchParameter(ABInstanceRef ab, int index)
+    : ab(ab), index(index), value(0.0f) {}
+
+float AudiobenchParameter::getValue() const { return value; }
+
+void AudiobenchParameter::setValue(float newValue) { value = newValue; }
+
+float AudiobenchParameter::getDefaultValue() const { return 0.0f; }
+
+String AudiobenchParameter::getName(int maximumStringLength) const {
+    char* dataPtr;
+    uint32_t dataLen;
+    ABUiGetParameterName(ab, index, &dataPtr, &dataLen);
+    String name = String::fromUTF8(dataPtr, dataLen);
+    ABCleanupSerializedData(dataPtr, dataLen);
+    return name.substring(0, maximumStringLength);
+}
+
+String AudiobenchParameter::getLabel() const { return {}; }
+
+String AudiobenchParameter::getText(float value,
+                                    int maximumStringLength) const {
+    float min, max;
+    ABUiGetParameterRange(ab, index, &min, &max);
+    return String(min + value * (max - min), 3)
+        .substring(0, maximumStringLength);
+}
+
+float AudiobenchParameter::getValueForText(const String& text) const {
+    float min, max;
+    ABUiGetParameterRange(ab, index, &min, &max);
+    if (max == min) return 0.0f;
+    return jlimit(0.0f, 1.0f, (text.getFloatValue() - min) / (max - min));
+}
+
+// These are called by Audiobench when the user changes a control that is
+// assigned to a host parameter, so the host can record it as automation.
+static void parameterBeginGesture(void* data, int index) {
+    auto processor = (AudiobenchAudioProcessor*)data;
+    processor->getParameters()[index]->beginChangeGesture();
+}
+
+static void parameterSetValue(void* data, int index, float value) {
+    auto processor = (AudiobenchAudioProcessor*)data;
+    processor->getParameters()[index]->setValueNotifyingHost(value);
+}
+
+static void parameterEndGesture(void* data, int index) {
+    auto processor = (AudiobenchAudioProcessor*)data;
+    processor->getParameters()[index]->endChangeGesture();
+}
+
 //==============================================================================
 AudiobenchAudioProcessor::AudiobenchAudioProcessor()
 #ifndef JucePlugin_PreferredChannelConfigurations
@@ -22,6 +75,9 @@ AudiobenchAudioProcessor::AudiobenchAudioProcessor()
 #if !JucePlugin_IsMidiEffect
 #if !JucePlugin_IsSynth
                          .withInput("Input", AudioChannelSet::stereo(), true)
+#else
+                         // Lets hosts send sidechain audio to the synth.
+                         .withInput("Input", AudioChannelSet::stereo(), false)
 #endif
                          .withOutput("Output", AudioChannelSet::stereo(), true)
 #endif
@@ -29,6 +85,25 @@ AudiobenchAudioProcessor::AudiobenchAudioProcessor()
 #endif
 {
     ab = ABCreateInstance();
+    for (int channel = 0; channel < 16; channel++) {
+        channelNotes[channel] = -1;
+        channelPitchBends[channel] = 0.0f;
+        channelPressures[channel] = 0.0f;
+        channelTimbres[channel] = 0.5f;
+    }
+    int numParameters = ABAudioGetNumParameters(ab);
+    for (int index = 0; index < numParameters; index++) {
+        auto parameter = new AudiobenchParameter(ab, index);
+        hostParameters.push_back(parameter);
+        lastHostParameterValues.push_back(parameter->getValue());
+        addParameter(parameter);
+    }
+    ABParameterCallbacks callbacks;
+    callbacks.data = (void*)this;
+    callbacks.beginGesture = parameterBeginGesture;
+    callbacks.setValue = parameterSetValue;
+    callbacks.endGesture = parameterEndGesture;
+    ABUiSetParameterCallbacks(ab, callbacks);
 }
 
 AudiobenchAudioProcessor::~AudiobenchAudioProcessor() { ABDestroyInstance(ab); }
@@ -84,7 +159,11 @@ void AudiobenchAudioProcessor::prepareToPlay(double sampleRate,
                                              int samplesPerBlock) {
     // Use this method as the place to do any pre-playback
     // initialisation that you need..
-    ABAudioSetGlobalParameters(ab, samplesPerBlock, (int) sampleRate);
+    ABAudioSetGlobalParameters(ab, getTotalNumOutputChannels(), samplesPerBlock,
+                               (int) sampleRate);
+    inputAudio.assign(getTotalNumOutputChannels() * samplesPerBlock, 0.0f);
+    // Audio is rendered ahead of time, let the host compensate for it.
+    setLatencySamples(ABAudioGetLatencySamples(ab));
 }
 
 void AudiobenchAudioProcessor::releaseResources() {
@@ -99,10 +178,17 @@ bool AudiobenchAudioProcessor::isBusesLayoutSupported(
     ignoreUnused(layouts);
     return true;
 #else
-    // This is the place where you check if the layout is supported.
-    // In this template code we only support mono or stereo.
-    if (layouts.getMainOutputChannelSet() != AudioChannelSet::mono() &&
-        layouts.getMainOutputChannelSet() != AudioChannelSet::stereo())
+    // These are the layouts Audiobench knows how to pan audio across, the
+    // channels have to be in the same order Audiobench renders them in.
+    auto output = layouts.getMainOutputChannelSet();
+    if (output != AudioChannelSet::mono() &&
+        output != AudioChannelSet::stereo() &&
+        output != AudioChannelSet::createLCR() &&
+        output != AudioChannelSet::quadraphonic() &&
+        output != AudioChannelSet::create5point0() &&
+        output != AudioChannelSet::create5point1() &&
+        output != AudioChannelSet::create7point0() &&
+        output != AudioChannelSet::create7point1())
         return false;
 
         // This checks if the input layout matches the output layout
@@ -122,18 +208,82 @@ void AudiobenchAudioProcessor::processBlock(AudioBuffer<float>& buffer,
     auto totalNumInputChannels = getTotalNumInputChannels();
     auto totalNumOutputChannels = getTotalNumOutputChannels();
 
+    AudioPlayHead::CurrentPositionInfo position;
+    auto playHead = getPlayHead();
+    if (playHead != nullptr && playHead->getCurrentPosition(position)) {
+        ABAudioBpm(ab, (float)position.bpm);
+        ABAudioElapsedTime(ab, (float)position.timeInSeconds);
+        ABAudioElapsedBeats(ab, (float)position.ppqPosition);
+        ABAudioBarStart(ab, (float)position.ppqPositionOfLastBarStart);
+        if (position.timeSigNumerator > 0 && position.timeSigDenominator > 0) {
+            ABAudioTimeSignature(ab, position.timeSigNumerator,
+                                 position.timeSigDenominator);
+        }
+        ABAudioTransportState(ab, position.isPlaying, position.isRecording);
+        ABAudioLoopPoints(ab, position.isLooping, (float)position.ppqLoopStart,
+                          (float)position.ppqLoopEnd);
+    }
+
     // Doing two seperate loops prevents the problem where a note is turned on
     // and off in the same buffer, but the on is processed after the off so the
     // note just stays on forever.
     for (auto meta : midiMessages) {
         auto message = meta.getMessage();
+        // Picks up MPE Configuration Messages, which is how MPE controllers
+        // announce which channels carry expression for individual notes.
+        mpeZoneLayout.processNextMidiEvent(message);
+        int channel = message.getChannel() - 1;
+        // Until an MPE zone is set up, every channel is an ordinary MIDI
+        // channel whose pitch wheel bends every note.
+        bool isMemberChannel = false;
+        float pitchBendRange = 0.0f;
+        for (auto zone : {mpeZoneLayout.getLowerZone(),
+                          mpeZoneLayout.getUpperZone()}) {
+            if (zone.isUsingChannelAsMemberChannel(channel + 1)) {
+                isMemberChannel = true;
+                pitchBendRange = (float)zone.perNotePitchbendRange;
+            }
+        }
+        int channelNote = isMemberChannel ? channelNotes[channel] : -1;
         if (message.isNoteOn()) {
-            ABAudioStartNote(ab, message.getNoteNumber(),
-                        message.getFloatVelocity());
+            int note = message.getNoteNumber();
+            ABAudioStartNote(ab, note, message.getFloatVelocity(),
+                             meta.samplePosition);
+            if (isMemberChannel) {
+                // MPE controllers send a note's initial expression before
+                // the note itself.
+                channelNotes[channel] = note;
+                ABAudioNotePitchBend(
+                    ab, note, channelPitchBends[channel] * pitchBendRange);
+                ABAudioNotePressure(ab, note, channelPressures[channel]);
+                ABAudioNoteTimbre(ab, note, channelTimbres[channel]);
+            }
         } else if (message.isPitchWheel()) {
             float value = (message.getPitchWheelValue() - 0x2000 + 0.5f) /
                           (0x2000 - 0.5f);
-            ABAudioPitchWheel(ab, value);
+            if (isMemberChannel) {
+                // Kept unscaled so that the range in effect when the note
+                // starts is used.
+                channelPitchBends[channel] = value;
+                if (channelNote >= 0) {
+                    ABAudioNotePitchBend(ab, channelNote,
+                                         value * pitchBendRange);
+                }
+            } else {
+                ABAudioPitchWheel(ab, value);
+            }
+        } else if (isMemberChannel && message.isChannelPressure()) {
+            float value = message.getChannelPressureValue() / 127.0f;
+            channelPressures[channel] = value;
+            if (channelNote >= 0) {
+                ABAudioNotePressure(ab, channelNote, value);
+            }
+        } else if (isMemberChannel && message.isControllerOfType(74)) {
+            float value = message.getControllerValue() / 127.0f;
+            channelTimbres[channel] = value;
+            if (channelNote >= 0) {
+                ABAudioNoteTimbre(ab, channelNote, value);
+            }
         } else if (message.isController()) {
             float value =
                 (message.getControllerValue() - 0x40 + 0.5f) / (0x40 - 0.5f);
@@ -143,7 +293,16 @@ void AudiobenchAudioProcessor::processBlock(AudioBuffer<float>& buffer,
     for (auto meta : midiMessages) {
         auto message = meta.getMessage();
         if (message.isNoteOff()) {
-            ABAudioReleaseNote(ab, message.getNoteNumber());
+            int channel = message.getChannel() - 1;
+            if (channelNotes[channel] == message.getNoteNumber()) {
+                channelNotes[channel] = -1;
+            }
+            // Note ons with a velocity of zero count as note offs, but they
+            // don't say how fast the key was released.
+            float velocity = message.isNoteOn(true) ? 0.5f
+                                                    : message.getFloatVelocity();
+            ABAudioReleaseNote(ab, message.getNoteNumber(), velocity,
+                               meta.samplePosition);
         }
     }
     // MIDI seems to do weird things, this may be helpful in the future.
@@ -172,6 +331,35 @@ void AudiobenchAudioProcessor::processBlock(AudioBuffer<float>& buffer,
     //     }
     // }
 
+    // Only values the host changed are pushed, so Audiobench can tell them
+    // apart from changes the user made in the GUI.
+    for (size_t index = 0; index < hostParameters.size(); index++) {
+        float value = hostParameters[index]->getValue();
+        if (value != lastHostParameterValues[index]) {
+            lastHostParameterValues[index] = value;
+            ABAudioSetParameter(ab, (int)index, value);
+        }
+    }
+
+    // Audiobench expects input with the same number of channels as its output,
+    // so the last input channel is repeated if there are not enough.
+    int numSamples = buffer.getNumSamples();
+    const float* hostInput = nullptr;
+    if ((size_t) (numSamples * totalNumOutputChannels) <= inputAudio.size()) {
+        for (int sample = 0; sample < numSamples; sample++) {
+            for (int channel = 0; channel < totalNumOutputChannels; channel++) {
+                float value = 0.0f;
+                if (totalNumInputChannels > 0) {
+                    int inputChannel =
+                        jmin(channel, totalNumInputChannels - 1);
+                    value = buffer.getSample(inputChannel, sample);
+                }
+                inputAudio[sample * totalNumOutputChannels + channel] = value;
+            }
+        }
+        hostInput = inputAudio.data();
+    }
+
     // In case we have more outputs than inputs, this code clears any output
     // channels that didn't contain input data, (because these aren't
     // guaranteed to be empty - they may contain garbage).
@@ -181,7 +369,7 @@ void AudiobenchAudioProcessor::processBlock(AudioBuffer<float>& buffer,
     for (auto i = totalNumInputChannels; i < totalNumOutputChannels; ++i)
         buffer.clear(i, 0, buffer.getNumSamples());
 
-    float* audioBuffer = ABAudioRenderAudio(ab);
+    float* audioBuffer = ABAudioRenderAudio(ab, hostInput);
 
     // This is the place where you'd normally do the guts of your plugin's
     // audio processing...
@@ -193,7 +381,8 @@ void AudiobenchAudioProcessor::processBlock(AudioBuffer<float>& buffer,
         for (int channel = 0; channel < totalNumOutputChannels; ++channel) {
             auto* channelData = buffer.getWritePointer(channel);
             for (int sample = 0; sample < buffer.getNumSamples(); sample++) {
-                channelData[sample] = audioBuffer[sample * 2 + channel];
+                channelData[sample] =
+                    audioBuffer[sample * totalNumOutputChannels + channel];
             }
         }
     }
@@ -225,6 +414,8 @@ void AudiobenchAudioProcessor::setStateInformation(const void* data,
     // When I try to use the Audio version (where it sends a message to the UI thread), it hangs
     // the UI thread which in turn hangs the audio thread.
     ABUiDeserializePatch(ab, (char*)data, sizeInBytes);
+    // The new patch may assign different controls to the host parameters.
+    updateHostDisplay();
 }
 
 //==============================================================================
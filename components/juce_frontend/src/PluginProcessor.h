@@ -13,6 +13,27 @@
 #include <JuceHeader.h>
 #include "audiobench.h"
 
+// One of the fixed bank of parameters exposed to the host. Controls in the
+// patch are assigned to them from the GUI, so the name depends on the patch.
+class AudiobenchParameter : public AudioProcessorParameter
+{
+public:
+    AudiobenchParameter(ABInstanceRef ab, int index);
+
+    float getValue() const override;
+    void setValue(float newValue) override;
+    float getDefaultValue() const override;
+    String getName(int maximumStringLength) const override;
+    String getLabel() const override;
+    String getText(float value, int maximumStringLength) const override;
+    float getValueForText(const String& text) const override;
+
+private:
+    ABInstanceRef ab;
+    int index;
+    std::atomic<float> value;
+};
+
 //==============================================================================
 /**
 */
@@ -59,6 +80,23 @@ public:
     ABInstanceRef ab;
 
 private:
+    // MPE controllers send each note on its own MIDI channel, with a master
+    // channel reserved for messages that affect every note. The zone layout
+    // says which channels those are, and the arrays track the current state of
+    // each channel so expression can be routed to the right note. Pitch bends
+    // are stored from -1 to 1 and scaled by the range of their zone.
+    MPEZoneLayout mpeZoneLayout;
+    int channelNotes[16];
+    float channelPitchBends[16];
+    float channelPressures[16];
+    float channelTimbres[16];
+    // Host input audio, interleaved the way Audiobench expects it.
+    std::vector<float> inputAudio;
+    std::vector<AudiobenchParameter*> hostParameters;
+    // The value of each host parameter the last time it was pushed to
+    // Audiobench.
+    std::vector<float> lastHostParameterValues;
+
     //==============================================================================
     JUCE_DECLARE_NON_COPYABLE_WITH_LEAK_DETECTOR (AudiobenchAudioProcessor)
 };
@@ -1,5 +1,6 @@
 pub use jlrs::prelude::*;
 
+use jlrs::layout::valid_layout::ValidLayout;
 use scones::make_constructor;
 use shared_util::{Clip, Position};
 
@@ -141,14 +142,25 @@ macro_rules! include_packed_library {
     }};
 }
 
+/// A function call started by `ExecutionEngine::spawn_fn` which may still be running on another
+/// Julia thread.
+#[derive(Debug, PartialEq, Eq)]
+pub struct BackgroundJob(i64);
+
 /// Creating more than one of these at a time will raise a panic.
 pub struct ExecutionEngine {
     julia: Julia,
     global_code_segments: Vec<GeneratedCode>,
+    /// Reused for the inputs of every function call so that calls do not allocate. It is always
+    /// empty between calls, so none of the values in it outlive the frame they were created in.
+    input_buffer: Vec<Value<'static, 'static>>,
 }
 
 // If you get random segfaults this might need to be bigger.
 const STACK_SIZE: usize = 8192;
+/// How many threads Julia should start with if the user has not configured it. Anything more than
+/// one lets background jobs run while the main thread keeps working.
+const DEFAULT_JULIA_THREADS: i32 = 2;
 /// Code to run a function and return any produced exceptions as a string including a backtrace
 /// instead of just the raw exception argument.
 const EE_ENV: &'static str = r#"
@@ -173,6 +185,51 @@ function __load_code_helper__(code, filename)
     end
 end
 
+function __load_code_helper__(code, filename, module_name)
+    try
+        # Replaces any previous module with the same name.
+        target = Core.eval(Main, Expr(:module, true, Symbol(module_name), Expr(:block)))
+        Main.include_string(target, code, filename)
+    catch error
+        bt = catch_backtrace()
+        throw(sprint(showerror, error, bt))
+    end
+end
+
+const __background_jobs__ = Dict{Int, Task}()
+__next_job_id__ = 0
+
+function __spawn_helper__(fn_to_run, arguments...)
+    global __next_job_id__ += 1
+    if Threads.nthreads() > 1
+        task = Threads.@spawn __error_format_helper__(fn_to_run, arguments...)
+    else
+        # There is no other thread to run the job on, so just run it now.
+        task = @task __error_format_helper__(fn_to_run, arguments...)
+        schedule(task)
+        try
+            wait(task)
+        catch
+        end
+    end
+    __background_jobs__[__next_job_id__] = task
+    __next_job_id__
+end
+
+function __poll_job_helper__(id)
+    task = __background_jobs__[id]
+    if !istaskdone(task)
+        return false
+    end
+    delete!(__background_jobs__, id)
+    if istaskfailed(task)
+        # Already formatted by __error_format_helper__, return it as-is so it does not get
+        # formatted a second time.
+        return task.exception
+    end
+    true
+end
+
 module UnpackedDependencies end
 "#;
 
@@ -180,9 +237,19 @@ impl ExecutionEngine {
     pub fn new() -> Self {
         const ERR: &'static str =
             "Tried to create an ExecutionEngine while Julia was already running!";
+        // The environment belongs to the whole host process, so the thread count is passed to
+        // Julia through its startup options instead. Julia only reads JULIA_NUM_THREADS when this
+        // is left at zero.
+        if std::env::var_os("JULIA_NUM_THREADS").is_none() {
+            // Safety: Julia has not been started yet, so nothing else is using its options.
+            unsafe {
+                jl_sys::jl_options.nthreads = DEFAULT_JULIA_THREADS;
+            }
+        }
         let mut this = Self {
             julia: unsafe { Julia::init(STACK_SIZE).expect(ERR) },
             global_code_segments: Vec::new(),
+            input_buffer: Vec::new(),
         };
         let env_code = GeneratedCode::from_unique_source("__execution_engine__", EE_ENV);
         // We can't use add_global_code yet because it relies on code from EE_ENV.
@@ -206,6 +273,21 @@ impl ExecutionEngine {
     /// Executes the specified code clip in the global scope such that it will affect the
     /// execution of all Julia code executed after this call.
     pub fn add_global_code(&mut self, code: GeneratedCode) -> Result<(), String> {
+        self.add_code(None, code)
+    }
+
+    /// Executes the specified code clip inside a new module `Main.module_name`, replacing any
+    /// module that previously had that name. Functions defined by the code can then be called
+    /// with paths starting with `["Main", module_name]`.
+    pub fn add_code_in_module(
+        &mut self,
+        module_name: &str,
+        code: GeneratedCode,
+    ) -> Result<(), String> {
+        self.add_code(Some(module_name), code)
+    }
+
+    fn add_code(&mut self, module_name: Option<&str>, code: GeneratedCode) -> Result<(), String> {
         let tracker_filename = format!("__global_code_{}__.jl", self.global_code_segments.len());
         let res = self
             .julia
@@ -214,9 +296,16 @@ impl ExecutionEngine {
                 let tracker_filename = Value::new(frame, tracker_filename).unwrap();
                 let main_module = Module::main(global);
                 let include_helper = main_module.function("__load_code_helper__").unwrap();
-                Ok(include_helper
-                    .call2(frame, code_str, tracker_filename)
-                    .unwrap())
+                Ok(if let Some(module_name) = module_name {
+                    let module_name = Value::new(frame, module_name).unwrap();
+                    include_helper
+                        .call3(frame, code_str, tracker_filename, module_name)
+                        .unwrap()
+                } else {
+                    include_helper
+                        .call2(frame, code_str, tracker_filename)
+                        .unwrap()
+                })
             })
             .unwrap();
         self.global_code_segments.push(code);
@@ -227,13 +316,13 @@ impl ExecutionEngine {
     }
 
     fn format_error(error: Value, segments: &[GeneratedCode]) -> String {
-        let raw_error = error
-            .cast::<JuliaString>()
-            .unwrap()
-            .as_str()
-            .unwrap()
-            .to_owned();
-        let mut error = &raw_error[..];
+        let raw_error = error.cast::<JuliaString>().unwrap().as_str().unwrap();
+        Self::format_raw_error(raw_error, segments)
+    }
+
+    /// Replaces references to tracker filenames with the original source of the code.
+    fn format_raw_error(raw_error: &str, segments: &[GeneratedCode]) -> String {
+        let mut error = raw_error;
         let mut result = String::new();
         while let Some(index) = error.find("__global_code_") {
             let before = &error[..index];
@@ -276,6 +365,124 @@ impl ExecutionEngine {
         make_inputs: IF,
         convert_result: OF,
     ) -> Result<O, String>
+    where
+        IF: for<'f> FnOnce(
+            &mut StaticFrame<'f, jlrs::mode::Sync>,
+            &mut Vec<Value<'f, 'f>>,
+        ) -> JlrsResult<()>,
+        OF: for<'f> FnOnce(&mut StaticFrame<'f, jlrs::mode::Sync>, Value<'f, 'f>) -> JlrsResult<O>,
+    {
+        self.call_wrapped_fn("__error_format_helper__", path, make_inputs, convert_result)
+    }
+
+    /// Copies `data` into the start of the Julia array stored in the global variable at `path`.
+    /// Unlike passing a new array to a function, this does not allocate. The array must already
+    /// exist and be at least as long as `data`.
+    pub fn write_global_array<T: ValidLayout + Copy>(
+        &mut self,
+        path: &[&str],
+        data: &[T],
+    ) -> Result<(), String> {
+        let r = self.julia.frame(STACK_SIZE - 10, |global, frame| {
+            let module = match Self::find_module(global, &path[..path.len() - 1]) {
+                Ok(v) => v,
+                Err(err) => return Ok(Err(err)),
+            };
+            let name = path[path.len() - 1];
+            let array = match module.global(name) {
+                Ok(v) => v,
+                Err(..) => {
+                    return Ok(Err(format!(
+                        "ERROR: There is no global named {} in the module.",
+                        name
+                    )))
+                }
+            };
+            let mut array = match array.cast::<TypedArray<T>>() {
+                Ok(v) => v,
+                Err(..) => {
+                    return Ok(Err(format!(
+                        "ERROR: {} is not an array of the expected type.",
+                        name
+                    )))
+                }
+            };
+            let mut contents = array.inline_data_mut(frame)?;
+            let contents = contents.as_mut_slice();
+            if contents.len() < data.len() {
+                return Ok(Err(format!(
+                    "ERROR: {} has room for {} items but {} were written to it.",
+                    name,
+                    contents.len(),
+                    data.len()
+                )));
+            }
+            contents[..data.len()].copy_from_slice(data);
+            Ok(Ok(()))
+        });
+        r.unwrap()
+    }
+
+    fn find_module<'base>(global: Global<'base>, path: &[&str]) -> Result<Module<'base>, String> {
+        let mut module = Module::main(global);
+        for submodule_name in path {
+            module = match module.submodule(*submodule_name) {
+                Ok(v) => v,
+                Err(..) => {
+                    return Err(format!(
+                        "ERROR: There is no module named {}.",
+                        submodule_name
+                    ))
+                }
+            };
+        }
+        Ok(module)
+    }
+
+    /// Starts calling a Julia function on a different Julia thread and returns immediately. Use
+    /// `poll_job` to find out when it is done. If Julia was only started with one thread, the
+    /// function is run to completion before this returns.
+    pub fn spawn_fn<IF>(&mut self, path: &[&str], make_inputs: IF) -> Result<BackgroundJob, String>
+    where
+        IF: for<'f> FnOnce(
+            &mut StaticFrame<'f, jlrs::mode::Sync>,
+            &mut Vec<Value<'f, 'f>>,
+        ) -> JlrsResult<()>,
+    {
+        self.call_wrapped_fn("__spawn_helper__", path, make_inputs, |_, id| {
+            Ok(BackgroundJob(id.cast::<i64>()?))
+        })
+    }
+
+    /// Returns `Ok(true)` once the job has finished running, or the error it threw if it failed.
+    /// A job should not be polled again after it has finished.
+    pub fn poll_job(&mut self, job: &BackgroundJob) -> Result<bool, String> {
+        let id = job.0;
+        let res = self.call_fn(
+            &["Main", "__poll_job_helper__"],
+            |frame, inputs| {
+                inputs.push(Value::new(frame, id)?);
+                Ok(())
+            },
+            |_, status| {
+                if let Ok(done) = status.cast::<bool>() {
+                    Ok(Ok(done))
+                } else {
+                    let error = status.cast::<JuliaString>()?.as_str()?.to_owned();
+                    Ok(Err(error))
+                }
+            },
+        )?;
+        res.map_err(|error| Self::format_raw_error(&error, &self.global_code_segments[..]))
+    }
+
+    fn call_wrapped_fn<O, IF, OF>(
+        &mut self,
+        wrapper_name: &str,
+        path: &[&str],
+        make_inputs: IF,
+        convert_result: OF,
+    ) -> Result<O, String>
     where
         IF: for<'f> FnOnce(
             &mut StaticFrame<'f, jlrs::mode::Sync>,
@@ -286,24 +493,15 @@ impl ExecutionEngine {
         let Self {
             julia,
             global_code_segments,
-            ..
+            input_buffer,
         } = self;
         let r = julia.frame(STACK_SIZE - 10, |global, frame| {
-            let mut module = Module::main(global);
-            let wrapper = module.function("__error_format_helper__").unwrap();
+            let wrapper = Module::main(global).function(wrapper_name).unwrap();
             let path_len = path.len();
-            for submodule_name in &path[..path_len - 1] {
-                let m = module.submodule(*submodule_name);
-                module = match m {
-                    Ok(v) => v,
-                    Err(..) => {
-                        return Ok(Err(format!(
-                            "ERROR: There is no module named {}.",
-                            submodule_name
-                        )))
-                    }
-                };
-            }
+            let module = match Self::find_module(global, &path[..path_len - 1]) {
+                Ok(v) => v,
+                Err(err) => return Ok(Err(err)),
+            };
             let func = module.function(path[path_len - 1]);
             let func = match func {
                 Ok(v) => v,
@@ -314,7 +512,10 @@ impl ExecutionEngine {
                     )))
                 }
             };
-            let mut inputs = Vec::new();
+            // Safety: the buffer is empty, so changing the lifetime of its values does not
+            // change anything it refers to. It is emptied again before it is put back.
+            let mut inputs: Vec<Value<'_, '_>> =
+                unsafe { std::mem::transmute(std::mem::take(input_buffer)) };
             inputs.push(func);
             if let Err(err) = make_inputs(frame, &mut inputs) {
                 return Ok(Err(format!(
@@ -323,6 +524,8 @@ impl ExecutionEngine {
                 )));
             };
             let result = wrapper.call(frame, &mut inputs).unwrap();
+            inputs.clear();
+            *input_buffer = unsafe { std::mem::transmute(inputs) };
             Ok(match result {
                 Ok(value) => convert_result(frame, value).map_err(|_err| {
                     format!(
@@ -371,6 +574,8 @@ Scope End"#
             function throw_error(a)
                 @assert false
             end
+            const buffer = zeros(Float32, 4)
+            buffer_sum() = sum(buffer)
         end
     "#;
 
@@ -437,6 +642,20 @@ Scope End"#
         assert!(error.contains("__global_code_0__.jl:4"));
         assert!(error.contains("__global_code_1__.jl:14"));
 
+        ee.write_global_array(&["Main", "buffer"], &[1.0f32, 2.0])
+            .unwrap();
+        let sum = ee
+            .call_fn(
+                &["Main", "buffer_sum"],
+                |_, _| Ok(()),
+                |_, v| v.cast::<f32>(),
+            )
+            .unwrap();
+        assert_eq!(sum, 3.0);
+        assert!(ee
+            .write_global_array(&["Main", "buffer"], &[0.0f32; 5])
+            .is_err());
+
         let lib = include_packed_library!("StaticArrays");
         ee.add_global_code(lib).unwrap();
         let code = GeneratedCode::from_unique_source(
@@ -165,6 +165,24 @@ impl ConstructorItem {
         }
     }
 
+    pub fn create_remapped_fields(&self) -> TokenStream2 {
+        match &self.typ {
+            ConstructorItemType::ParentRef => quote! {},
+            ConstructorItemType::GridPos => {
+                let name = self.name.clone();
+                quote! { #name: self.#name + offset, }
+            }
+            ConstructorItemType::ControlRef(..) => {
+                let name = format_ident!("{}_index", self.name);
+                quote! { #name: remap_control(self.#name)?, }
+            }
+            _ => {
+                let name = self.name.clone();
+                quote! { #name: self.#name.clone(), }
+            }
+        }
+    }
+
     pub fn create_constructor_argument(&self) -> TokenStream2 {
         match &self.typ {
             ConstructorItemType::ParentRef => quote! { parent },
@@ -346,6 +364,15 @@ pub fn make_widget_outline(args: TokenStream) -> TokenStream {
         })
         .collect();
 
+    let mut remapped_fields: Vec<_> = constructor_description
+        .args
+        .iter()
+        .map(ConstructorItem::create_remapped_fields)
+        .collect();
+    if let Some(FeedbackMode::ManualValue) = &feedback_mode {
+        remapped_fields.push(quote! { feedback_name: self.feedback_name.clone(), });
+    }
+
     let constructor_name = constructor_description.name.clone();
     let constructor_arg_values: Vec<_> = constructor_description
         .args
@@ -391,6 +418,19 @@ pub fn make_widget_outline(args: TokenStream) -> TokenStream {
                 ::std::result::Result::Ok(result)
             }
 
+            /// Returns a copy of this outline moved by `offset` whose controls are found at the
+            /// indexes returned by `remap_control`, or None if any of them have no new index.
+            #[allow(unused_variables)]
+            pub fn remapped(
+                &self,
+                remap_control: &dyn Fn(usize) -> ::std::option::Option<usize>,
+                offset: ::scui::Vec2D,
+            ) -> ::std::option::Option<Self> {
+                ::std::option::Option::Some(Self {
+                    #(#remapped_fields)*
+                })
+            }
+
             pub fn instantiate(
                 &self,
                 parent: &impl #widget_parent_trait,
@@ -450,6 +490,14 @@ pub fn make_widget_outline_enum(args: TokenStream) -> TokenStream {
             }
         })
         .collect();
+    let remapped_body: Vec<_> = class_names
+        .iter()
+        .map(|name| {
+            quote! {
+                Self::#name(outline) => Self::#name(outline.remapped(remap_control, offset)?)
+            }
+        })
+        .collect();
     let instantiate_body: Vec<_> = class_names
         .iter()
         .map(|name| {
@@ -491,6 +539,18 @@ pub fn make_widget_outline_enum(args: TokenStream) -> TokenStream {
                 })
             }
 
+            /// Returns a copy of this outline moved by `offset` whose controls are found at the
+            /// indexes returned by `remap_control`, or None if any of them have no new index.
+            pub fn remapped(
+                &self,
+                remap_control: &dyn Fn(usize) -> ::std::option::Option<usize>,
+                offset: ::scui::Vec2D,
+            ) -> ::std::option::Option<Self> {
+                ::std::option::Option::Some(match self {
+                    #(#remapped_body),*
+                })
+            }
+
             pub fn instantiate<P>(
                 &self,
                 parent: &P,